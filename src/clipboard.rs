@@ -0,0 +1,185 @@
+// Clipboard.rs - For copying text to and pasting text from the system clipboard
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+use std::{env, fmt};
+
+// The tools that can reach the system clipboard, tried in order
+const TOOLS: &[Tool] = &[
+    Tool {
+        copy: &["pbcopy"],
+        paste: &["pbpaste"],
+        display: None,
+    },
+    Tool {
+        copy: &["wl-copy"],
+        paste: &["wl-paste", "--no-newline"],
+        display: Some("WAYLAND_DISPLAY"),
+    },
+    Tool {
+        copy: &["xclip", "-selection", "clipboard"],
+        paste: &["xclip", "-selection", "clipboard", "-o"],
+        display: Some("DISPLAY"),
+    },
+    Tool {
+        copy: &["xsel", "--clipboard", "--input"],
+        paste: &["xsel", "--clipboard", "--output"],
+        display: Some("DISPLAY"),
+    },
+    Tool {
+        copy: &["clip.exe"],
+        paste: &["powershell.exe", "-NoProfile", "-Command", "Get-Clipboard"],
+        display: None,
+    },
+];
+
+// A pair of programs for copying and pasting
+struct Tool {
+    copy: &'static [&'static str], // The program that reads text to copy from stdin
+    paste: &'static [&'static str], // The program that prints the clipboard
+    display: Option<&'static str>, // The variable that must be set for it to work
+}
+
+// The reasons that the clipboard couldn't be used
+#[derive(Debug)]
+pub enum ClipboardError {
+    Empty,          // Nothing has been copied yet
+    Failed(String), // The clipboard program didn't succeed
+    Io(io::Error),  // The clipboard program couldn't be run
+}
+
+impl fmt::Display for ClipboardError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Describe the problem for the command line
+        match self {
+            Self::Empty => write!(f, "nothing has been copied"),
+            Self::Failed(program) => write!(f, "{program} failed"),
+            Self::Io(error) => write!(f, "{error}"),
+        }
+    }
+}
+
+impl From<io::Error> for ClipboardError {
+    fn from(error: io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+
+// A place that text can be copied to and pasted from
+pub trait Clipboard {
+    fn copy(&mut self, text: &str) -> Result<(), ClipboardError>;
+    fn paste(&mut self) -> Result<String, ClipboardError>;
+}
+
+// Copies by asking the terminal to set the clipboard, which works over SSH
+#[derive(Debug, Default)]
+pub struct Osc52Clipboard {
+    last: Option<String>, // Terminals rarely let programs read the clipboard, so keep a copy
+}
+
+// Copies and pastes by running the clipboard programs of the system
+#[derive(Debug)]
+pub struct SystemClipboard {
+    copy: &'static [&'static str],  // The program and arguments for copying
+    paste: &'static [&'static str], // The program and arguments for pasting
+}
+
+pub fn detect() -> Box<dyn Clipboard> {
+    // The system clipboard of a remote machine isn't the one the user sees
+    let remote = env::var_os("SSH_TTY").is_some() || env::var_os("SSH_CONNECTION").is_some();
+    match SystemClipboard::detect() {
+        Some(system) if !remote => Box::new(system),
+        _ => Box::new(Osc52Clipboard::default()),
+    }
+}
+
+impl Clipboard for Osc52Clipboard {
+    fn copy(&mut self, text: &str) -> Result<(), ClipboardError> {
+        // Send the text to the terminal, encoded in base64
+        let mut stdout = io::stdout();
+        write!(stdout, "\x1b]52;c;{}\x07", base64(text.as_bytes()))?;
+        stdout.flush()?;
+        self.last = Some(text.to_string());
+        Ok(())
+    }
+    fn paste(&mut self) -> Result<String, ClipboardError> {
+        // Give back the last text copied from this editor
+        self.last.clone().ok_or(ClipboardError::Empty)
+    }
+}
+
+impl SystemClipboard {
+    pub fn detect() -> Option<Self> {
+        // Find the first clipboard tool that is installed and can be used here
+        TOOLS
+            .iter()
+            .filter(|tool| {
+                tool.display.is_none_or(|var| env::var_os(var).is_some())
+                    && on_path(tool.copy[0])
+                    && on_path(tool.paste[0])
+            })
+            .map(|tool| Self {
+                copy: tool.copy,
+                paste: tool.paste,
+            })
+            .next()
+    }
+}
+
+impl Clipboard for SystemClipboard {
+    fn copy(&mut self, text: &str) -> Result<(), ClipboardError> {
+        // Feed the text to the copying program
+        let mut child = Command::new(self.copy[0])
+            .args(&self.copy[1..])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(text.as_bytes())?;
+        }
+        if child.wait()?.success() {
+            Ok(())
+        } else {
+            Err(ClipboardError::Failed(self.copy[0].to_string()))
+        }
+    }
+    fn paste(&mut self) -> Result<String, ClipboardError> {
+        // Read the clipboard from what the pasting program prints
+        let output = Command::new(self.paste[0])
+            .args(&self.paste[1..])
+            .stdin(Stdio::null())
+            .stderr(Stdio::null())
+            .output()?;
+        if output.status.success() {
+            Ok(String::from_utf8_lossy(&output.stdout).replace("\r\n", "\n"))
+        } else {
+            Err(ClipboardError::Failed(self.paste[0].to_string()))
+        }
+    }
+}
+
+fn on_path(program: &str) -> bool {
+    // Check if a program can be found in one of the folders in $PATH
+    env::var_os("PATH")
+        .is_some_and(|path| env::split_paths(&path).any(|folder| folder.join(program).is_file()))
+}
+
+fn base64(bytes: &[u8]) -> String {
+    // Encode bytes in standard base64 with padding
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut result = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let group = chunk
+            .iter()
+            .enumerate()
+            .fold(0_u32, |acc, (i, &b)| acc | u32::from(b) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                result.push(ALPHABET[(group >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                result.push('=');
+            }
+        }
+    }
+    result
+}
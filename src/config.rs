@@ -1,196 +1,1920 @@
 // Config.rs - In charge of storing configuration information
+use crate::editorconfig::EditorConfig;
+use crate::oxa::is_instruction;
+use directories::BaseDirs;
 use regex::Regex;
 use ron::de::from_str;
-use serde::Deserialize;
-use std::collections::HashMap;
-use std::fs;
-use termion::color;
+use ron::Value;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, SystemTime};
+use std::{cmp, env, fmt, fs, io, thread};
+use termion::{color, style};
+
+// Config files that are layered underneath and on top of the user config
+pub const SYSTEM_CONFIG: &str = "/etc/ox/ox.ron";
+pub const PROJECT_CONFIG: &str = ".ox.ron";
+
+// How often to check the config files for changes, and how long they must settle for
+const WATCH_INTERVAL: Duration = Duration::from_millis(250);
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(100);
+
+// The version of the config layout that this version of Ox understands
+pub const CONFIG_VERSION: u32 = 2;
+
+// Steps that upgrade the raw contents of a config to each version, before it's read
+type Migration = (u32, fn(&mut Value));
+const MIGRATIONS: &[Migration] = &[(2, Reader::rename_digits)];
+
+// The values that the tab width and line number padding are kept within
+const TAB_WIDTH: RangeInclusive<usize> = 1..=16;
+const PADDING: RangeInclusive<usize> = 0..=16;
+const UNDO_HISTORY: RangeInclusive<usize> = 1..=100_000;
 
 // Enum for determining what type of token it is
-#[derive(Clone)]
+#[derive(Debug, Clone)]
 pub enum TokenType {
     MultiLine(String, Vec<Regex>),
     SingleLine(String, Vec<Regex>),
 }
 
+impl TokenType {
+    pub fn capture_groups(&self) -> Vec<&str> {
+        // List the named groups in the expressions, which pick their own highlight group
+        let (TokenType::SingleLine(_, regex) | TokenType::MultiLine(_, regex)) = self;
+        let mut groups: Vec<&str> = regex
+            .iter()
+            .flat_map(|exp| exp.capture_names().flatten())
+            .collect();
+        groups.dedup();
+        groups
+    }
+}
+
+// Cache of the compiled syntax highlighting for each language
+#[derive(Debug, Default)]
+pub struct SyntaxCache {
+    compiled: Mutex<HashMap<String, Arc<Vec<TokenType>>>>,
+}
+
+impl Clone for SyntaxCache {
+    fn clone(&self) -> Self {
+        // Copies start out empty and fill up as languages are used
+        Self::default()
+    }
+}
+
+impl SyntaxCache {
+    pub fn get_or_compile(
+        &self,
+        config: &Reader,
+        path: &str,
+        first_line: &str,
+        mime: Option<&str>,
+    ) -> Arc<Vec<TokenType>> {
+        // Compile the highlighting for a language the first time it is needed
+        let Some(lang) = Reader::detect_language(config, path, first_line, mime) else {
+            return Arc::default();
+        };
+        match self.compiled.lock() {
+            Ok(mut compiled) => compiled
+                .entry(lang.name.clone())
+                .or_insert_with(|| Arc::new(Reader::compile_syntax(lang, &config.priority)))
+                .clone(),
+            // Don't trust a cache that was left half updated
+            Err(_) => Arc::new(Reader::compile_syntax(lang, &config.priority)),
+        }
+    }
+}
+
 // Error enum for config reading
 #[derive(Debug)]
 pub enum Status {
-    Parse(String),
+    Parse(ParseError),
     File,
-    Success,
+    Warning(Vec<String>),
+    Success(String),
+}
+
+impl Status {
+    pub fn summarise(statuses: Vec<Self>) -> Self {
+        // Find the most important status out of several config layers
+        let mut result = Self::File;
+        for status in statuses {
+            result = match (result, status) {
+                (Self::Parse(error), _) | (_, Self::Parse(error)) => Self::Parse(error),
+                (Self::Warning(mut warnings), Self::Warning(more)) => {
+                    warnings.extend(more);
+                    Self::Warning(warnings)
+                }
+                (Self::Warning(warnings), _) | (_, Self::Warning(warnings)) => {
+                    Self::Warning(warnings)
+                }
+                (_, Self::Success(path)) => Self::Success(path),
+                (result, Self::File) => result,
+            };
+        }
+        result
+    }
+}
+
+// Struct for holding the location and cause of a syntax issue in the config
+#[derive(Debug)]
+pub struct ParseError {
+    pub line: usize,
+    pub col: usize,
+    pub message: String,
+}
+
+impl From<ron::Error> for ParseError {
+    fn from(error: ron::Error) -> Self {
+        // Extract the position and message from the RON error
+        Self {
+            line: error.position.line,
+            col: error.position.col,
+            message: error.code.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Errors that aren't syntax related have no position
+        if self.line == 0 {
+            write!(f, "config error: {}", self.message)
+        } else {
+            write!(
+                f,
+                "config error at line {}, col {}: {}",
+                self.line, self.col, self.message
+            )
+        }
+    }
 }
 
 // Key binding type
-#[derive(Debug, Clone, Hash, Eq, PartialEq, Deserialize)]
+#[derive(Debug, Clone, Hash, Eq, PartialEq, Deserialize, Serialize)]
 pub enum KeyBinding {
     Ctrl(char),
     Alt(char),
 }
 
+// How a syntax highlighting group is drawn
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(from = "RawHighlightStyle")]
+pub struct HighlightStyle {
+    pub fg: (u8, u8, u8),
+    pub bg: Option<(u8, u8, u8)>,
+    pub bold: bool,
+    pub italic: bool,
+    pub underline: bool,
+}
+
+// Highlights can be a plain colour, as older configs have them, or a full style
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawHighlightStyle {
+    Colour(#[serde(deserialize_with = "colour")] (u8, u8, u8)),
+    Style {
+        #[serde(deserialize_with = "colour")]
+        fg: (u8, u8, u8),
+        #[serde(default, deserialize_with = "optional_colour")]
+        bg: Option<(u8, u8, u8)>,
+        #[serde(default)]
+        bold: bool,
+        #[serde(default)]
+        italic: bool,
+        #[serde(default)]
+        underline: bool,
+    },
+}
+
+impl From<RawHighlightStyle> for HighlightStyle {
+    fn from(raw: RawHighlightStyle) -> Self {
+        match raw {
+            RawHighlightStyle::Colour(fg) => Self {
+                fg,
+                bg: None,
+                bold: false,
+                italic: false,
+                underline: false,
+            },
+            RawHighlightStyle::Style {
+                fg,
+                bg,
+                bold,
+                italic,
+                underline,
+            } => Self {
+                fg,
+                bg,
+                bold,
+                italic,
+                underline,
+            },
+        }
+    }
+}
+
+// Colours can be written as (r, g, b) or as a hex string, e.g. "#1e90ff" or "#fff"
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawColour {
+    Rgb((u8, u8, u8)),
+    Hex(String),
+}
+
+fn colour<'de, D: Deserializer<'de>>(deserializer: D) -> Result<(u8, u8, u8), D::Error> {
+    // Hex colours that can't be read are swapped for defaults by Reader::invalid_colours
+    Ok(match RawColour::deserialize(deserializer)? {
+        RawColour::Rgb(rgb) => rgb,
+        RawColour::Hex(hex) => Reader::parse_hex(&hex).unwrap_or_default(),
+    })
+}
+
+fn optional_colour<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<(u8, u8, u8)>, D::Error> {
+    // Optional colours accept the same formats
+    Ok(
+        Option::<RawColour>::deserialize(deserializer)?.map(|colour| match colour {
+            RawColour::Rgb(rgb) => rgb,
+            RawColour::Hex(hex) => Reader::parse_hex(&hex).unwrap_or_default(),
+        }),
+    )
+}
+
+// Line number display modes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum LineNumberMode {
+    Absolute,
+    Relative,
+    Hybrid,
+}
+
+impl TryFrom<String> for LineNumberMode {
+    type Error = String;
+    fn try_from(mode: String) -> Result<Self, Self::Error> {
+        // Read the mode from its name in the config
+        match mode.to_lowercase().as_str() {
+            "absolute" => Ok(Self::Absolute),
+            "relative" => Ok(Self::Relative),
+            "hybrid" => Ok(Self::Hybrid),
+            _ => Err(format!(
+                "unknown line number mode `{mode}`, expected absolute, relative or hybrid"
+            )),
+        }
+    }
+}
+
+impl From<LineNumberMode> for String {
+    fn from(mode: LineNumberMode) -> Self {
+        match mode {
+            LineNumberMode::Absolute => "absolute",
+            LineNumberMode::Relative => "relative",
+            LineNumberMode::Hybrid => "hybrid",
+        }
+        .to_string()
+    }
+}
+
+// Colours the terminal is able to show
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorMode {
+    TrueColor,
+    Color256,
+    Color16,
+}
+
+// The colour mode of the terminal, detected the first time a colour is used
+static COLOR_MODE: OnceLock<ColorMode> = OnceLock::new();
+
+// The xterm palette that 16 colour terminals use
+const PALETTE_16: [(u8, u8, u8); 16] = [
+    (0, 0, 0),
+    (205, 0, 0),
+    (0, 205, 0),
+    (205, 205, 0),
+    (0, 0, 238),
+    (205, 0, 205),
+    (0, 205, 205),
+    (229, 229, 229),
+    (127, 127, 127),
+    (255, 0, 0),
+    (0, 255, 0),
+    (255, 255, 0),
+    (92, 92, 255),
+    (255, 0, 255),
+    (0, 255, 255),
+    (255, 255, 255),
+];
+
+// The key bindings of a config file, which are read from its text on their own
+#[derive(Deserialize)]
+struct Bindings {
+    #[serde(default = "Reader::default_keys")]
+    keys: HashMap<KeyBinding, Vec<String>>,
+}
+
 // Struct for storing and managing configuration
-#[derive(Debug, Deserialize, Clone)]
+// Anything left out of the config file is taken from the default config
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct Reader {
+    #[serde(default = "Reader::default_version")]
+    pub version: u32,
+    #[serde(default)]
+    pub include: Vec<String>,
+    #[serde(default)]
     pub general: General,
+    #[serde(default)]
+    pub theme_name: Option<String>,
+    #[serde(default)]
     pub theme: Theme,
+    #[serde(default)]
+    pub themes: HashMap<String, Theme>,
+    #[serde(default = "Reader::default_macros")]
     pub macros: HashMap<String, Vec<String>>,
-    pub highlights: HashMap<String, HashMap<String, (u8, u8, u8)>>,
+    #[serde(default = "Reader::default_highlights")]
+    pub highlights: HashMap<String, HashMap<String, HighlightStyle>>,
+    #[serde(default = "Reader::default_priority")]
+    pub priority: Vec<String>,
+    #[serde(default = "Reader::default_keys")]
     pub keys: HashMap<KeyBinding, Vec<String>>,
+    #[serde(default = "Reader::default_languages")]
     pub languages: Vec<Language>,
+    #[serde(skip)]
+    pub syntax: SyntaxCache,
+    #[serde(skip)]
+    pub set_general: HashSet<String>,
+    #[serde(skip)]
+    pub set_indent: HashSet<String>,
+}
+
+impl Default for Reader {
+    fn default() -> Self {
+        // Use the hard-coded configuration
+        from_str(DEFAULT).unwrap()
+    }
 }
 
 impl Reader {
+    fn default_version() -> u32 {
+        // Configs without a version were written for the first layout
+        1
+    }
+    fn default_macros() -> HashMap<String, Vec<String>> {
+        Self::default().macros
+    }
+    fn default_highlights() -> HashMap<String, HashMap<String, HighlightStyle>> {
+        Self::default().highlights
+    }
+    fn default_priority() -> Vec<String> {
+        Self::default().priority
+    }
+    fn default_keys() -> HashMap<KeyBinding, Vec<String>> {
+        Self::default().keys
+    }
+    fn default_languages() -> Vec<Language> {
+        Self::default().languages
+    }
     pub fn read(config: &str) -> (Self, Status) {
         // Read the config file, if it fails, use a hard-coded configuration
-        // Expand the path to get rid of any filepath issues
-        let config = if let Ok(config) = shellexpand::full(config) {
-            (*config).to_string()
-        } else {
-            config.to_string()
-        };
+        Self::read_included(config, &mut vec![])
+    }
+    fn read_included(config: &str, parents: &mut Vec<PathBuf>) -> (Self, Status) {
         // Attempt to read and parse the configuration file
-        if let Ok(file) = fs::read_to_string(config) {
-            let result: (Self, Status) = if let Ok(contents) = from_str(&file) {
-                (contents, Status::Success)
-            } else {
-                // There is a syntax issue with the config file
-                let result: Result<Self, ron::Error> = from_str(&file);
+        let path = Self::expand(config);
+        if let Ok(file) = fs::read_to_string(&path) {
+            match Self::parse(&file) {
+                Ok((mut contents, raw, outdated)) => {
+                    // Point out any fields that were ignored, they are probably typos
+                    let mut warnings = Self::unknown_fields(&raw);
+                    contents.set_general = Self::general_fields(&raw);
+                    contents.set_indent = Self::indented_languages(&raw);
+                    warnings.extend(contents.invalid_colours(&raw));
+                    warnings.extend(outdated);
+                    warnings.extend(contents.expand_variables());
+                    let named = contents.theme_name.is_some();
+                    // Build this config on top of the ones it includes
+                    if !contents.include.is_empty() {
+                        match Self::include(contents, config, parents, &mut warnings) {
+                            Ok(merged) => contents = merged,
+                            Err(error) => return (Self::default(), Status::Parse(error)),
+                        }
+                    }
+                    warnings.extend(contents.inherit_themes(&raw));
+                    // Named themes can come from the included configs too
+                    if named {
+                        warnings.extend(contents.use_theme_name());
+                    }
+                    warnings.extend(contents.clamp_values());
+                    if warnings.is_empty() {
+                        (contents, Status::Success(config.to_string()))
+                    } else {
+                        (contents, Status::Warning(warnings))
+                    }
+                }
                 // Provide the syntax issue with the config file for debugging
-                (
-                    from_str(DEFAULT).unwrap(),
-                    Status::Parse(format!("{:?}", result)),
-                )
-            };
-            result
+                Err(error) => (Self::default(), Status::Parse(error)),
+            }
         } else {
             // File wasn't able to be found
-            (from_str(DEFAULT).unwrap(), Status::File)
+            (Self::default(), Status::File)
         }
     }
-    pub fn get_syntax_regex(config: &Self, extension: &str) -> Vec<TokenType> {
-        // Compile the regular expressions from their string format
-        let mut result = vec![];
-        for lang in &config.languages {
-            // Locate the correct language for the extension
-            if lang.extensions.contains(&extension.to_string()) {
-                // Run through all the regex syntax definitions
-                for (name, reg) in &lang.definitions {
-                    let mut single = vec![];
-                    let mut multi = vec![];
-                    for expr in reg {
-                        if expr.starts_with("(?ms)") || expr.starts_with("(?sm)") {
-                            // Multiline regular expression
-                            if let Ok(regx) = Regex::new(&expr) {
-                                multi.push(regx);
+    pub fn read_layered(paths: &[&str]) -> (Self, Vec<Status>) {
+        // Read several config files, with later files overriding earlier ones
+        let mut result: Option<Self> = None;
+        let mut statuses = vec![];
+        for path in paths {
+            let (config, status) = Self::read(path);
+            if let Status::Success(_) | Status::Warning(_) = status {
+                Self::stack(&mut result, config, path);
+            }
+            statuses.push(status);
+        }
+        // Use the hard-coded configuration if no layers could be read
+        (result.unwrap_or_default(), statuses)
+    }
+    fn include(
+        config: Self,
+        path: &str,
+        parents: &mut Vec<PathBuf>,
+        warnings: &mut Vec<String>,
+    ) -> Result<Self, ParseError> {
+        // Merge the included configs in order, with the including config on top
+        let expanded = Self::expand(path);
+        let canonical = fs::canonicalize(&expanded).unwrap_or_else(|_| expanded.into());
+        if parents.contains(&canonical) {
+            return Err(ParseError {
+                line: 0,
+                col: 0,
+                message: format!("config `{path}` is included in a loop"),
+            });
+        }
+        let folder = canonical
+            .parent()
+            .unwrap_or_else(|| Path::new(""))
+            .to_path_buf();
+        parents.push(canonical);
+        let mut result = None;
+        for include in &config.include {
+            // Included paths are relative to the file that includes them
+            let include = folder.join(Self::expand(include));
+            let include = include.to_string_lossy();
+            match Self::read_included(&include, parents) {
+                (layer, Status::Success(_)) => Self::stack(&mut result, layer, &include),
+                (layer, Status::Warning(more)) => {
+                    warnings.extend(more);
+                    Self::stack(&mut result, layer, &include);
+                }
+                (_, Status::File) => {
+                    warnings.push(format!("included config `{include}` not found"));
+                }
+                (_, Status::Parse(error)) => return Err(error),
+            }
+        }
+        parents.pop();
+        Self::stack(&mut result, config, path);
+        Ok(result.unwrap_or_default())
+    }
+    fn stack(result: &mut Option<Self>, config: Self, path: &str) {
+        // Merge a config read from a file on top of the previous ones
+        if let Some(base) = result {
+            // Work out which settings this layer actually sets
+            let raw = fs::read_to_string(Self::expand(path))
+                .ok()
+                .and_then(|file| Self::parse(&file).ok())
+                .map(|(_, raw, _)| raw);
+            base.merge(config, raw.as_ref());
+        } else {
+            *result = Some(config);
+        }
+    }
+    fn parse(file: &str) -> Result<(Self, Value, Option<String>), ParseError> {
+        // Read a config file, upgrading it to the current layout first
+        let mut raw = from_str::<Value>(file)?;
+        let outdated = Self::migrate(&mut raw);
+        // Key bindings are enum variants, which a Value can't hold, so they come from the text
+        let bindings = from_str::<Bindings>(file)?;
+        let mut fields = match raw.clone() {
+            Value::Map(fields) => fields,
+            _ => ron::Map::new(),
+        };
+        fields.remove(&Value::String("keys".to_string()));
+        match Value::Map(fields).into_rust::<Self>() {
+            Ok(mut contents) => {
+                contents.keys = bindings.keys;
+                Ok((contents, raw, outdated))
+            }
+            // Reading the text again gives the error the line it happened on
+            Err(error) => Err(from_str::<Self>(file).err().unwrap_or(error).into()),
+        }
+    }
+    pub fn migrate(raw: &mut Value) -> Option<String> {
+        // Upgrade a config written for an older layout to the current one
+        let Value::Map(config) = raw else {
+            return None;
+        };
+        let version = match field(config, "version") {
+            Some(Value::Number(number)) => number.as_i64().unwrap_or(1),
+            _ => 1,
+        };
+        if version > i64::from(CONFIG_VERSION) {
+            return Some(format!(
+                "config version {version} is newer than this version of Ox supports, please update Ox"
+            ));
+        }
+        for (to, step) in MIGRATIONS {
+            if version < i64::from(*to) {
+                step(raw);
+            }
+        }
+        if let Value::Map(config) = raw {
+            config.insert(
+                Value::String("version".to_string()),
+                Value::Number(i64::from(CONFIG_VERSION).into()),
+            );
+        }
+        None
+    }
+    fn rename_digits(raw: &mut Value) {
+        // Version 2 calls the digits highlight group numbers
+        let rename = |group: &mut Value| {
+            if let Value::Map(groups) = group {
+                if let Some(value) = groups.remove(&Value::String("digits".to_string())) {
+                    groups.insert(Value::String("numbers".to_string()), value);
+                }
+            }
+        };
+        let Value::Map(config) = raw else {
+            return;
+        };
+        for (key, value) in config.iter_mut() {
+            match (key, value) {
+                (Value::String(name), Value::Map(themes)) if name == "highlights" => {
+                    themes.values_mut().for_each(rename);
+                }
+                (Value::String(name), Value::Seq(langs)) if name == "languages" => {
+                    for lang in langs {
+                        if let Value::Map(lang) = lang {
+                            for (key, value) in lang.iter_mut() {
+                                if *key == Value::String("definitions".to_string()) {
+                                    rename(value);
+                                }
                             }
-                        } else {
-                            // Single line regular expression
-                            if let Ok(regx) = Regex::new(&expr) {
-                                single.push(regx);
+                        }
+                    }
+                }
+                (Value::String(name), Value::Seq(groups)) if name == "priority" => {
+                    for group in groups {
+                        if *group == Value::String("digits".to_string()) {
+                            *group = Value::String("numbers".to_string());
+                        }
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+    fn expand_variables(&mut self) -> Vec<String> {
+        // Expand environment variables in the text that gets displayed
+        let mut fields = vec![
+            (
+                "general.status_left".to_string(),
+                &mut self.general.status_left,
+            ),
+            (
+                "general.status_right".to_string(),
+                &mut self.general.status_right,
+            ),
+            ("general.tab".to_string(), &mut self.general.tab),
+        ];
+        for lang in &mut self.languages {
+            fields.push((format!("languages[{}].icon", lang.name), &mut lang.icon));
+        }
+        let mut warnings = vec![];
+        for (path, value) in fields {
+            match shellexpand::env(value.as_str()).map(String::from) {
+                Ok(expanded) => *value = expanded,
+                // Leave the text as it is when the variable doesn't exist
+                Err(error) => warnings.push(format!(
+                    "environment variable `{}` in `{path}` isn't set",
+                    error.var_name
+                )),
+            }
+        }
+        warnings
+    }
+    fn clamp_values(&mut self) -> Vec<String> {
+        // Bring sizes that would break the layout back into a usable range
+        let general = &mut self.general;
+        let fields = [
+            ("tab_width", &mut general.tab_width, TAB_WIDTH),
+            (
+                "line_number_padding_left",
+                &mut general.line_number_padding_left,
+                PADDING,
+            ),
+            (
+                "line_number_padding_right",
+                &mut general.line_number_padding_right,
+                PADDING,
+            ),
+            (
+                "max_undo_history",
+                &mut general.max_undo_history,
+                UNDO_HISTORY,
+            ),
+        ];
+        let mut warnings = vec![];
+        for (name, value, range) in fields {
+            let clamped = (*value).clamp(*range.start(), *range.end());
+            if clamped != *value {
+                warnings.push(format!(
+                    "{name} must be between {} and {}, using {clamped} instead of {value}",
+                    range.start(),
+                    range.end()
+                ));
+                *value = clamped;
+            }
+        }
+        warnings
+    }
+    fn inherit_themes(&mut self, raw: &Value) -> Vec<String> {
+        // Fill in the colours that the themes in a file leave out from their base themes
+        let mut warnings = vec![];
+        let Value::Map(config) = raw else {
+            return warnings;
+        };
+        let section = |name: &str| {
+            config
+                .iter()
+                .find(|(key, _)| **key == Value::String(name.to_string()))
+                .map(|(_, value)| value.clone())
+        };
+        // The fields each theme in this file sets, the rest come from its base
+        let mut raw = HashMap::new();
+        if let Some(Value::Map(themes)) = section("themes") {
+            for (name, fields) in themes.iter() {
+                if let Value::String(name) = name {
+                    raw.insert(name.clone(), fields.clone());
+                }
+            }
+        }
+        let mut names: Vec<String> = raw.keys().cloned().collect();
+        names.sort();
+        let mut done = HashMap::new();
+        for name in names {
+            let theme = self.resolve_theme(&name, &raw, &mut done, &mut vec![], &mut warnings);
+            self.themes.insert(name, theme);
+        }
+        if let (Some(base), Some(fields)) = (self.theme.base.clone(), section("theme")) {
+            let parent = self.base_theme(&base, &raw, &mut done, &mut vec![], &mut warnings);
+            self.theme = overlay(&parent, self.theme.clone(), Some(&fields));
+        }
+        warnings
+    }
+    fn resolve_theme(
+        &self,
+        name: &str,
+        raw: &HashMap<String, Value>,
+        done: &mut HashMap<String, Theme>,
+        chain: &mut Vec<String>,
+        warnings: &mut Vec<String>,
+    ) -> Theme {
+        // Work out the colours of a theme in this file, following its chain of bases
+        if let Some(theme) = done.get(name) {
+            return theme.clone();
+        }
+        let theme = self.themes.get(name).cloned().unwrap_or_default();
+        let resolved = match theme.base.clone() {
+            Some(base) => {
+                chain.push(name.to_string());
+                let parent = self.base_theme(&base, raw, done, chain, warnings);
+                chain.pop();
+                overlay(&parent, theme, raw.get(name))
+            }
+            None => theme,
+        };
+        done.insert(name.to_string(), resolved.clone());
+        resolved
+    }
+    fn base_theme(
+        &self,
+        base: &str,
+        raw: &HashMap<String, Value>,
+        done: &mut HashMap<String, Theme>,
+        chain: &mut Vec<String>,
+        warnings: &mut Vec<String>,
+    ) -> Theme {
+        // Find the theme that another one inherits from, the default theme if there isn't one
+        if let Some(start) = chain.iter().position(|name| name == base) {
+            let mut cycle = chain[start..].to_vec();
+            cycle.push(base.to_string());
+            warnings.push(format!(
+                "themes inherit from each other in a loop `{}`, using the default theme as the base",
+                cycle.join(" -> ")
+            ));
+            Theme::default()
+        } else if raw.contains_key(base) {
+            self.resolve_theme(base, raw, done, chain, warnings)
+        } else if let Some(theme) = self
+            .themes
+            .get(base)
+            .cloned()
+            .or_else(|| Self::apply_theme(base))
+        {
+            theme
+        } else {
+            warnings.push(format!(
+                "unknown base theme `{base}`, expected one of {}",
+                self.theme_names().join(", ")
+            ));
+            Theme::default()
+        }
+    }
+    fn use_theme_name(&mut self) -> Option<String> {
+        // Swap the colours for a named theme when one is given
+        let name = self.theme_name.clone()?;
+        self.set_theme(&name).err()
+    }
+    pub fn set_theme(&mut self, name: &str) -> Result<(), String> {
+        // Switch to one of the themes in the config, or a bundled one
+        let mut theme = self
+            .themes
+            .get(name)
+            .cloned()
+            .or_else(|| Self::apply_theme(name))
+            .ok_or_else(|| {
+                format!(
+                    "unknown theme `{name}`, expected one of {}",
+                    self.theme_names().join(", ")
+                )
+            })?;
+        // The syntax highlights are chosen separately
+        theme.default_theme.clone_from(&self.theme.default_theme);
+        self.theme = theme;
+        self.theme_name = Some(name.to_string());
+        Ok(())
+    }
+    pub fn theme_names(&self) -> Vec<String> {
+        // Get the names of the themes in the config followed by the bundled ones
+        let mut names: Vec<String> = self.themes.keys().cloned().collect();
+        names.sort();
+        names.extend(Self::list_themes());
+        names
+    }
+    pub fn list_themes() -> Vec<String> {
+        // Get the names of the bundled themes
+        THEMES.iter().map(|(name, _)| name.to_string()).collect()
+    }
+    pub fn apply_theme(name: &str) -> Option<Theme> {
+        // Get the colours of a bundled theme
+        THEMES
+            .iter()
+            .find(|(theme, _)| *theme == name)
+            .and_then(|(_, theme)| from_str(theme).ok())
+    }
+    pub fn watch<F>(paths: Vec<String>, mut callback: F) -> thread::JoinHandle<()>
+    where
+        F: FnMut(Self, Status) + Send + 'static,
+    {
+        // Reload the config layers in the background whenever one of them changes
+        thread::spawn(move || {
+            let layers: Vec<&str> = paths.iter().map(String::as_str).collect();
+            let modified = || -> Vec<_> { layers.iter().map(|p| Self::modified(p)).collect() };
+            let mut mtimes = modified();
+            let mut last_good = Self::read_layered(&layers).0;
+            loop {
+                thread::sleep(WATCH_INTERVAL);
+                let mut current = modified();
+                if current == mtimes {
+                    continue;
+                }
+                // Wait for the files to settle so that one save doesn't cause several reloads
+                loop {
+                    thread::sleep(WATCH_DEBOUNCE);
+                    let settled = modified();
+                    if settled == current {
+                        break;
+                    }
+                    current = settled;
+                }
+                mtimes = current;
+                let (config, statuses) = Self::read_layered(&layers);
+                let status = Status::summarise(statuses);
+                if let Status::Parse(_) = status {
+                    // Hand back the previous working config instead of the defaults
+                    callback(last_good.clone(), status);
+                } else {
+                    last_good = config.clone();
+                    callback(config, status);
+                }
+            }
+        })
+    }
+    pub fn parse_hex(hex: &str) -> Option<(u8, u8, u8)> {
+        // Read a colour written as #RRGGBB or #RGB
+        let digits = hex.strip_prefix('#')?;
+        if !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        let channel = |part: &str| u8::from_str_radix(part, 16).ok();
+        match digits.len() {
+            6 => Some((
+                channel(&digits[0..2])?,
+                channel(&digits[2..4])?,
+                channel(&digits[4..6])?,
+            )),
+            // Each digit of the shorthand is doubled, so #fff is #ffffff
+            3 => Some((
+                channel(&digits[0..1])? * 17,
+                channel(&digits[1..2])? * 17,
+                channel(&digits[2..3])? * 17,
+            )),
+            _ => None,
+        }
+    }
+    fn invalid_colours(&mut self, raw: &Value) -> Vec<String> {
+        // Put back the defaults for hex colours that couldn't be read
+        let mut result = vec![];
+        let Value::Map(config) = raw else {
+            return result;
+        };
+        let invalid = |value: &Value| match value {
+            Value::String(hex) if Self::parse_hex(hex).is_none() => Some(hex.clone()),
+            Value::Option(Some(value)) => match &**value {
+                Value::String(hex) if Self::parse_hex(hex).is_none() => Some(hex.clone()),
+                _ => None,
+            },
+            _ => None,
+        };
+        let section = |name: &str| {
+            config
+                .iter()
+                .find(|(key, _)| **key == Value::String(name.to_string()))
+                .map(|(_, value)| value)
+        };
+        // Collect the fields of a theme that hold bad colours
+        let mut broken_fields = |theme: &Value, path: &str| {
+            let mut broken = vec![];
+            if let Value::Map(theme) = theme {
+                for (key, value) in theme.iter() {
+                    // These hold the names of themes rather than colours
+                    if matches!(key, Value::String(name) if name == "base" || name == "default_theme")
+                    {
+                        continue;
+                    }
+                    if let (Value::String(name), Some(hex)) = (key, invalid(value)) {
+                        result.push(format!(
+                            "invalid colour `{hex}` for `{path}.{name}`, using the default"
+                        ));
+                        broken.push((key.clone(), value.clone()));
+                    }
+                }
+            }
+            (!broken.is_empty()).then(|| Value::Map(broken.into_iter().collect()))
+        };
+        if let Some(broken) = section("theme").and_then(|theme| broken_fields(theme, "theme")) {
+            self.theme = overlay(&self.theme, Theme::default(), Some(&broken));
+        }
+        if let Some(Value::Map(themes)) = section("themes") {
+            for (name, theme) in themes.iter() {
+                let Value::String(name) = name else {
+                    continue;
+                };
+                if let Some(broken) = broken_fields(theme, &format!("themes.{name}")) {
+                    if let Some(theme) = self.themes.get_mut(name) {
+                        *theme = overlay(theme, Theme::default(), Some(&broken));
+                    }
+                }
+            }
+        }
+        if let Some(Value::Map(sets)) = section("highlights") {
+            let defaults = Self::default_highlights();
+            for (set, groups) in sets.iter() {
+                let (Value::String(set), Value::Map(groups)) = (set, groups) else {
+                    continue;
+                };
+                for (group, style) in groups.iter() {
+                    let Value::String(group) = group else {
+                        continue;
+                    };
+                    // Styles can hold bad colours in their fg or bg
+                    let hex = match style {
+                        Value::Map(fields) => fields.values().find_map(invalid),
+                        style => invalid(style),
+                    };
+                    if let Some(hex) = hex {
+                        result.push(format!(
+                            "invalid colour `{hex}` for `highlights.{set}.{group}`, using the default"
+                        ));
+                        let default = defaults.get(set).and_then(|colours| colours.get(group));
+                        if let Some(colours) = self.highlights.get_mut(set) {
+                            if let Some(default) = default {
+                                colours.insert(group.clone(), *default);
+                            } else {
+                                colours.remove(group);
                             }
                         }
                     }
-                    if !single.is_empty() {
-                        result.push(TokenType::SingleLine(name.clone(), single));
+                }
+            }
+        }
+        result
+    }
+    fn unknown_fields(raw: &Value) -> Vec<String> {
+        // Find the fields in a config file that don't exist in the config format
+        let mut result = vec![];
+        let (Value::Map(config), Some(Value::Map(known))) = (raw, to_value(&Self::default()))
+        else {
+            return result;
+        };
+        let language = Self::default().languages.first().and_then(to_value);
+        for (key, value) in config.iter() {
+            let Value::String(name) = key else {
+                continue;
+            };
+            if !known.keys().any(|k| k == key) {
+                result.push(format!("unknown field `{name}`"));
+                continue;
+            }
+            match name.as_str() {
+                "general" => unknown_keys(value, to_value(&General::default()), name, &mut result),
+                "theme" => unknown_keys(value, to_value(&Theme::default()), name, &mut result),
+                "themes" => {
+                    if let Value::Map(themes) = value {
+                        for (theme, fields) in themes.iter() {
+                            if let Value::String(theme) = theme {
+                                let path = format!("themes.{theme}");
+                                let known = to_value(&Theme::default());
+                                unknown_keys(fields, known, &path, &mut result);
+                            }
+                        }
                     }
-                    if !multi.is_empty() {
-                        result.push(TokenType::MultiLine(name.clone(), multi));
+                }
+                "languages" => {
+                    if let Value::Seq(langs) = value {
+                        for (i, lang) in langs.iter().enumerate() {
+                            let path = format!("languages[{i}]");
+                            unknown_keys(lang, language.clone(), &path, &mut result);
+                        }
                     }
                 }
-                // Process all the keywords
-                result.push(TokenType::SingleLine(
-                    "keywords".to_string(),
-                    lang.keywords
-                        .iter()
-                        .map(|x| Regex::new(&format!(r"\b({})\b", x)).unwrap())
-                        .collect(),
+                _ => (),
+            }
+        }
+        result
+    }
+    fn merge(&mut self, other: Self, raw: Option<&Value>) {
+        // Deep merge another config into this one, skipping sections it leaves out
+        let section = |name: &str| match raw {
+            Some(Value::Map(map)) => map
+                .iter()
+                .find(|(key, _)| **key == Value::String(name.to_string()))
+                .map(|(_, value)| value),
+            _ => None,
+        };
+        self.general = overlay(&self.general, other.general, section("general"));
+        self.set_general.extend(other.set_general);
+        self.set_indent.extend(other.set_indent);
+        if section("theme_name").is_some() {
+            // A named theme replaces every colour beneath it
+            self.theme_name = other.theme_name;
+            self.theme = other.theme;
+        } else {
+            self.theme = overlay(&self.theme, other.theme, section("theme"));
+        }
+        if section("themes").is_some() {
+            self.themes.extend(other.themes);
+        }
+        if section("macros").is_some() {
+            self.macros.extend(other.macros);
+        }
+        if section("priority").is_some() {
+            self.priority = other.priority;
+        }
+        if section("keys").is_some() {
+            self.keys.extend(other.keys);
+        }
+        if section("highlights").is_some() {
+            for (name, colours) in other.highlights {
+                self.highlights.entry(name).or_default().extend(colours);
+            }
+        }
+        // Merge languages by their name
+        if section("languages").is_some() {
+            for lang in other.languages {
+                if let Some(existing) = self.languages.iter_mut().find(|l| l.name == lang.name) {
+                    *existing = lang;
+                } else {
+                    self.languages.push(lang);
+                }
+            }
+        }
+    }
+    pub fn standard_locations() -> Vec<String> {
+        // List the places a user config can live, in order of priority
+        let mut result = vec![];
+        if let Ok(path) = env::var("OX_CONFIG") {
+            result.push(path);
+        }
+        if let Ok(path) = env::var("XDG_CONFIG_HOME") {
+            result.push(format!("{path}/ox/ox.ron"));
+        }
+        result.push("~/.config/ox/ox.ron".to_string());
+        // Platform specific directory (e.g. %APPDATA% on Windows)
+        if let Some(path) = BaseDirs::new().and_then(|dirs| {
+            dirs.config_dir()
+                .join("ox")
+                .join("ox.ron")
+                .to_str()
+                .map(String::from)
+        }) {
+            result.push(path);
+        }
+        result
+    }
+    pub fn locate() -> Option<String> {
+        // Find the first user config that exists
+        Self::standard_locations()
+            .into_iter()
+            .find(|path| Path::new(&Self::expand(path)).exists())
+    }
+    pub fn find_project(file: &str) -> Option<String> {
+        // Look for a project config from the file's folder up to the project root
+        let start = Path::new(file)
+            .parent()
+            .filter(|folder| !folder.as_os_str().is_empty())
+            .unwrap_or_else(|| Path::new("."));
+        let mut folder = fs::canonicalize(start).ok();
+        while let Some(current) = folder {
+            let config = current.join(PROJECT_CONFIG);
+            if config.exists() {
+                return config.to_str().map(String::from);
+            }
+            // Don't look outside of the repository the file is in
+            if current.join(".git").exists() {
+                return None;
+            }
+            folder = current.parent().map(Path::to_path_buf);
+        }
+        None
+    }
+    pub fn user_location() -> String {
+        // Find the user config, or where it should go if there isn't one yet
+        Self::locate().unwrap_or_else(|| Self::standard_locations().remove(0))
+    }
+    pub fn write_default(path: &str, force: bool) -> io::Result<()> {
+        // Write out the default config to give users a starting point
+        let path = PathBuf::from(Self::expand(path));
+        if path.exists() && !force {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} already exists", path.display()),
+            ));
+        }
+        if let Some(folder) = path.parent() {
+            fs::create_dir_all(folder)?;
+        }
+        // The built-in config is written out as is so that its comments are kept
+        fs::write(path, DEFAULT.trim_start())
+    }
+    pub fn expand(config: &str) -> String {
+        // Expand the path to get rid of any filepath issues
+        if let Ok(config) = shellexpand::full(config) {
+            (*config).to_string()
+        } else {
+            config.to_string()
+        }
+    }
+    pub fn modified(config: &str) -> Option<SystemTime> {
+        // Find out when the config file was last modified
+        fs::metadata(Self::expand(config)).ok()?.modified().ok()
+    }
+    pub fn validate(&self) -> Vec<String> {
+        // Check the config for values that parse but make no sense
+        let mut result = vec![];
+        for lang in &self.languages {
+            if lang.extensions.is_empty() {
+                result.push(format!("language `{}` has no extensions", lang.name));
+            }
+            if lang.indent.as_ref().and_then(|indent| indent.tab_width) == Some(0) {
+                result.push(format!(
+                    "language `{}`: tab_width must be at least 1",
+                    lang.name
                 ));
             }
+            for expr in &lang.shebang {
+                if Regex::new(expr).is_err() {
+                    result.push(format!(
+                        "language `{}`: invalid shebang regex `{expr}`",
+                        lang.name
+                    ));
+                }
+            }
+            for expr in &lang.indent_after {
+                if Regex::new(expr).is_err() {
+                    result.push(format!(
+                        "language `{}`: invalid indent_after regex `{expr}`",
+                        lang.name
+                    ));
+                }
+            }
+            if let Err(error) = Self::keywords_regex(&lang.keywords) {
+                result.push(format!(
+                    "language `{}`: keywords can't be highlighted: {}",
+                    lang.name,
+                    error.to_string().lines().last().unwrap_or_default()
+                ));
+            }
+            for (name, reg) in &lang.definitions {
+                for expr in reg {
+                    if let Err(error) = Regex::new(expr) {
+                        // Only keep the last line of the regex error to fit the status line
+                        let error = error.to_string();
+                        let error = error.lines().last().unwrap_or_default();
+                        result.push(format!(
+                            "language `{}`, group `{name}`: invalid regex `{expr}`: {}",
+                            lang.name,
+                            error.trim_start_matches("error: ")
+                        ));
+                    }
+                }
+            }
+        }
+        if self.general.autosave_interval == Some(0) {
+            result
+                .push("autosave_interval must be at least 1, use None for no backups".to_string());
+        }
+        // Check that key bindings run commands that exist
+        let mut unknown = vec![];
+        for (key, commands) in &self.keys {
+            for command in commands {
+                let name = command.trim();
+                let name = match name.split_once(' ') {
+                    Some((repeat, rest)) if repeat.parse::<usize>().is_ok() => rest.trim(),
+                    _ => name,
+                };
+                if !self.macros.contains_key(name) && !is_instruction(name) {
+                    unknown.push(format!("key `{key:?}`: unknown command `{command}`"));
+                }
+            }
+        }
+        unknown.sort();
+        result.extend(unknown);
+        // Check that every highlight group is used by a language
+        let mut unused: Vec<&String> = self
+            .highlights
+            .values()
+            .flat_map(HashMap::keys)
+            .filter(|name| {
+                !self.languages.iter().any(|lang| {
+                    lang.definitions.contains_key(*name)
+                        || (*name == "keywords" && !lang.keywords.is_empty())
+                })
+            })
+            .collect();
+        unused.sort();
+        unused.dedup();
+        for name in unused {
+            result.push(format!(
+                "highlight group `{name}` isn't used by any language"
+            ));
+        }
+        result
+    }
+    pub fn detect_language<'a>(
+        config: &'a Self,
+        path: &str,
+        first_line: &str,
+        mime: Option<&str>,
+    ) -> Option<&'a Language> {
+        // Find the language from the MIME type hint, the file name, the extension or the shebang
+        if let Some(lang) = mime.and_then(|mime| {
+            config
+                .languages
+                .iter()
+                .find(|lang| lang.mime_types.iter().any(|m| m == mime))
+        }) {
+            return Some(lang);
+        }
+        let name = Path::new(path)
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or(path);
+        if let Some(lang) = config
+            .languages
+            .iter()
+            .find(|lang| lang.filenames.iter().any(|file| file == name))
+        {
+            return Some(lang);
+        }
+        if let Some(lang) = Path::new(path)
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .and_then(|ext| Self::get_language_for_extension(config, ext))
+        {
+            return Some(lang);
+        }
+        Self::detect_language_from_content(config, first_line)
+    }
+    pub fn get_language_for_extension<'a>(config: &'a Self, ext: &str) -> Option<&'a Language> {
+        // Find the language that files with this extension are written in, ignoring case
+        config
+            .languages
+            .iter()
+            .find(|lang| lang.extensions.iter().any(|e| e.eq_ignore_ascii_case(ext)))
+    }
+    pub fn detect_language_from_content<'a>(
+        config: &'a Self,
+        first_line: &str,
+    ) -> Option<&'a Language> {
+        // Find the language from the interpreter in a shebang line
+        let shebang = first_line.strip_prefix("#!")?;
+        config.languages.iter().find(|lang| {
+            lang.shebang
+                .iter()
+                .any(|expr| Regex::new(expr).is_ok_and(|regx| regx.is_match(shebang)))
+        })
+    }
+    pub fn effective_tab_width(config: &Self, path: &str, found: &EditorConfig) -> usize {
+        // Find the tab width for a file from its extension and .editorconfig
+        config
+            .effective_indent(found, Self::detect_language(config, path, "", None))
+            .0
+    }
+    pub fn effective_indent(&self, found: &EditorConfig, lang: Option<&Language>) -> (usize, bool) {
+        // Use the indentation of the language, falling back to .editorconfig and then general
+        let general = self.apply_editorconfig(found);
+        let indent = lang.and_then(|lang| lang.indent.as_ref());
+        // The built-in languages give way to .editorconfig, ones set in a config file don't
+        let own = lang.is_some_and(|lang| self.set_indent.contains(&lang.name));
+        (
+            indent
+                .and_then(|indent| indent.tab_width)
+                .filter(|_| own || found.width().is_none())
+                .unwrap_or(general.tab_width),
+            indent
+                .and_then(|indent| indent.use_tabs)
+                .filter(|_| own || found.use_tabs().is_none())
+                .unwrap_or(general.use_tabs),
+        )
+    }
+    pub fn apply_editorconfig(&self, found: &EditorConfig) -> General {
+        // Take the general settings that .editorconfig files give a file, unless a config sets them
+        let mut general = self.general.clone();
+        let free = |name: &str| !self.set_general.contains(name);
+        if let (Some(use_tabs), true) = (found.use_tabs(), free("use_tabs")) {
+            general.use_tabs = use_tabs;
+        }
+        if let (Some(width), true) = (found.width(), free("tab_width")) {
+            general.tab_width = cmp::min(width, *TAB_WIDTH.end());
+        }
+        if let (Some(trim), true) = (
+            found.trim_trailing_whitespace,
+            free("trim_trailing_whitespace"),
+        ) {
+            general.trim_trailing_whitespace = trim;
+        }
+        if let (Some(newline), true) = (found.insert_final_newline, free("ensure_final_newline")) {
+            general.ensure_final_newline = newline;
+        }
+        general
+    }
+    fn general_fields(raw: &Value) -> HashSet<String> {
+        // Find the names of the general settings that a config file sets
+        match raw {
+            Value::Map(map) => field(map, "general")
+                .and_then(|general| match general {
+                    Value::Map(fields) => Some(
+                        fields
+                            .keys()
+                            .filter_map(|key| match key {
+                                Value::String(name) => Some(name.clone()),
+                                _ => None,
+                            })
+                            .collect(),
+                    ),
+                    _ => None,
+                })
+                .unwrap_or_default(),
+            _ => HashSet::new(),
+        }
+    }
+    fn indented_languages(raw: &Value) -> HashSet<String> {
+        // Find the names of the languages that a config file gives an indent
+        let mut result = HashSet::new();
+        if let Value::Map(map) = raw {
+            if let Some(Value::Seq(langs)) = field(map, "languages") {
+                for lang in langs {
+                    if let Value::Map(lang) = lang {
+                        if let (Some(Value::String(name)), Some(_)) =
+                            (field(lang, "name"), field(lang, "indent"))
+                        {
+                            result.insert(name.clone());
+                        }
+                    }
+                }
+            }
         }
         result
     }
-    pub fn rgb_fg(colour: (u8, u8, u8)) -> color::Fg<color::Rgb> {
+    pub fn get_syntax_regex(
+        config: &Self,
+        path: &str,
+        first_line: &str,
+        mime: Option<&str>,
+    ) -> Arc<Vec<TokenType>> {
+        // Get the compiled regular expressions for the language of a file
+        config.syntax.get_or_compile(config, path, first_line, mime)
+    }
+    fn compile_syntax(lang: &Language, priority: &[String]) -> Vec<TokenType> {
+        // Compile the regular expressions from their string format
+        let mut result = vec![];
+        // Run through all the regex syntax definitions
+        let mut definitions: Vec<_> = lang.definitions.iter().collect();
+        definitions.sort_by_key(|(name, _)| *name);
+        for (name, reg) in definitions {
+            let mut single = vec![];
+            let mut multi = vec![];
+            for expr in reg {
+                if Self::is_multiline(expr) {
+                    // Multiline regular expression
+                    if let Ok(regx) = Regex::new(expr) {
+                        multi.push(regx);
+                    }
+                } else {
+                    // Single line regular expression
+                    if let Ok(regx) = Regex::new(expr) {
+                        single.push(regx);
+                    }
+                }
+            }
+            if !single.is_empty() {
+                result.push(TokenType::SingleLine(name.clone(), single));
+            }
+            if !multi.is_empty() {
+                result.push(TokenType::MultiLine(name.clone(), multi));
+            }
+        }
+        // Process all the keywords in one go
+        let keywords = if lang.keywords.is_empty() {
+            vec![]
+        } else {
+            Self::keywords_regex(&lang.keywords)
+                .ok()
+                .into_iter()
+                .collect()
+        };
+        result.push(TokenType::SingleLine("keywords".to_string(), keywords));
+        // Put the groups in priority order, strongest first
+        result.sort_by_key(|token| {
+            let (TokenType::SingleLine(name, _) | TokenType::MultiLine(name, _)) = token;
+            priority
+                .iter()
+                .position(|group| group == name)
+                .unwrap_or(priority.len())
+        });
+        result
+    }
+    fn is_multiline(expr: &str) -> bool {
+        // Expressions that let `.` match newlines, e.g. (?s), (?ms) or (?si), can span lines
+        expr.strip_prefix("(?")
+            .and_then(|rest| rest.split([')', ':']).next())
+            // Named groups like (?P<name>...) start the same way but aren't flags
+            .filter(|flags| flags.chars().all(|c| "imsuxUR-".contains(c)))
+            .is_some_and(|flags| flags.split('-').next().unwrap_or_default().contains('s'))
+    }
+    fn keywords_regex(keywords: &[String]) -> Result<Regex, regex::Error> {
+        // Match any of the keywords literally, trying the longest ones first
+        let mut keywords: Vec<&String> = keywords.iter().collect();
+        keywords.sort_by_key(|keyword| cmp::Reverse(keyword.len()));
+        // Only use word boundaries next to word characters
+        let word = |c: Option<char>| c.is_some_and(|c| c.is_alphanumeric() || c == '_');
+        let patterns: Vec<String> = keywords
+            .iter()
+            .map(|keyword| {
+                let start = if word(keyword.chars().next()) {
+                    r"\b"
+                } else {
+                    ""
+                };
+                let end = if word(keyword.chars().last()) {
+                    r"\b"
+                } else {
+                    ""
+                };
+                format!("{start}{}{end}", regex::escape(keyword))
+            })
+            .collect();
+        Regex::new(&format!("({})", patterns.join("|")))
+    }
+    pub fn highlight_style(highlight: &HighlightStyle) -> String {
+        // Get the ANSI codes that start a syntax highlighting group
+        let mut result = Self::rgb_fg(highlight.fg);
+        if let Some(bg) = highlight.bg {
+            result.push_str(&Self::rgb_bg(bg));
+        }
+        if highlight.bold {
+            result.push_str(style::Bold.as_ref());
+        }
+        if highlight.italic {
+            result.push_str(style::Italic.as_ref());
+        }
+        if highlight.underline {
+            result.push_str(style::Underline.as_ref());
+        }
+        result
+    }
+    pub fn rgb_fg(colour: (u8, u8, u8)) -> String {
         // Get the text ANSI code from an RGB value
-        color::Fg(color::Rgb(colour.0, colour.1, colour.2))
+        Self::fg(colour, *COLOR_MODE.get_or_init(Self::detect_color_mode))
     }
-    pub fn rgb_bg(colour: (u8, u8, u8)) -> color::Bg<color::Rgb> {
+    pub fn rgb_bg(colour: (u8, u8, u8)) -> String {
         // Get the background ANSI code from an RGB value
-        color::Bg(color::Rgb(colour.0, colour.1, colour.2))
+        Self::bg(colour, *COLOR_MODE.get_or_init(Self::detect_color_mode))
+    }
+    pub fn rgb_blend(a: (u8, u8, u8), b: (u8, u8, u8), t: f32) -> (u8, u8, u8) {
+        // Linearly interpolate between two colours, t = 0 gives a and t = 1 gives b
+        let t = t.clamp(0.0, 1.0);
+        let mix = |x: u8, y: u8| (f32::from(x) + (f32::from(y) - f32::from(x)) * t).round() as u8;
+        (mix(a.0, b.0), mix(a.1, b.1), mix(a.2, b.2))
+    }
+    pub fn detect_color_mode() -> ColorMode {
+        // Work out how many colours the terminal supports from the environment
+        let colorterm = env::var("COLORTERM").unwrap_or_default();
+        let term = env::var("TERM").unwrap_or_default();
+        if colorterm == "truecolor"
+            || colorterm == "24bit"
+            || ["-direct", "kitty", "alacritty", "wezterm", "foot"]
+                .iter()
+                .any(|name| term.contains(name))
+        {
+            ColorMode::TrueColor
+        } else if term.contains("256color") {
+            ColorMode::Color256
+        } else {
+            ColorMode::Color16
+        }
+    }
+    pub fn fg(colour: (u8, u8, u8), mode: ColorMode) -> String {
+        // Get the text ANSI code for a colour in the given colour mode
+        match mode {
+            ColorMode::TrueColor => color::Fg(color::Rgb(colour.0, colour.1, colour.2)).to_string(),
+            ColorMode::Color256 => {
+                color::Fg(color::AnsiValue(Self::nearest_256(colour))).to_string()
+            }
+            ColorMode::Color16 => match Self::nearest_16(colour) {
+                index @ 0..=7 => format!("\x1b[{}m", 30 + index),
+                index => format!("\x1b[{}m", 82 + index),
+            },
+        }
+    }
+    pub fn bg(colour: (u8, u8, u8), mode: ColorMode) -> String {
+        // Get the background ANSI code for a colour in the given colour mode
+        match mode {
+            ColorMode::TrueColor => color::Bg(color::Rgb(colour.0, colour.1, colour.2)).to_string(),
+            ColorMode::Color256 => {
+                color::Bg(color::AnsiValue(Self::nearest_256(colour))).to_string()
+            }
+            ColorMode::Color16 => match Self::nearest_16(colour) {
+                index @ 0..=7 => format!("\x1b[{}m", 40 + index),
+                index => format!("\x1b[{}m", 92 + index),
+            },
+        }
+    }
+    fn colour_distance(a: (u8, u8, u8), b: (u8, u8, u8)) -> u32 {
+        // Squared distance between two colours
+        let channel = |x: u8, y: u8| u32::from(x.abs_diff(y)).pow(2);
+        channel(a.0, b.0) + channel(a.1, b.1) + channel(a.2, b.2)
+    }
+    fn nearest_256(colour: (u8, u8, u8)) -> u8 {
+        // Find the closest colour in the 6x6x6 cube or the grey ramp of the xterm palette
+        const LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+        let level = |c: u8| (0..6).min_by_key(|&i| LEVELS[i].abs_diff(c)).unwrap_or(0);
+        let (r, g, b) = (level(colour.0), level(colour.1), level(colour.2));
+        let cube = (LEVELS[r], LEVELS[g], LEVELS[b]);
+        let average = (u32::from(colour.0) + u32::from(colour.1) + u32::from(colour.2)) / 3;
+        let grey = (average.saturating_sub(3) / 10).min(23) as u8;
+        let shade = 8 + grey * 10;
+        if Self::colour_distance(colour, (shade, shade, shade))
+            < Self::colour_distance(colour, cube)
+        {
+            232 + grey
+        } else {
+            (16 + 36 * r + 6 * g + b) as u8
+        }
+    }
+    fn nearest_16(colour: (u8, u8, u8)) -> u8 {
+        // Find the closest colour in the basic 16 colour palette
+        (0..16)
+            .min_by_key(|&i| Self::colour_distance(colour, PALETTE_16[i as usize]))
+            .unwrap_or(0)
+    }
+}
+
+// Look up a field of a raw config section
+fn field<'a>(map: &'a ron::Map, name: &str) -> Option<&'a Value> {
+    // Find a field of a raw config section by its name
+    map.iter()
+        .find(|(key, _)| **key == Value::String(name.to_string()))
+        .map(|(_, value)| value)
+}
+
+// Round trip a config section through ron to get at its fields
+fn to_value<T: Serialize>(value: &T) -> Option<Value> {
+    // Convert a config section into a generic value
+    from_str(&ron::ser::to_string(value).ok()?).ok()
+}
+
+fn unknown_keys(section: &Value, known: Option<Value>, path: &str, result: &mut Vec<String>) {
+    // Collect the keys of a config section that aren't in the known section
+    if let (Value::Map(section), Some(Value::Map(known))) = (section, known) {
+        for key in section.keys() {
+            if let Value::String(name) = key {
+                if !known.keys().any(|k| k == key) {
+                    result.push(format!("unknown field `{path}.{name}`"));
+                }
+            }
+        }
+    }
+}
+
+fn overlay<T: Clone + Serialize + DeserializeOwned>(base: &T, layer: T, set: Option<&Value>) -> T {
+    // Copy only the fields that a layer sets on top of the base section
+    let Some(Value::Map(fields)) = set else {
+        return base.clone();
+    };
+    match (to_value(base), to_value(&layer)) {
+        (Some(Value::Map(mut base_map)), Some(Value::Map(layer_map))) => {
+            for (key, value) in layer_map.iter() {
+                if fields.keys().any(|k| k == key) {
+                    base_map.insert(key.clone(), value.clone());
+                }
+            }
+            Value::Map(base_map).into_rust().unwrap_or(layer)
+        }
+        _ => layer,
     }
 }
 
 // Struct for storing the general configuration
-#[derive(Debug, Deserialize, Clone)]
+// The settings stay flat like the general section, layers and .editorconfig set them one by one
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
 pub struct General {
     pub line_number_padding_right: usize,
     pub line_number_padding_left: usize,
+    pub line_number_mode: LineNumberMode,
+    pub wrap_lines: bool,
+    pub wrap_column: Option<usize>,
+    pub scroll_off: usize,
+    pub show_whitespace: bool,
+    pub whitespace_glyphs: WhitespaceGlyphs,
+    pub mouse_support: bool,
+    pub use_icons: bool,
+    pub restore_session: bool,
+    pub autosave_interval: Option<u64>,
     pub tab_width: usize,
+    pub use_tabs: bool,
+    pub auto_indent: bool,
+    pub trim_trailing_whitespace: bool,
+    pub trim_cursor_line: bool,
+    pub ensure_final_newline: bool,
+    pub trim_final_newlines: bool,
     pub undo_period: u64,
+    pub max_undo_history: usize,
+    pub finder_ignore: Vec<String>,
     pub status_left: String,
     pub status_right: String,
     pub tab: String,
 }
 
+// The characters that stand in for whitespace when it is shown
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct WhitespaceGlyphs {
+    pub tab: String,
+    pub space: String,
+    pub newline: String,
+}
+
+impl Default for WhitespaceGlyphs {
+    fn default() -> Self {
+        // Keep in sync with the general section of the default config
+        Self {
+            tab: "\u{2192}".to_string(),
+            space: "\u{b7}".to_string(),
+            newline: "\u{ac}".to_string(),
+        }
+    }
+}
+
 // Struct for storing theme information
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
 pub struct Theme {
+    pub base: Option<String>,
+    #[serde(deserialize_with = "colour")]
     pub editor_bg: (u8, u8, u8),
+    #[serde(deserialize_with = "colour")]
     pub editor_fg: (u8, u8, u8),
+    #[serde(deserialize_with = "colour")]
     pub status_bg: (u8, u8, u8),
+    #[serde(deserialize_with = "colour")]
     pub status_fg: (u8, u8, u8),
+    #[serde(deserialize_with = "colour")]
     pub line_number_fg: (u8, u8, u8),
+    #[serde(deserialize_with = "colour")]
     pub inactive_tab_fg: (u8, u8, u8),
+    #[serde(deserialize_with = "colour")]
     pub inactive_tab_bg: (u8, u8, u8),
+    #[serde(deserialize_with = "colour")]
     pub active_tab_fg: (u8, u8, u8),
+    #[serde(deserialize_with = "colour")]
     pub active_tab_bg: (u8, u8, u8),
+    #[serde(deserialize_with = "colour")]
+    pub selection_bg: (u8, u8, u8),
+    #[serde(deserialize_with = "colour")]
+    pub selection_fg: (u8, u8, u8),
+    #[serde(deserialize_with = "colour")]
+    pub search_highlight_bg: (u8, u8, u8),
+    #[serde(deserialize_with = "colour")]
+    pub search_highlight_fg: (u8, u8, u8),
+    #[serde(deserialize_with = "colour")]
+    pub search_current_bg: (u8, u8, u8),
+    #[serde(deserialize_with = "colour")]
+    pub cursor_color: (u8, u8, u8),
+    #[serde(deserialize_with = "optional_colour")]
+    pub cursor_insert_color: Option<(u8, u8, u8)>,
+    #[serde(deserialize_with = "optional_colour")]
+    pub current_line_bg: Option<(u8, u8, u8)>,
+    #[serde(deserialize_with = "colour")]
+    pub matching_bracket_bg: (u8, u8, u8),
+    #[serde(deserialize_with = "optional_colour")]
+    pub whitespace_fg: Option<(u8, u8, u8)>,
+    pub transparent_bg: bool,
     pub default_theme: String,
 }
 
+impl Default for General {
+    fn default() -> Self {
+        // Keep in sync with the general section of the default config
+        Self {
+            line_number_padding_right: 2,
+            line_number_padding_left: 1,
+            line_number_mode: LineNumberMode::Absolute,
+            wrap_lines: false,
+            wrap_column: None,
+            scroll_off: 3,
+            show_whitespace: false,
+            whitespace_glyphs: WhitespaceGlyphs::default(),
+            mouse_support: true,
+            use_icons: true,
+            restore_session: true,
+            autosave_interval: Some(60),
+            tab_width: 4,
+            use_tabs: true,
+            auto_indent: true,
+            trim_trailing_whitespace: false,
+            trim_cursor_line: true,
+            ensure_final_newline: true,
+            trim_final_newlines: false,
+            undo_period: 5,
+            max_undo_history: 1000,
+            finder_ignore: vec![".git".to_string(), "target".to_string()],
+            status_left: " %f%d %t %D \u{2502} %n %i".to_string(),
+            status_right: "\u{4e26} %l / %L \u{2502} \u{fae6}(%x, %y) ".to_string(),
+            tab: "%I%f%d".to_string(),
+        }
+    }
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        // Keep in sync with the theme section of the default config
+        Self {
+            base: None,
+            editor_bg: (41, 41, 61),
+            editor_fg: (255, 255, 255),
+            status_bg: (59, 59, 84),
+            status_fg: (35, 240, 144),
+            line_number_fg: (65, 65, 98),
+            inactive_tab_fg: (255, 255, 255),
+            inactive_tab_bg: (59, 59, 84),
+            active_tab_fg: (255, 255, 255),
+            active_tab_bg: (41, 41, 61),
+            selection_bg: (84, 84, 128),
+            selection_fg: (255, 255, 255),
+            search_highlight_bg: (255, 215, 0),
+            search_highlight_fg: (41, 41, 61),
+            search_current_bg: (255, 140, 0),
+            cursor_color: (255, 255, 255),
+            cursor_insert_color: None,
+            current_line_bg: None,
+            matching_bracket_bg: (95, 95, 140),
+            whitespace_fg: None,
+            transparent_bg: false,
+            default_theme: "default".to_string(),
+        }
+    }
+}
+
+impl Theme {
+    pub fn current_line_background(&self) -> (u8, u8, u8) {
+        // Nudge the background towards the text colour unless a colour was given
+        self.current_line_bg
+            .unwrap_or_else(|| Reader::rgb_blend(self.editor_bg, self.editor_fg, 0.06))
+    }
+    pub fn line_background(&self, current: bool) -> String {
+        // Get the background code for a line, the terminal's own when it is transparent
+        match self.current_line_bg {
+            Some(colour) if current => Reader::rgb_bg(colour),
+            _ if self.transparent_bg => color::Bg(color::Reset).to_string(),
+            _ if current => Reader::rgb_bg(self.current_line_background()),
+            _ => Reader::rgb_bg(self.editor_bg),
+        }
+    }
+}
+
 // Struct for storing language information
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct Language {
     pub name: String,
     pub icon: String,
     pub extensions: Vec<String>,
+    #[serde(default)]
+    pub filenames: Vec<String>,
+    #[serde(default)]
+    pub shebang: Vec<String>,
+    #[serde(default)]
+    pub mime_types: Vec<String>,
+    #[serde(default)]
+    pub indent: Option<Indent>,
+    #[serde(default)]
+    pub comment_line: Option<String>,
+    #[serde(default)]
+    pub comment_block: Option<(String, String)>,
+    #[serde(default = "Language::default_bracket_pairs")]
+    pub bracket_pairs: Vec<(char, char)>,
+    #[serde(default)]
+    pub indent_after: Vec<String>,
     pub keywords: Vec<String>,
     pub definitions: HashMap<String, Vec<String>>,
 }
 
+impl Language {
+    pub fn default_bracket_pairs() -> Vec<(char, char)> {
+        // Round, square and curly brackets match up in most languages
+        vec![('(', ')'), ('[', ']'), ('{', '}')]
+    }
+}
+
+// Struct for storing indentation settings that override the general ones
+#[derive(Debug, Default, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct Indent {
+    pub tab_width: Option<usize>,
+    pub use_tabs: Option<bool>,
+}
+
+// Themes that come with Ox, which can be picked with the theme_name field
+const THEMES: &[(&str, &str)] = &[
+    (
+        "default-dark",
+        r"(
+            editor_bg: (41, 41, 61),
+            editor_fg: (255, 255, 255),
+            status_bg: (59, 59, 84),
+            status_fg: (35, 240, 144),
+            line_number_fg: (65, 65, 98),
+            active_tab_fg: (255, 255, 255),
+            active_tab_bg: (41, 41, 61),
+            inactive_tab_fg: (255, 255, 255),
+            inactive_tab_bg: (59, 59, 84),
+            selection_bg: (84, 84, 128),
+            selection_fg: (255, 255, 255),
+            cursor_color: (255, 255, 255),
+        )",
+    ),
+    (
+        "solarized-dark",
+        r"(
+            editor_bg: (0, 43, 54),
+            editor_fg: (131, 148, 150),
+            status_bg: (7, 54, 66),
+            status_fg: (42, 161, 152),
+            line_number_fg: (88, 110, 117),
+            active_tab_fg: (147, 161, 161),
+            active_tab_bg: (0, 43, 54),
+            inactive_tab_fg: (101, 123, 131),
+            inactive_tab_bg: (7, 54, 66),
+            selection_bg: (88, 110, 117),
+            selection_fg: (253, 246, 227),
+            cursor_color: (147, 161, 161),
+        )",
+    ),
+    (
+        "solarized-light",
+        r"(
+            editor_bg: (253, 246, 227),
+            editor_fg: (101, 123, 131),
+            status_bg: (238, 232, 213),
+            status_fg: (38, 139, 210),
+            line_number_fg: (147, 161, 161),
+            active_tab_fg: (88, 110, 117),
+            active_tab_bg: (253, 246, 227),
+            inactive_tab_fg: (147, 161, 161),
+            inactive_tab_bg: (238, 232, 213),
+            selection_bg: (147, 161, 161),
+            selection_fg: (253, 246, 227),
+            cursor_color: (88, 110, 117),
+        )",
+    ),
+    (
+        "gruvbox",
+        r"(
+            editor_bg: (40, 40, 40),
+            editor_fg: (235, 219, 178),
+            status_bg: (60, 56, 54),
+            status_fg: (184, 187, 38),
+            line_number_fg: (124, 111, 100),
+            active_tab_fg: (235, 219, 178),
+            active_tab_bg: (40, 40, 40),
+            inactive_tab_fg: (168, 153, 132),
+            inactive_tab_bg: (60, 56, 54),
+            selection_bg: (80, 73, 69),
+            selection_fg: (235, 219, 178),
+            cursor_color: (235, 219, 178),
+        )",
+    ),
+    (
+        "one-dark",
+        r"(
+            editor_bg: (40, 44, 52),
+            editor_fg: (171, 178, 191),
+            status_bg: (33, 37, 43),
+            status_fg: (97, 175, 239),
+            line_number_fg: (76, 82, 99),
+            active_tab_fg: (171, 178, 191),
+            active_tab_bg: (40, 44, 52),
+            inactive_tab_fg: (92, 99, 112),
+            inactive_tab_bg: (33, 37, 43),
+            selection_bg: (62, 68, 81),
+            selection_fg: (171, 178, 191),
+            cursor_color: (82, 139, 255),
+        )",
+    ),
+];
+
 // Default configuration format
-const DEFAULT: &str = r#"
+const DEFAULT: &str = r##"
 // General settings for Ox
 (
+	version: 2, // The version of the config layout
+	include: [], // Other config files to build this one on top of
+	theme_name: None, // A theme from themes or a bundled one to use instead of the colours below, e.g. Some("gruvbox")
 	general: General(
 		line_number_padding_right: 2, // Line number padding on the right
 		line_number_padding_left:  1, // Line number padding on the left
+		line_number_mode: "absolute", // Line numbers: absolute, relative or hybrid
+		wrap_lines: false, // Wrap long lines onto the next row instead of scrolling
+		wrap_column: None, // Wrap before this column if it's narrower than the screen, e.g. Some(80)
+		scroll_off: 3, // Lines to keep between the cursor and the top or bottom
+		show_whitespace: false, // Draw tabs, spaces and line ends as the glyphs below
+		whitespace_glyphs: (tab: "\u{2192}", space: "\u{b7}", newline: "\u{ac}"), // The glyphs for shown whitespace
+		mouse_support: true, // Click to move the cursor, drag to select and scroll with the wheel
+		use_icons: true, // Show the Nerd Font icons for %i, %I and %D, turn off if they show as boxes
+		restore_session: true, // Reopen the files from last time when started without any
+		autosave_interval: Some(60), // Seconds between backups of unsaved changes, None for no backups
 		tab_width:                 4, // The amount of spaces for a tab
-		undo_period:               5, // Seconds of inactivity for undo
-		status_left:  " %f%d %D \u{2502} %n %i", // Left part of status line
+		use_tabs:                  true, // Save indentation as tabs rather than spaces
+		auto_indent:               true, // Indent new lines to match the line above
+		trim_trailing_whitespace:  false, // Remove whitespace from the ends of lines on save
+		trim_cursor_line:          true, // Trim the line the cursor is on as well
+		ensure_final_newline:      true, // Make sure files end with a newline on save
+		trim_final_newlines:       false, // Remove blank lines from the ends of files on save
+		undo_period:               5, // Seconds without typing before edits become a new undo step
+		max_undo_history:          1000, // The most undo steps to keep for each document
+		finder_ignore: [".git", "target"], // Globs to leave out of the file finder, on top of .gitignore
+		status_left:  " %f%d %t %D \u{2502} %n %i", // Left part of status line
 		status_right: "\u{4e26} %l / %L \u{2502} \u{fae6}(%x, %y) ", // Right part of status line
 		tab: "%I%f%d", // Tab formatting
 	),
 	// Custom defined macros
-	macros: {
-		// Macro to move a line up
-		"move line up": [
-			"store line 1", // Store current line in bank #1
-			"delete 0",     // Delete current line
-			"move 1 up",    // Move cursor up by 1
-			"line above",   // Insert an empty line above
-			"move 1 up",    // Move cursor up to the empty line
-			"load line 1",  // Load line in bank #1 over the empty line
-		],
-		// Macro to move a line down
-		"move line down": [
-			"store line 1", // Store the current line in bank #1
-			"delete 0",     // Delete the current line
-			"line below",   // Create an empty line below
-			"move 1 down",  // Move cursor down to empty line
-			"load line 1",  // Overwrite empty line with line in bank #1
-		],
-	},
+	macros: {},
 	// RGB values for the colours of Ox
 	theme: Theme(
+		base:             None, // A theme from themes or a bundled one to take the colours left out from
 		editor_bg:        (41, 41, 61), // The main background color
 		editor_fg:        (255, 255, 255), // The default text color
 		status_bg:        (59, 59, 84), // The background color of the status line
@@ -200,9 +1924,23 @@ const DEFAULT: &str = r#"
 		active_tab_bg:    (41, 41, 61), //  The background color of the active tab
 		inactive_tab_fg:  (255, 255, 255), // The text color of the inactive tab(s)
 		inactive_tab_bg:  (59, 59, 84), // The text color of the inactive tab(s)
+		selection_bg:     (84, 84, 128), // The background color of selected text
+		selection_fg:     (255, 255, 255), // The text color of selected text
+		search_highlight_bg: (255, 215, 0), // The background color of search matches
+		search_highlight_fg: (41, 41, 61), // The text color of search matches
+		search_current_bg: (255, 140, 0), // The background color of the search match at the cursor
+		cursor_color:     (255, 255, 255), // The color of the cursor
+		cursor_insert_color: None, // The color of the cursor while typing into the command line
+		current_line_bg:  None, // The background of the cursor's line, a shade of editor_bg when None
+		matching_bracket_bg: (95, 95, 140), // The background of the bracket at the cursor and its partner
+		whitespace_fg:    None, // The color of shown whitespace, line_number_fg when None
+		transparent_bg:   false, // Leave the editor background to the terminal, e.g. for a transparent one
 		default_theme:    "default", // The default syntax highlights to use
 	),
+	// Extra themes for theme_name and the theme command, e.g. "light": Theme(base: Some("solarized-light"), editor_bg: "#fdf6e3")
+	themes: {},
 	// Colours for the syntax highlighting
+	// A token can also be given a style, e.g. (fg: (134, 76, 232), bg: Some((41, 41, 61)), bold: true, italic: true, underline: true)
 	highlights: {
 		"default": {
 			"comments":   (113, 113, 169),
@@ -210,7 +1948,7 @@ const DEFAULT: &str = r#"
 			"references": (134, 76, 232),
 			"strings":    (39, 222, 145),
 			"characters": (40, 198, 232),
-			"digits":     (40, 198, 232),
+			"numbers":    (40, 198, 232),
 			"booleans":   (86, 217, 178),
 			"functions":  (47, 141, 252),
 			"structs":    (47, 141, 252),
@@ -219,6 +1957,7 @@ const DEFAULT: &str = r#"
 			"headers":    (47, 141, 252),
 			"symbols":    (47, 141, 252),
 			"global":     (86, 217, 178),
+			"todos":      (fg: (249, 233, 0), bold: true),
 		},
 		"alternative": {
 			"comments":   (113, 113, 169),
@@ -226,7 +1965,7 @@ const DEFAULT: &str = r#"
 			"references": (64, 86, 244),
 			"strings":    (76, 224, 179),
 			"characters": (110, 94, 206),
-			"digits":     (4, 95, 204),
+			"numbers":    (4, 95, 204),
 			"booleans":   (76, 224, 179),
 			"functions":  (4, 95, 204),
 			"structs":    (4, 95, 204),
@@ -235,27 +1974,51 @@ const DEFAULT: &str = r#"
 			"headers":    (141, 129, 217),
 			"symbols":    (249, 233, 0),
 			"global":     (76, 224, 179),
+			"todos":      (fg: (255, 140, 0), bold: true),
 		},
 	},
+	// Highlight groups that win when tokens start in the same place, strongest first
+	priority: ["comments", "strings"],
 	// Key bindings
 	keys: {
 		// Keybinding: [Oxa commands]
 		Ctrl('q'): ["quit"], // Quit current document
 		Ctrl('s'): ["save"], // Save current document
 		Ctrl('w'): ["save ?"], // Save current document as
-		Ctrl('p'): ["save *"], // Save all open documents
+		Ctrl('p'): ["find"], // Find a file to open
 		Ctrl('n'): ["new"], // Create new document
 		Ctrl('o'): ["open"], // Open document
 		Ctrl('d'): ["prev"], // Move to previous tab
 		Ctrl('h'): ["next"], // Move to next tab
+		Ctrl('g'): ["goto ?"], // Go to a line
 		Ctrl('z'): ["undo"], // Undo last edit
 		Ctrl('y'): ["redo"], // Redo last edit
 		Ctrl('f'): ["search"], // Trigger search command
 		Ctrl('r'): ["replace"], // Trigger replace command
 		Ctrl('a'): ["replace *"], // Trigger replace all command
-		Ctrl('v'): ["move line up"], // Move line up
+		Ctrl('b'): ["select"], // Start or clear a selection
+		Ctrl('7'): ["comment"], // Toggle comments (Ctrl + / on most terminals)
+		Ctrl('c'): ["copy"], // Copy the selection or line
+		Ctrl('x'): ["cut"], // Cut the selection or line
+		Ctrl('v'): ["paste"], // Paste from the clipboard
+		Ctrl('u'): ["move line up"], // Move line up
 		Ctrl('k'): ["move line down"], // Move line down
 		Alt('a'):  ["cmd"], // Open the command line
+		Alt('b'):  ["select column"], // Start or clear a column selection
+		Alt('d'):  ["duplicate"], // Duplicate the selection or line
+		Alt('U'):  ["case upper"], // Put the selection or word in upper case
+		Alt('L'):  ["case lower"], // Put the selection or word in lower case
+		Alt(','):  ["move word left"], // Move to the start of the word before the cursor
+		Alt('.'):  ["move word right"], // Move to the end of the word after the cursor
+		Alt('\u{7f}'): ["delete word left"], // Delete the word before the cursor (Alt + Backspace)
+		Alt('w'):  ["delete word right"], // Delete the word after the cursor
+		Alt('s'):  ["save *"], // Save all open documents
+		Alt('t'):  ["switch"], // Pick an open document from a list
+		Alt('v'):  ["pane vertical"], // Show the document in a new pane to the right
+		Alt('h'):  ["pane horizontal"], // Show the document in a new pane below
+		Alt('o'):  ["pane next"], // Move to the next pane
+		Alt('x'):  ["pane close"], // Close the current pane
+		Alt('y'):  ["redo ?"], // Pick a branch of the undo history to redo
 	},
 	// Language specific settings
 	languages: [
@@ -263,6 +2026,9 @@ const DEFAULT: &str = r#"
 			name: "Rust", // Name of the language
 			icon: "\u{e7a8} ", // Icon for the language
 			extensions: ["rs"], // Extensions of the language
+			mime_types: ["text/rust", "text/x-rust"], // MIME types of the language
+			comment_line: Some("//"), // Line comments of the language
+			comment_block: Some(("/*", "*/")), // Block comments of the language
 			// Keywords of the language
 			keywords: [
 				"as", "break", "const", "continue", "crate", "else", 
@@ -284,6 +2050,9 @@ const DEFAULT: &str = r#"
 					"(?m)(//.*)$", 
 					"(?ms)(/\\*.*?\\*/)",
 				],
+				"todos":      [
+					"\\b(TODO|FIXME|XXX|NOTE|HACK)\\b",
+				],
 				"strings":    [
 					"(\".*?\")",
 				],
@@ -291,7 +2060,7 @@ const DEFAULT: &str = r#"
 					"('.')", 
 					"('\\\\.')",
 				],
-				"digits":     [
+				"numbers":    [
 					"\\b(\\d+.\\d+|\\d+)",
 					"\\b(\\d+.\\d+(?:f32|f64))",
 				],
@@ -326,6 +2095,10 @@ const DEFAULT: &str = r#"
 			name: "Ruby", // Name of the language
 			icon: "\u{e739} ", // Icon for the language
 			extensions: ["rb"], // Extensions of the language
+			filenames: ["Rakefile", "Gemfile"], // File names of the language
+			shebang: ["ruby"], // Interpreters to look for in the shebang line
+			mime_types: ["text/x-ruby", "application/x-ruby"], // MIME types of the language
+			comment_line: Some("#"), // Line comments of the language
 			// Keywords of the language
 			keywords: [
 				"__ENCODING__", "__LINE__", "__FILE__", "BEGIN", "END", 
@@ -342,11 +2115,14 @@ const DEFAULT: &str = r#"
 					"(?m)(#.*)$", 
 					"(?ms)(=begin.*=end)", 
 				],
+				"todos":      [
+					"\\b(TODO|FIXME|XXX|NOTE|HACK)\\b",
+				],
 				"strings":    [
 					"((?:f|r|)\".*?\")",
 					"(\'.*?\')",
 				],
-				"digits":     [
+				"numbers":    [
 					r"\b(\d+.\d+|\d+)",
 				],
 				"booleans":   [
@@ -371,6 +2147,9 @@ const DEFAULT: &str = r#"
 			name: "Crystal", // Name of the language
 			icon: "\u{e7a3} ", // Icon for the language
 			extensions: ["cr"], // Extensions of the language
+			shebang: ["crystal"], // Interpreters to look for in the shebang line
+			mime_types: ["text/x-crystal"], // MIME types of the language
+			comment_line: Some("#"), // Line comments of the language
 			// Keywords of the language
 			keywords: [
 				"__ENCODING__", "__LINE__", "__FILE__", "BEGIN", "END", 
@@ -388,12 +2167,15 @@ const DEFAULT: &str = r#"
 					"(?m)(#.*)$", 
 					"(?ms)(=begin.*=end)", 
 				],
+				"todos":      [
+					"\\b(TODO|FIXME|XXX|NOTE|HACK)\\b",
+				],
 				"strings":    [
 					"(?ms)(\".*?\")",
 					"((?:f|r|)\".*?\")",
 					"(\'.*?\')",
 				],
-				"digits":     [
+				"numbers":    [
 					r"\b(\d+.\d+|\d+)",
 				],
 				"booleans":   [
@@ -418,6 +2200,10 @@ const DEFAULT: &str = r#"
 			name: "Python", // Name of the language
 			icon: "\u{e73c} ", // Icon for the language
 			extensions: ["py", "pyw"], // Extensions of the language
+			shebang: ["python"], // Interpreters to look for in the shebang line
+			mime_types: ["text/x-python", "text/x-script.python"], // MIME types of the language
+			indent: Some((tab_width: Some(4), use_tabs: Some(false))), // Indentation of the language
+			comment_line: Some("#"), // Line comments of the language
 			// Keywords of the language
 			keywords: [
 				"and", "as", "assert", "break", "class", "continue", 
@@ -428,20 +2214,23 @@ const DEFAULT: &str = r#"
 				"str", "bool", "int", "tuple", "list", "dict", "tuple",
 				"len", "None", "input", "type", "set", "range", "enumerate",
 				"open", "iter", "min", "max", "dir", "self", "isinstance", 
-				"help", "next", "super",
+				"help", "next", "super", "async", "await", "nonlocal",
 			],
 			// Syntax definitions
 			definitions: {
 				"comments":   [
 					"(?m)(#.*)$", 
 				],
+				"todos":      [
+					"\\b(TODO|FIXME|XXX|NOTE|HACK)\\b",
+				],
 				"strings":    [
 					"(?ms)(\"\"\".*?\"\"\")",
 					"(?ms)(\'\'\'.*?\'\'\')",
 					"((?:f|r|)\".*?\")",
 					"(\'.*?\')",
 				],
-				"digits":     [
+				"numbers":    [
 					"\\b(\\d+.\\d+|\\d+)",
 				],
 				"booleans":   [
@@ -455,21 +2244,25 @@ const DEFAULT: &str = r#"
 					"def\\s+([a-z_][A-Za-z0-9_]*)",
 				],
 				"attributes": [
-					"@.*$",
+					"(@[A-Za-z_][A-Za-z0-9_.]*)",
 				]
 			}
 		),
 		Language(
 			name: "Javascript", // Name of the language
 			icon: "\u{e74e} ", // Icon for the language
-			extensions: ["js"], // Extensions of the language
+			extensions: ["js", "mjs"], // Extensions of the language
+			shebang: ["node"], // Interpreters to look for in the shebang line
+			mime_types: ["text/javascript", "application/javascript"], // MIME types of the language
+			comment_line: Some("//"), // Line comments of the language
+			comment_block: Some(("/*", "*/")), // Block comments of the language
 			// Keywords of the language
 			keywords: [
-				"abstract", "arguments", "await", "boolean", "break", "byte", 
+				"abstract", "arguments", "async", "await", "boolean", "break", "byte", 
 				"case", "catch", "char", "class", "const", "continue", "debugger", 
 				"default", "delete", "do", "double", "else", "enum", "eval", 
 				"export", "extends", "final", "finally", "float", "for", "of",
-				"function", "goto", "if", "implements", "import", "in", "instanceof", 
+				"from", "function", "goto", "if", "implements", "import", "in", "instanceof", 
 				"int", "interface", "let", "long", "native", "new", "null", "package", 
 				"private", "protected", "public", "return", "short", "static", 
 				"super", "switch", "synchronized", "this", "throw", "throws", 
@@ -480,15 +2273,18 @@ const DEFAULT: &str = r#"
 			definitions: {
 				"comments":   [
 					"(?m)(//.*)$", 
-					"(?ms)(/\\*.*\\*/)$", 
+					"(?ms)(/\\*.*?\\*/)",
+				],
+				"todos":      [
+					"\\b(TODO|FIXME|XXX|NOTE|HACK)\\b",
 				],
 				"strings":    [
-					"(?ms)(\"\"\".*?\"\"\")",
-					"(?ms)(\'\'\'.*?\'\'\')",
-					"((?:f|r|)\".*?\")",
+					"(?ms)(`.*?`)",
+					"(\".*?\")",
 					"(\'.*?\')",
+					"(?:^|[=(,:;!&|?{}\\[])\\s*(/(?:\\\\.|[^/*\\\\\\n])(?:\\\\.|[^/\\\\\\n])*/[dgimsuy]*)",
 				],
-				"digits":     [
+				"numbers":    [
 					"\\b(\\d+.\\d+|\\d+)",
 				],
 				"booleans":   [
@@ -500,7 +2296,10 @@ const DEFAULT: &str = r#"
 				],
 				"functions":  [
 					"function\\s+([a-z_][A-Za-z0-9_]*)",
-					"\\b([a-z_][A-Za-z0-9_]*)\\s*\\("
+					"\\b([a-z_][A-Za-z0-9_]*)\\s*\\(",
+				],
+				"symbols":    [
+					"(=>)",
 				],
 			}
 		),
@@ -508,6 +2307,9 @@ const DEFAULT: &str = r#"
 			name: "C", // Name of the language
 			icon: "\u{e61e} ", // Icon for the language
 			extensions: ["c", "h"], // Extensions of the language
+			mime_types: ["text/x-c"], // MIME types of the language
+			comment_line: Some("//"), // Line comments of the language
+			comment_block: Some(("/*", "*/")), // Block comments of the language
 			// Keywords of the language
 			keywords: [
 				"auto", "break", "case", "char", "const", "continue", "default", 
@@ -524,6 +2326,9 @@ const DEFAULT: &str = r#"
 					"(?m)(//.*)$", 
 					"(?ms)(/\\*.*?\\*/)",
 				],
+				"todos":      [
+					"\\b(TODO|FIXME|XXX|NOTE|HACK)\\b",
+				],
 				"strings":    [
 					"(\".*?\")",
 				],
@@ -531,7 +2336,7 @@ const DEFAULT: &str = r#"
 					"('.')", 
 					"('\\\\.')",
 				],
-				"digits":     [
+				"numbers":    [
 					"\\b(\\d+.\\d+|\\d+)",
 					"\\b(\\d+.\\d+(?:f|))",
 				],
@@ -553,6 +2358,1068 @@ const DEFAULT: &str = r#"
 				],
 			}
 		),
+		Language(
+			name: "Typescript", // Name of the language
+			icon: "\u{e628} ", // Icon for the language
+			extensions: ["ts", "tsx"], // Extensions of the language
+			shebang: ["ts-node", "deno"], // Interpreters to look for in the shebang line
+			mime_types: ["text/typescript", "application/typescript"], // MIME types of the language
+			comment_line: Some("//"), // Line comments of the language
+			comment_block: Some(("/*", "*/")), // Block comments of the language
+			// Keywords of the language
+			keywords: [
+				"abstract", "any", "as", "async", "await", "boolean", "break", 
+				"case", "catch", "class", "const", "constructor", "continue", 
+				"debugger", "declare", "default", "delete", "do", "else", "enum", 
+				"export", "extends", "finally", "for", "from", "function", "get", 
+				"if", "implements", "import", "in", "instanceof", "interface", 
+				"is", "keyof", "let", "module", "namespace", "never", "new", 
+				"null", "number", "of", "private", "protected", "public", 
+				"readonly", "return", "set", "static", "string", "super", 
+				"switch", "symbol", "this", "throw", "try", "type", "typeof", 
+				"undefined", "unknown", "var", "void", "while", "yield", "console",
+			],
+			// Syntax definitions
+			definitions: {
+				"comments":   [
+					"(?m)(//.*)$", 
+					"(?ms)(/\\*.*?\\*/)",
+				],
+				"todos":      [
+					"\\b(TODO|FIXME|XXX|NOTE|HACK)\\b",
+				],
+				"strings":    [
+					"(?ms)(`.*?`)",
+					"(\".*?\")",
+					"(\'.*?\')",
+					"(?:^|[=(,:;!&|?{}\\[])\\s*(/(?:\\\\.|[^/*\\\\\\n])(?:\\\\.|[^/\\\\\\n])*/[dgimsuy]*)",
+				],
+				"numbers":    [
+					"\\b(\\d+.\\d+|\\d+)",
+				],
+				"booleans":   [
+					"\\b(true)\\b", 
+					"\\b(false)\\b",
+				],
+				"structs":    [
+					"(?:class|interface|enum|type)\\s+([A-Za-z0-9_]*)",
+				],
+				"functions":  [
+					"function\\s+([a-z_][A-Za-z0-9_]*)",
+					"\\b([a-z_][A-Za-z0-9_]*)\\s*\\(",
+				],
+				"attributes": [
+					"(@[A-Za-z0-9_]*)",
+				],
+				"symbols":    [
+					"(=>)",
+				],
+			}
+		),
+		Language(
+			name: "Go", // Name of the language
+			icon: "\u{e626} ", // Icon for the language
+			extensions: ["go"], // Extensions of the language
+			mime_types: ["text/x-go"], // MIME types of the language
+			indent: Some((use_tabs: Some(true))), // Indentation of the language
+			comment_line: Some("//"), // Line comments of the language
+			comment_block: Some(("/*", "*/")), // Block comments of the language
+			// Keywords of the language
+			keywords: [
+				"break", "case", "chan", "const", "continue", "default", "defer", 
+				"else", "fallthrough", "for", "func", "go", "goto", "if", 
+				"import", "interface", "map", "package", "range", "return", 
+				"select", "struct", "switch", "type", "var", "nil", "bool", 
+				"byte", "rune", "string", "error", "int", "int8", "int16", 
+				"int32", "int64", "uint", "uint8", "uint16", "uint32", "uint64", 
+				"uintptr", "float32", "float64", "complex64", "complex128", 
+				"append", "cap", "close", "copy", "delete", "len", "make", 
+				"new", "panic", "recover", "iota",
+			],
+			// Syntax definitions
+			definitions: {
+				"comments":   [
+					"(?m)(//.*)$", 
+					"(?ms)(/\\*.*?\\*/)",
+				],
+				"todos":      [
+					"\\b(TODO|FIXME|XXX|NOTE|HACK)\\b",
+				],
+				"strings":    [
+					"(?ms)(`.*?`)",
+					"(\".*?\")",
+				],
+				"characters": [
+					"('.')", 
+					"('\\\\.')",
+				],
+				"numbers":    [
+					"\\b(\\d+.\\d+|\\d+)",
+				],
+				"booleans":   [
+					"\\b(true)\\b", 
+					"\\b(false)\\b",
+				],
+				"functions":  [
+					"func\\s+(?:\\(.*?\\)\\s*|)([A-Za-z_][A-Za-z0-9_]*)\\s*\\(",
+				],
+				"structs":    [
+					"type\\s+([A-Za-z_][A-Za-z0-9_]*)\\s+(?:struct|interface)", 
+				],
+			}
+		),
+		Language(
+			name: "C++", // Name of the language
+			icon: "\u{e61d} ", // Icon for the language
+			extensions: ["cpp", "cc", "cxx", "hpp", "hh", "hxx"], // Extensions of the language
+			mime_types: ["text/x-c++"], // MIME types of the language
+			comment_line: Some("//"), // Line comments of the language
+			comment_block: Some(("/*", "*/")), // Block comments of the language
+			// Keywords of the language
+			keywords: [
+				"alignas", "alignof", "auto", "bool", "break", "case", "catch", 
+				"char", "class", "const", "constexpr", "const_cast", "continue", 
+				"decltype", "default", "delete", "do", "double", "dynamic_cast", 
+				"else", "enum", "explicit", "export", "extern", "float", "for", 
+				"friend", "goto", "if", "inline", "int", "long", "mutable", 
+				"namespace", "new", "noexcept", "nullptr", "operator", "private", 
+				"protected", "public", "register", "reinterpret_cast", "return", 
+				"short", "signed", "sizeof", "static", "static_assert", 
+				"static_cast", "struct", "switch", "template", "this", "throw", 
+				"try", "typedef", "typeid", "typename", "union", "unsigned", 
+				"using", "virtual", "void", "volatile", "while", "std", "string", 
+				"vector", "size_t", "cout", "cin", "cerr", "endl",
+			],
+			// Syntax definitions
+			definitions: {
+				"comments":   [
+					"(?m)(//.*)$", 
+					"(?ms)(/\\*.*?\\*/)",
+				],
+				"todos":      [
+					"\\b(TODO|FIXME|XXX|NOTE|HACK)\\b",
+				],
+				"strings":    [
+					"(\".*?\")",
+				],
+				"characters": [
+					"('.')", 
+					"('\\\\.')",
+				],
+				"numbers":    [
+					"\\b(\\d+.\\d+|\\d+)",
+					"\\b(\\d+.\\d+(?:f|))",
+				],
+				"booleans":   [
+					"\\b(true)\\b", 
+					"\\b(false)\\b",
+				],
+				"functions":  [
+					"\\b([a-z_][A-Za-z0-9_]*)\\s*\\(",
+				],
+				"structs":    [
+					"(?:class|struct|enum|namespace)\\s+([A-Za-z0-9_]*)", 
+					"([A-Za-z0-9_]+)::",
+				],
+				"attributes": [
+					"^\\s*(#.*?)\\s",
+				],
+				"headers":    [
+					"(<.*?>)",
+				],
+			}
+		),
 	],
 )
-"#;
+"##;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ron::ser::{to_string_pretty, PrettyConfig};
+
+    // A config written for the first layout, which called the numbers group digits
+    const VERSION_ONE: &str = r#"(
+        highlights: {"default": {"digits": (1, 2, 3), "strings": (4, 5, 6)}},
+        priority: ["digits", "strings"],
+        languages: [
+            (
+                name: "Test",
+                icon: "",
+                extensions: ["test"],
+                keywords: [],
+                definitions: {"digits": ["\\d+"]},
+            ),
+        ],
+    )"#;
+
+    #[test]
+    fn migrates_version_one_configs() {
+        let (config, _, outdated) = Reader::parse(VERSION_ONE).unwrap();
+        assert_eq!(outdated, None);
+        assert_eq!(config.version, CONFIG_VERSION);
+        let default = &config.highlights["default"];
+        assert_eq!(default["numbers"].fg, (1, 2, 3));
+        assert_eq!(default["strings"].fg, (4, 5, 6));
+        assert!(!default.contains_key("digits"));
+        assert_eq!(config.priority, vec!["numbers", "strings"]);
+        let lang = &config.languages[0];
+        assert_eq!(lang.definitions["numbers"], vec!["\\d+"]);
+        assert!(!lang.definitions.contains_key("digits"));
+    }
+
+    #[test]
+    fn migrated_configs_round_trip() {
+        let (config, _, _) = Reader::parse(VERSION_ONE).unwrap();
+        let written = to_string_pretty(&config, PrettyConfig::new()).unwrap();
+        let (again, _, outdated) = Reader::parse(&written).unwrap();
+        assert_eq!(outdated, None);
+        assert_eq!(again.version, CONFIG_VERSION);
+        assert_eq!(again.highlights, config.highlights);
+        assert_eq!(again.priority, config.priority);
+        assert_eq!(
+            again.languages[0].definitions,
+            config.languages[0].definitions
+        );
+        assert_eq!(again.keys, config.keys);
+    }
+
+    #[test]
+    fn current_configs_are_left_alone() {
+        let (_, raw, _) = Reader::parse(DEFAULT).unwrap();
+        let Value::Map(config) = raw else {
+            panic!("the default config isn't a map");
+        };
+        let Some(Value::Map(highlights)) = field(&config, "highlights") else {
+            panic!("the default config has no highlights");
+        };
+        let Some(Value::Map(default)) = field(highlights, "default") else {
+            panic!("the default config has no default highlights");
+        };
+        assert!(field(default, "numbers").is_some());
+    }
+
+    #[test]
+    fn newer_configs_ask_for_an_update() {
+        let (_, _, outdated) = Reader::parse("(version: 99)").unwrap();
+        assert!(outdated.unwrap().contains("please update Ox"));
+    }
+
+    #[test]
+    fn unknown_fields_are_read_after_migrating() {
+        let (_, raw, _) = Reader::parse(VERSION_ONE).unwrap();
+        assert!(Reader::unknown_fields(&raw).is_empty());
+    }
+
+    #[test]
+    fn keys_can_be_rebound() {
+        let layer = r#"(keys: {Ctrl('w'): ["save"], Alt('q'): ["sav"]})"#;
+        let (layer, raw, _) = Reader::parse(layer).unwrap();
+        let mut config = Reader::default();
+        config.merge(layer, Some(&raw));
+        assert_eq!(config.keys[&KeyBinding::Ctrl('w')], vec!["save"]);
+        // The bindings the layer leaves out keep their defaults
+        assert_eq!(config.keys[&KeyBinding::Ctrl('s')], vec!["save"]);
+        assert_eq!(
+            config.validate(),
+            vec!["key `Alt('q')`: unknown command `sav`"]
+        );
+    }
+
+    fn folder(name: &str) -> PathBuf {
+        // A fresh folder to write config files into
+        let folder = env::temp_dir().join(format!("ox-config-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&folder);
+        fs::create_dir_all(&folder).unwrap();
+        folder
+    }
+
+    fn write(folder: &Path, name: &str, contents: &str) -> String {
+        // Write a config file, giving its path
+        let path = folder.join(name);
+        fs::write(&path, contents).unwrap();
+        path.to_string_lossy().to_string()
+    }
+
+    #[test]
+    fn layers_only_override_what_they_set() {
+        let folder = folder("layers");
+        let base = write(
+            &folder,
+            "base.ron",
+            "(general: (tab_width: 2, use_tabs: false))",
+        );
+        let top = write(&folder, "top.ron", "(general: (use_tabs: true))");
+        let (config, statuses) = Reader::read_layered(&[&base, &top]);
+        fs::remove_dir_all(&folder).unwrap();
+        assert!(statuses.iter().all(|s| matches!(s, Status::Success(_))));
+        assert_eq!(config.general.tab_width, 2);
+        assert!(config.general.use_tabs);
+        assert_eq!(config.theme.editor_bg, Reader::default().theme.editor_bg);
+    }
+
+    #[test]
+    fn includes_are_built_on() {
+        let folder = folder("includes");
+        write(
+            &folder,
+            "base.ron",
+            "(general: (tab_width: 8, wrap_lines: true))",
+        );
+        let main = write(
+            &folder,
+            "main.ron",
+            r#"(include: ["base.ron", "missing.ron"], general: (tab_width: 3))"#,
+        );
+        let (config, status) = Reader::read(&main);
+        write(&folder, "a.ron", r#"(include: ["b.ron"])"#);
+        let looped = write(&folder, "b.ron", r#"(include: ["a.ron"])"#);
+        let (_, looped) = Reader::read(&looped);
+        fs::remove_dir_all(&folder).unwrap();
+        assert_eq!(config.general.tab_width, 3);
+        assert!(config.general.wrap_lines);
+        let Status::Warning(warnings) = status else {
+            panic!("the missing include wasn't pointed out");
+        };
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].ends_with("missing.ron` not found"));
+        let Status::Parse(error) = looped else {
+            panic!("the include loop wasn't caught");
+        };
+        assert!(error.message.contains("included in a loop"));
+    }
+
+    #[test]
+    fn hex_colours_are_read() {
+        assert_eq!(Reader::parse_hex("#1e90ff"), Some((30, 144, 255)));
+        assert_eq!(Reader::parse_hex("#FFF"), Some((255, 255, 255)));
+        assert_eq!(Reader::parse_hex("#0a1"), Some((0, 170, 17)));
+        assert_eq!(Reader::parse_hex("1e90ff"), None);
+        assert_eq!(Reader::parse_hex("#12345"), None);
+        assert_eq!(Reader::parse_hex("#ggg"), None);
+        let folder = folder("colours");
+        let path = write(
+            &folder,
+            "ox.ron",
+            r##"(theme: (editor_bg: "#102030", status_fg: "#nope"), highlights: {"default": {"strings": (fg: "#fff", bg: "#000")}})"##,
+        );
+        let (config, status) = Reader::read(&path);
+        fs::remove_dir_all(&folder).unwrap();
+        assert_eq!(config.theme.editor_bg, (16, 32, 48));
+        assert_eq!(config.theme.status_fg, Reader::default().theme.status_fg);
+        let strings = config.highlights["default"]["strings"];
+        assert_eq!((strings.fg, strings.bg), ((255, 255, 255), Some((0, 0, 0))));
+        let Status::Warning(warnings) = status else {
+            panic!("the bad colour wasn't pointed out");
+        };
+        assert_eq!(
+            warnings,
+            vec!["invalid colour `#nope` for `theme.status_fg`, using the default"]
+        );
+    }
+
+    fn language(path: &str, first_line: &str, mime: Option<&str>) -> Option<String> {
+        // The name of the language that a file is detected as
+        Reader::detect_language(&Reader::default(), path, first_line, mime)
+            .map(|lang| lang.name.clone())
+    }
+
+    #[test]
+    fn shebangs_are_read() {
+        assert_eq!(
+            language("build", "#!/usr/bin/env python3", None).as_deref(),
+            Some("Python")
+        );
+        assert_eq!(language("notes.txt", "# python", None), None);
+    }
+
+    #[test]
+    fn mime_types_are_read() {
+        assert_eq!(
+            language("main.rs", "", Some("text/x-go")).as_deref(),
+            Some("Go")
+        );
+    }
+
+    #[test]
+    fn files_are_detected_by_name() {
+        assert_eq!(language("app/Gemfile", "", None).as_deref(), Some("Ruby"));
+        assert_eq!(language("src/main.rs", "", None).as_deref(), Some("Rust"));
+    }
+
+    #[test]
+    fn extensions_ignore_case() {
+        assert_eq!(language("MAIN.RS", "", None).as_deref(), Some("Rust"));
+    }
+
+    #[test]
+    fn keywords_are_matched_whole() {
+        let keywords: Vec<String> = ["fn", "for", "format", "in", "int", "<="]
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        let regx = Reader::keywords_regex(&keywords).unwrap();
+        let found: Vec<&str> = regx
+            .find_iter("format for fnord int x<=in")
+            .map(|m| m.as_str())
+            .collect();
+        assert_eq!(found, vec!["format", "for", "int", "<=", "in"]);
+        let regx = Reader::keywords_regex(&["a.b".to_string()]).unwrap();
+        assert!(regx.is_match("a.b") && !regx.is_match("axb"));
+    }
+
+    #[test]
+    fn parse_errors_give_their_position() {
+        let folder = folder("broken");
+        let path = write(
+            &folder,
+            "ox.ron",
+            "(\n    general: (\n        tab_width: 4;\n    ),\n)",
+        );
+        let (config, status) = Reader::read(&path);
+        fs::remove_dir_all(&folder).unwrap();
+        let Status::Parse(error) = status else {
+            panic!("the syntax error wasn't reported");
+        };
+        assert_eq!((error.line, error.col), (3, 21));
+        assert!(error
+            .to_string()
+            .starts_with("config error at line 3, col 21: "));
+        // The hard-coded configuration is used in the meantime
+        assert_eq!(
+            config.general.tab_width,
+            Reader::default().general.tab_width
+        );
+    }
+
+    fn groups(syntax: &[TokenType]) -> Vec<&str> {
+        // The highlight groups of compiled syntax, strongest first
+        syntax
+            .iter()
+            .map(|token| {
+                let (TokenType::SingleLine(name, _) | TokenType::MultiLine(name, _)) = token;
+                name.as_str()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn syntax_comes_from_the_matched_language() {
+        let (config, _, _) = Reader::parse(
+            r##"(
+                priority: ["comments", "strings", "keywords"],
+                languages: [
+                    (
+                        name: "First",
+                        icon: "",
+                        extensions: ["one"],
+                        keywords: ["alpha"],
+                        definitions: {"strings": ["\"[^\"]*\""]},
+                    ),
+                    (
+                        name: "Second",
+                        icon: "",
+                        extensions: ["two"],
+                        keywords: ["beta"],
+                        definitions: {"comments": ["#.*"]},
+                    ),
+                ],
+            )"##,
+        )
+        .unwrap();
+        let syntax = Reader::get_syntax_regex(&config, "main.two", "", None);
+        assert_eq!(groups(&syntax), vec!["comments", "keywords"]);
+        let TokenType::SingleLine(_, keywords) = &syntax[1] else {
+            panic!("keywords aren't matched on one line");
+        };
+        assert!(keywords[0].is_match("beta") && !keywords[0].is_match("alpha"));
+        let syntax = Reader::get_syntax_regex(&config, "main.one", "", None);
+        assert_eq!(groups(&syntax), vec!["strings", "keywords"]);
+    }
+
+    #[test]
+    fn user_configs_are_found_in_order() {
+        let folder = folder("locations");
+        let xdg = folder.join("xdg");
+        fs::create_dir_all(xdg.join("ox")).unwrap();
+        let custom = folder.join("custom.ron");
+        let saved: Vec<_> = ["OX_CONFIG", "XDG_CONFIG_HOME"]
+            .iter()
+            .map(|name| (name, env::var(name)))
+            .collect();
+        env::set_var("OX_CONFIG", &custom);
+        env::set_var("XDG_CONFIG_HOME", &xdg);
+        let locations = Reader::standard_locations();
+        let xdg = xdg.join("ox").join("ox.ron").to_string_lossy().to_string();
+        let custom = custom.to_string_lossy().to_string();
+        assert_eq!(locations[..3], [&custom, &xdg, "~/.config/ox/ox.ron"]);
+        // The first one that exists is used
+        write(&folder, "xdg/ox/ox.ron", "()");
+        let xdg_found = Reader::locate();
+        write(&folder, "custom.ron", "()");
+        let custom_found = Reader::locate();
+        for (name, value) in saved {
+            match value {
+                Ok(value) => env::set_var(name, value),
+                Err(_) => env::remove_var(name),
+            }
+        }
+        fs::remove_dir_all(&folder).unwrap();
+        assert_eq!(xdg_found, Some(xdg));
+        assert_eq!(custom_found, Some(custom));
+    }
+
+    #[test]
+    fn partial_configs_keep_the_defaults() {
+        let folder = folder("partial");
+        let path = write(&folder, "ox.ron", "(general: (tab_width: 2))");
+        let (mut config, status) = Reader::read(&path);
+        fs::remove_dir_all(&folder).unwrap();
+        assert!(matches!(status, Status::Success(_)));
+        let mut expected = Reader::default();
+        expected.general.tab_width = 2;
+        // Key bindings lose their modifier as generic values, so they're checked on their own
+        assert_eq!(config.keys, expected.keys);
+        config.keys.clear();
+        expected.keys.clear();
+        assert_eq!(to_value(&config), to_value(&expected));
+    }
+
+    #[test]
+    fn unknown_fields_are_pointed_out() {
+        let folder = folder("unknown");
+        let path = write(&folder, "ox.ron", "(general: (tab_width: 2, tab_wdith: 8))");
+        let (config, status) = Reader::read(&path);
+        fs::remove_dir_all(&folder).unwrap();
+        let Status::Warning(warnings) = status else {
+            panic!("the unknown field wasn't pointed out");
+        };
+        assert_eq!(warnings, vec!["unknown field `general.tab_wdith`"]);
+        // The fields that are known still apply
+        assert_eq!(config.general.tab_width, 2);
+    }
+
+    #[test]
+    fn changed_configs_are_reloaded() {
+        let folder = folder("watch");
+        let path = write(&folder, "ox.ron", "(general: (tab_width: 2))");
+        let (sender, receiver) = std::sync::mpsc::channel();
+        Reader::watch(vec![path.clone()], move |config, status| {
+            let _ = sender.send((config.general.tab_width, status));
+        });
+        // Give the watcher time to note when the file was last changed
+        thread::sleep(WATCH_INTERVAL);
+        write(&folder, "ox.ron", "(general: (tab_width: 3))");
+        let wait = WATCH_INTERVAL * 4 + WATCH_DEBOUNCE;
+        let reloaded = receiver.recv_timeout(wait);
+        // A broken save hands back the last config that worked
+        write(&folder, "ox.ron", "(general: (tab_width: ))");
+        let broken = receiver.recv_timeout(wait);
+        fs::remove_dir_all(&folder).unwrap();
+        let (tab_width, status) = reloaded.expect("the change wasn't picked up");
+        assert_eq!(tab_width, 3);
+        assert!(matches!(status, Status::Success(_)));
+        let (tab_width, status) = broken.expect("the broken change wasn't picked up");
+        assert_eq!(tab_width, 3);
+        assert!(matches!(status, Status::Parse(_)));
+    }
+
+    #[test]
+    fn built_in_languages_are_highlighted() {
+        let config = Reader::default();
+        for (file, name) in [
+            ("main.py", "Python"),
+            ("main.js", "Javascript"),
+            ("main.ts", "Typescript"),
+            ("main.go", "Go"),
+            ("main.c", "C"),
+            ("main.cpp", "C++"),
+        ] {
+            assert_eq!(language(file, "", None).as_deref(), Some(name));
+            let syntax = Reader::get_syntax_regex(&config, file, "", None);
+            for token in syntax.iter() {
+                let (TokenType::SingleLine(group, regex) | TokenType::MultiLine(group, regex)) =
+                    token;
+                assert!(
+                    !regex.is_empty(),
+                    "{} has no expressions for {}",
+                    file,
+                    group
+                );
+            }
+            assert!(
+                groups(&syntax).contains(&"strings"),
+                "{} has no strings",
+                file
+            );
+        }
+    }
+
+    #[test]
+    fn files_without_an_extension_are_not_guessed() {
+        assert_eq!(language("rs", "", None), None);
+        assert_eq!(language("src/rs", "", None), None);
+        assert_eq!(
+            language("archive.tar.rs", "", None).as_deref(),
+            Some("Rust")
+        );
+    }
+
+    #[test]
+    fn default_configs_read_back_as_the_defaults() {
+        let folder = folder("default");
+        let path = folder
+            .join("ox")
+            .join("ox.ron")
+            .to_string_lossy()
+            .to_string();
+        Reader::write_default(&path, false).unwrap();
+        let again = Reader::write_default(&path, false).map_err(|error| error.kind());
+        let (mut config, status) = Reader::read(&path);
+        fs::remove_dir_all(&folder).unwrap();
+        // An existing config is only replaced when asked to
+        assert_eq!(again, Err(io::ErrorKind::AlreadyExists));
+        assert!(matches!(status, Status::Success(_)));
+        let mut expected = Reader::default();
+        assert_eq!(config.keys, expected.keys);
+        config.keys.clear();
+        expected.keys.clear();
+        assert_eq!(to_value(&config), to_value(&expected));
+    }
+
+    #[test]
+    fn the_nearest_project_config_wins() {
+        let folder = fs::canonicalize(folder("project")).unwrap();
+        fs::create_dir_all(folder.join("repo/.git")).unwrap();
+        fs::create_dir_all(folder.join("repo/a/b/c")).unwrap();
+        fs::create_dir_all(folder.join("repo/d")).unwrap();
+        fs::create_dir_all(folder.join("other/e")).unwrap();
+        let outer = write(&folder, PROJECT_CONFIG, "()");
+        let repo = write(&folder, &format!("repo/{PROJECT_CONFIG}"), "()");
+        let nested = write(&folder, &format!("repo/a/{PROJECT_CONFIG}"), "()");
+        let find = |file: &str| Reader::find_project(&folder.join(file).to_string_lossy());
+        let found = [
+            find("repo/a/b/c/main.rs"),
+            find("repo/d/main.rs"),
+            find("other/e/main.rs"),
+        ];
+        fs::remove_file(&repo).unwrap();
+        // The search stops at the root of the repository
+        let outside = find("repo/d/main.rs");
+        fs::remove_dir_all(&folder).unwrap();
+        assert_eq!(found, [Some(nested), Some(repo), Some(outer)]);
+        assert_eq!(outside, None);
+    }
+
+    #[test]
+    fn variables_are_expanded_in_icons() {
+        env::set_var("OX_TEST_ICON", "T");
+        env::remove_var("OX_TEST_MISSING");
+        let folder = folder("variables");
+        let path = write(
+            &folder,
+            "ox.ron",
+            r#"(
+                general: (status_left: "$OX_TEST_MISSING %f"),
+                languages: [(name: "Test", icon: "${OX_TEST_ICON} ", extensions: ["test"], keywords: [], definitions: {})],
+            )"#,
+        );
+        let (config, status) = Reader::read(&path);
+        fs::remove_dir_all(&folder).unwrap();
+        let lang = config.languages.iter().find(|lang| lang.name == "Test");
+        assert_eq!(lang.unwrap().icon, "T ");
+        // Variables that aren't set are left as they are
+        assert_eq!(config.general.status_left, "$OX_TEST_MISSING %f");
+        let Status::Warning(warnings) = status else {
+            panic!("the missing variable wasn't pointed out");
+        };
+        assert_eq!(
+            warnings,
+            vec!["environment variable `OX_TEST_MISSING` in `general.status_left` isn't set"]
+        );
+    }
+
+    #[test]
+    fn every_keyword_is_highlighted() {
+        for lang in Reader::default().languages {
+            let regx = Reader::keywords_regex(&lang.keywords).unwrap();
+            for keyword in &lang.keywords {
+                let line = format!("x {keyword} y");
+                let found = regx.find(&line).map(|m| m.as_str());
+                assert_eq!(found, Some(keyword.as_str()), "in {}", lang.name);
+            }
+        }
+    }
+
+    #[test]
+    fn dotall_expressions_span_lines() {
+        let lang: Language = from_str(
+            r#"(
+                name: "Test",
+                icon: "",
+                extensions: [],
+                keywords: [],
+                definitions: {"comments": ["(?s)(/\\*.*?\\*/)", "(//.*)"]},
+            )"#,
+        )
+        .unwrap();
+        let syntax = Reader::compile_syntax(&lang, &[]);
+        let multiline: Vec<_> = syntax
+            .iter()
+            .filter_map(|token| match token {
+                TokenType::MultiLine(name, regex) => Some((name.as_str(), regex.len())),
+                TokenType::SingleLine(..) => None,
+            })
+            .collect();
+        assert_eq!(multiline, vec![("comments", 1)]);
+        assert!(Reader::is_multiline("(?ms)x") && Reader::is_multiline("(?si:x)"));
+        assert!(!Reader::is_multiline("(?m)//.*$"));
+        assert!(!Reader::is_multiline("(?-s)x") && !Reader::is_multiline("(?P<s>x)"));
+    }
+
+    #[test]
+    fn compiled_syntax_is_shared() {
+        let config = Reader::default();
+        let first = Reader::get_syntax_regex(&config, "main.rs", "", None);
+        let again = Reader::get_syntax_regex(&config, "src/lib.rs", "", None);
+        let other = Reader::get_syntax_regex(&config, "main.py", "", None);
+        assert!(Arc::ptr_eq(&first, &again));
+        assert!(!Arc::ptr_eq(&first, &other));
+        // A reloaded config compiles its own
+        let reloaded = config.clone();
+        let fresh = Reader::get_syntax_regex(&reloaded, "main.rs", "", None);
+        assert!(!Arc::ptr_eq(&first, &fresh));
+    }
+
+    fn expressions<'a>(syntax: &'a [TokenType], group: &str) -> Vec<&'a Regex> {
+        // The expressions that compiled syntax has for a highlight group
+        syntax
+            .iter()
+            .filter_map(|token| {
+                let (TokenType::SingleLine(name, regex) | TokenType::MultiLine(name, regex)) =
+                    token;
+                (name == group).then_some(regex)
+            })
+            .flatten()
+            .collect()
+    }
+
+    #[test]
+    fn python_is_built_in() {
+        let config = Reader::default();
+        assert_eq!(language("tool.pyw", "", None).as_deref(), Some("Python"));
+        let syntax = Reader::get_syntax_regex(&config, "main.py", "", None);
+        let keywords = expressions(&syntax, "keywords");
+        for keyword in ["def", "class", "import", "lambda"] {
+            assert!(keywords.iter().any(|regx| regx.is_match(keyword)));
+        }
+        let matches = |group: &str, text: &str| {
+            expressions(&syntax, group)
+                .iter()
+                .any(|regx| regx.is_match(text))
+        };
+        assert!(matches("comments", "x = 1 # note"));
+        assert!(matches("strings", "\"\"\"one\ntwo\"\"\""));
+        assert!(matches("attributes", "@staticmethod"));
+    }
+
+    #[test]
+    fn javascript_and_typescript_are_built_in() {
+        let config = Reader::default();
+        for file in ["main.js", "main.mjs", "main.ts", "view.tsx"] {
+            let syntax = Reader::get_syntax_regex(&config, file, "", None);
+            let matches = |group: &str, text: &str| {
+                expressions(&syntax, group)
+                    .iter()
+                    .any(|regx| regx.is_match(text))
+            };
+            for keyword in ["function", "const", "let"] {
+                assert!(matches("keywords", keyword), "{} lacks {}", file, keyword);
+            }
+            assert!(matches("symbols", "(x) => x"), "{} lacks arrows", file);
+            assert!(
+                matches("strings", "`one\n${two}`"),
+                "{} lacks templates",
+                file
+            );
+            assert!(matches("strings", "x = /a+b/g"), "{} lacks regexes", file);
+            assert!(
+                matches("comments", "/* one\ntwo */"),
+                "{} lacks comments",
+                file
+            );
+        }
+    }
+
+    #[test]
+    fn todos_are_highlighted() {
+        let config = Reader::default();
+        let syntax = Reader::get_syntax_regex(&config, "main.rs", "", None);
+        let todos = expressions(&syntax, "todos");
+        assert!(todos.iter().any(|regx| regx.is_match("// TODO: tidy up")));
+        assert!(!todos.iter().any(|regx| regx.is_match("// TODOS")));
+        assert!(config.highlights["default"].contains_key("todos"));
+        assert!(config.highlights["alternative"].contains_key("todos"));
+    }
+
+    #[test]
+    fn syntax_is_in_priority_order() {
+        let lang: Language = from_str(
+            r#"(
+                name: "Test",
+                icon: "",
+                extensions: [],
+                keywords: ["let"],
+                definitions: {"comments": ["(#.*)"], "numbers": ["(\\d+)"], "strings": ["(\".*?\")"]},
+            )"#,
+        )
+        .unwrap();
+        let priority = ["strings".to_string(), "comments".to_string()];
+        let syntax = Reader::compile_syntax(&lang, &priority);
+        // The groups that aren't in the list come after, with keywords last
+        assert_eq!(
+            groups(&syntax),
+            vec!["strings", "comments", "numbers", "keywords"]
+        );
+        assert_eq!(Reader::default().priority, vec!["comments", "strings"]);
+    }
+
+    #[test]
+    fn red_is_red_in_16_colours() {
+        assert_eq!(Reader::fg((255, 0, 0), ColorMode::Color16), "\x1b[91m");
+        assert_eq!(Reader::bg((255, 0, 0), ColorMode::Color16), "\x1b[101m");
+        // Darker reds go to the normal red
+        assert_eq!(Reader::fg((190, 20, 10), ColorMode::Color16), "\x1b[31m");
+        assert_eq!(Reader::bg((190, 20, 10), ColorMode::Color16), "\x1b[41m");
+    }
+
+    #[test]
+    fn colours_are_blended() {
+        let (a, b) = ((0, 100, 255), (200, 50, 255));
+        assert_eq!(Reader::rgb_blend(a, b, 0.0), a);
+        assert_eq!(Reader::rgb_blend(a, b, 1.0), b);
+        assert_eq!(Reader::rgb_blend(a, b, 0.5), (100, 75, 255));
+        // Amounts outside 0 to 1 are clamped
+        assert_eq!(Reader::rgb_blend(a, b, -1.0), a);
+        assert_eq!(Reader::rgb_blend(a, b, 2.0), b);
+    }
+
+    #[test]
+    fn themes_can_be_switched() {
+        let folder = folder("themes");
+        let path = write(
+            &folder,
+            "ox.ron",
+            "(themes: {\"day\": (editor_bg: (250, 250, 250)), \"night\": (editor_bg: (10, 10, 10))})",
+        );
+        let (mut config, _) = Reader::read(&path);
+        fs::remove_dir_all(&folder).unwrap();
+        config.set_theme("day").unwrap();
+        assert_eq!(config.theme.editor_bg, (250, 250, 250));
+        config.set_theme("night").unwrap();
+        assert_eq!(config.theme.editor_bg, (10, 10, 10));
+        let error = config.set_theme("dusk").unwrap_err();
+        assert!(error.starts_with("unknown theme `dusk`, expected one of day, night"));
+        // A failed switch keeps the theme that was in use
+        assert_eq!(config.theme.editor_bg, (10, 10, 10));
+        assert_eq!(config.theme_name.as_deref(), Some("night"));
+    }
+
+    #[test]
+    fn the_current_line_stands_out() {
+        let mut themes = vec![Theme::default()];
+        themes.extend(
+            Reader::list_themes()
+                .iter()
+                .filter_map(|name| Reader::apply_theme(name)),
+        );
+        for theme in &themes {
+            assert_ne!(theme.current_line_background(), theme.editor_bg);
+        }
+        // Giving it the editor background turns it off
+        let mut theme = Theme::default();
+        theme.current_line_bg = Some(theme.editor_bg);
+        assert_eq!(theme.line_background(true), theme.line_background(false));
+    }
+
+    #[test]
+    fn highlights_can_be_colours_or_styles() {
+        let colour: HighlightStyle = from_str("(1, 2, 3)").unwrap();
+        assert_eq!(
+            colour,
+            HighlightStyle {
+                fg: (1, 2, 3),
+                bg: None,
+                bold: false,
+                italic: false,
+                underline: false,
+            }
+        );
+        let style: HighlightStyle =
+            from_str("(fg: (1, 2, 3), bg: (4, 5, 6), italic: true, underline: true)").unwrap();
+        assert_eq!(
+            style,
+            HighlightStyle {
+                fg: (1, 2, 3),
+                bg: Some((4, 5, 6)),
+                bold: false,
+                italic: true,
+                underline: true,
+            }
+        );
+        // The attributes are turned into the codes that start the group
+        let codes = Reader::highlight_style(&style);
+        assert!(codes.contains(&style::Italic.to_string()));
+        assert!(codes.contains(&style::Underline.to_string()));
+        assert!(!codes.contains(&style::Bold.to_string()));
+    }
+
+    #[test]
+    fn search_matches_have_their_own_colours() {
+        let (theme, _, _) = Reader::parse("(theme: (editor_bg: (0, 0, 0)))").unwrap();
+        let theme = theme.theme;
+        let default = Theme::default();
+        assert_eq!(theme.search_highlight_bg, default.search_highlight_bg);
+        assert_eq!(theme.search_current_bg, default.search_current_bg);
+        let mut themes = vec![Reader::default().theme];
+        themes.extend(
+            Reader::list_themes()
+                .iter()
+                .filter_map(|name| Reader::apply_theme(name)),
+        );
+        for theme in &themes {
+            assert_ne!(theme.search_highlight_bg, theme.search_current_bg);
+            assert_ne!(theme.search_highlight_bg, theme.editor_bg);
+            assert_ne!(theme.search_current_bg, theme.editor_bg);
+        }
+    }
+
+    #[test]
+    fn themes_inherit_from_their_base() {
+        let folder = folder("inherit");
+        let path = write(
+            &folder,
+            "ox.ron",
+            r#"(themes: {
+                "parent": (editor_bg: (1, 1, 1), editor_fg: (2, 2, 2)),
+                "child": (base: Some("parent"), editor_bg: (9, 9, 9)),
+                "a": (base: Some("b")),
+                "b": (base: Some("a")),
+            })"#,
+        );
+        let (config, status) = Reader::read(&path);
+        fs::remove_dir_all(&folder).unwrap();
+        let child = &config.themes["child"];
+        assert_eq!(child.editor_bg, (9, 9, 9));
+        assert_eq!(child.editor_fg, (2, 2, 2));
+        assert_eq!(child.status_bg, Theme::default().status_bg);
+        let Status::Warning(warnings) = status else {
+            panic!("the loop wasn't pointed out");
+        };
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].starts_with("themes inherit from each other in a loop `a -> b -> a`"));
+    }
+
+    #[test]
+    fn transparent_backgrounds_are_left_to_the_terminal() {
+        let mut theme = Theme {
+            transparent_bg: true,
+            ..Theme::default()
+        };
+        let reset = color::Bg(color::Reset).to_string();
+        for current in [false, true] {
+            let background = theme.line_background(current);
+            assert_eq!(background, reset);
+            assert!(!background.contains("48;"));
+        }
+        // A colour given for the cursor's line is still used
+        theme.current_line_bg = Some((1, 2, 3));
+        assert_ne!(theme.line_background(true), reset);
+        theme.transparent_bg = false;
+        assert_ne!(theme.line_background(false), reset);
+    }
+
+    #[test]
+    fn out_of_range_sizes_are_clamped() {
+        let folder = folder("clamp");
+        let path = write(
+            &folder,
+            "ox.ron",
+            "(general: (tab_width: 0, line_number_padding_right: 99, max_undo_history: 1000000000))",
+        );
+        let (config, status) = Reader::read(&path);
+        fs::remove_dir_all(&folder).unwrap();
+        let Status::Warning(warnings) = status else {
+            panic!("the sizes weren't pointed out");
+        };
+        assert_eq!(
+            warnings,
+            vec![
+                "tab_width must be between 1 and 16, using 1 instead of 0",
+                "line_number_padding_right must be between 0 and 16, using 16 instead of 99",
+                "max_undo_history must be between 1 and 100000, using 100000 instead of 1000000000",
+            ]
+        );
+        assert_eq!(config.general.tab_width, 1);
+        assert_eq!(config.general.line_number_padding_right, 16);
+        assert_eq!(config.general.max_undo_history, 100_000);
+        // Sizes in range are left alone
+        assert_eq!(config.general.line_number_padding_left, 1);
+    }
+
+    #[test]
+    fn wrapping_is_read() {
+        let folder = folder("wrapping");
+        let path = write(
+            &folder,
+            "ox.ron",
+            "(general: (wrap_lines: true, wrap_column: Some(80)))",
+        );
+        let (config, status) = Reader::read(&path);
+        fs::remove_dir_all(&folder).unwrap();
+        assert!(matches!(status, Status::Success(_)));
+        assert!(config.general.wrap_lines);
+        assert_eq!(config.general.wrap_column, Some(80));
+        // Long lines scroll unless wrapping is asked for
+        let general = General::default();
+        assert!(!general.wrap_lines);
+        assert_eq!(general.wrap_column, None);
+    }
+
+    #[test]
+    fn whitespace_glyphs_are_read() {
+        let folder = folder("whitespace");
+        let path = write(
+            &folder,
+            "ox.ron",
+            r#"(general: (show_whitespace: true, whitespace_glyphs: (tab: ">")))"#,
+        );
+        let (config, status) = Reader::read(&path);
+        fs::remove_dir_all(&folder).unwrap();
+        assert!(matches!(status, Status::Success(_)));
+        assert!(config.general.show_whitespace);
+        let glyphs = &config.general.whitespace_glyphs;
+        assert_eq!(glyphs.tab, ">");
+        // The glyphs left out keep their defaults
+        assert_eq!(glyphs.space, "\u{b7}");
+        assert_eq!(glyphs.newline, "\u{ac}");
+        assert!(!General::default().show_whitespace);
+    }
+
+    #[test]
+    fn trimming_is_read() {
+        let folder = folder("trimming");
+        let path = write(
+            &folder,
+            "ox.ron",
+            "(general: (trim_trailing_whitespace: true, trim_cursor_line: false))",
+        );
+        let (config, status) = Reader::read(&path);
+        fs::remove_dir_all(&folder).unwrap();
+        assert!(matches!(status, Status::Success(_)));
+        assert!(config.general.trim_trailing_whitespace);
+        assert!(!config.general.trim_cursor_line);
+        // The cursor's line is trimmed too unless it's asked to be kept
+        assert!(General::default().trim_cursor_line);
+    }
+}
@@ -1,15 +1,30 @@
 // Document.rs - For managing external files
-use crate::config::{Reader, Status, TokenType};
+use crate::config::{Language, Reader, Status, TokenType};
 use crate::editor::OFFSET;
-use crate::util::{line_offset, spaces_to_tabs, tabs_to_spaces};
+use crate::editorconfig::EditorConfig;
+use crate::highlight::{Highlighter, QUOTED};
+use crate::util::{
+    compute_indent, convert_case, line_offset, set_line_endings, spaces_to_tabs, tabs_to_spaces,
+    CaseMode,
+};
 use crate::{Event, EventStack, Position, Row, Size, VERSION};
 use regex::Regex;
+use std::cmp::Reverse;
 use std::ffi::OsStr;
+use std::ops::Range;
 use std::path::Path;
+use std::sync::Arc;
+use std::time::SystemTime;
 use std::{cmp, fs};
 use termion::event::Key;
 use unicode_width::UnicodeWidthStr;
 
+// Added to the path of a file to get where its unsaved changes are backed up
+const BACKUP_EXTENSION: &str = ".ox_backup";
+
+// The byte order mark that some UTF-8 files start with
+const BOM: char = '\u{feff}';
+
 // For holding the info in the command line
 pub struct CommandLine {
     pub msg: Type,
@@ -23,24 +38,55 @@ pub enum Type {
     Info,
 }
 
+// The ways that text can be selected
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SelectionMode {
+    Normal, // The text from the anchor to the cursor, following the lines
+    Column, // The rectangle with the anchor and the cursor in its corners
+}
+
+// The lines and display columns of a column selection, left is inclusive and right isn't
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ColumnSelection {
+    pub top: usize,
+    pub bottom: usize,
+    pub left: usize,
+    pub right: usize,
+}
+
 // Document struct (class) to manage files and text
 pub struct Document {
-    pub rows: Vec<Row>,         // For holding the contents of the document
-    pub path: String,           // For holding the path to the document
-    pub name: String,           // For holding the name of the document
-    pub dirty: bool,            // True if the current document has been edited
-    pub cmd_line: CommandLine,  // For holding the command line
-    pub line_offset: usize,     // For holding a line number offset
-    pub undo_stack: EventStack, // For holding the undo event stack
-    pub redo_stack: EventStack, // For holding the redo event stack
-    pub regex: Vec<TokenType>,  // For holding regular expressions
-    pub icon: String,           // For holding the icon of the document
-    pub kind: String,           // For holding the icon of the document
-    pub show_welcome: bool,     // Whether to show welcome in the document
-    pub cursor: Position,       // For holding the raw cursor location
-    pub offset: Position,       // For holding the offset on the X and Y axes
-    pub graphemes: usize,       // For holding the special grapheme cursor
-    pub tabs: bool,             // For detecting if tabs are used over spaces
+    pub rows: Vec<Row>,               // For holding the contents of the document
+    pub path: String,                 // For holding the path to the document
+    pub name: String,                 // For holding the name of the document
+    pub dirty: bool,                  // True if the current document has been edited
+    pub cmd_line: CommandLine,        // For holding the command line
+    pub line_offset: usize,           // For holding a line number offset
+    pub undo_stack: EventStack,       // For holding the undo event stack
+    pub regex: Arc<Vec<TokenType>>,   // For holding regular expressions
+    pub icon: String,                 // For holding the icon of the document
+    pub kind: String,                 // For holding the icon of the document
+    pub show_welcome: bool,           // Whether to show welcome in the document
+    pub cursor: Position,             // For holding the raw cursor location
+    pub offset: Position,             // For holding the offset on the X and Y axes
+    pub graphemes: usize,             // For holding the special grapheme cursor
+    pub tabs: bool,                   // For detecting if tabs are used over spaces
+    pub mime: Option<String>,         // For holding a MIME type hint for the language
+    pub tab_width: usize,             // For holding the indentation width of the document
+    pub use_tabs: bool,               // True if indentation should be saved as tabs
+    pub selection: Option<Position>,  // For holding the anchor of the selection
+    pub final_newline: bool,          // True if the document ends with a newline
+    pub comment_line: Option<String>, // For holding the line comment of the language
+    pub comment_block: Option<(String, String)>, // For holding the block comment of the language
+    pub selection_mode: SelectionMode, // For holding the way the selection is made
+    pub search: Option<Regex>,        // For highlighting the matches of a search
+    pub highlighter: Highlighter,     // For keeping the syntax highlighting up to date
+    pub bracket_pairs: Vec<(char, char)>, // For holding the brackets that match up
+    pub indent_after: Vec<Regex>,     // For holding the patterns of lines that open a block
+    pub modified: Option<SystemTime>, // For holding when the file was last written as far as Ox knows
+    pub use_icons: bool,              // True if the Nerd Font icons can be shown
+    pub line_ending: Option<&'static str>, // For holding the line ending to save with, if not \n
+    pub bom: bool,                    // True if the file starts with a byte order mark
 }
 
 // Add methods to the document struct
@@ -55,9 +101,11 @@ impl Document {
             path: String::new(),
             line_offset: config.general.line_number_padding_right
                 + config.general.line_number_padding_left,
-            undo_stack: EventStack::new(),
-            redo_stack: EventStack::new(),
-            regex: Reader::get_syntax_regex(&config, ""),
+            undo_stack: EventStack::new(
+                config.general.max_undo_history,
+                config.general.undo_period,
+            ),
+            regex: Reader::get_syntax_regex(config, "", "", None),
             icon: String::new(),
             kind: String::new(),
             show_welcome: true,
@@ -65,13 +113,35 @@ impl Document {
             cursor: Position { x: 0, y: OFFSET },
             offset: Position { x: 0, y: 0 },
             tabs: false,
+            mime: None,
+            tab_width: config.general.tab_width,
+            use_tabs: config.general.use_tabs,
+            selection: None,
+            selection_mode: SelectionMode::Normal,
+            final_newline: true,
+            comment_line: None,
+            comment_block: None,
+            search: None,
+            highlighter: Highlighter::default(),
+            bracket_pairs: Language::default_bracket_pairs(),
+            indent_after: vec![],
+            use_icons: config.general.use_icons,
+            line_ending: None,
+            bom: false,
+            modified: None,
         }
     }
     pub fn open(config: &Reader, status: &Status, path: &str) -> Option<Self> {
         // Create a new document from a path
         if let Ok(file) = fs::read_to_string(path) {
-            // File exists
-            let file = tabs_to_spaces(&file, config.general.tab_width);
+            // File exists, its line endings and byte order mark are kept for when it's saved
+            let crlf = file.contains("\r\n");
+            let bom = file.starts_with(BOM);
+            let file = set_line_endings(file.strip_prefix(BOM).unwrap_or(&file), "\n");
+            // .editorconfig files are only looked for once, the language uses them too
+            let found = EditorConfig::find(path);
+            let file = tabs_to_spaces(&file, Reader::effective_tab_width(config, path, &found));
+            let ends_with_newline = file.ends_with('\n');
             let mut file = file.split('\n').collect::<Vec<&str>>();
             // Handle newline on last line
             if let Some(line) = file.iter().last() {
@@ -83,8 +153,7 @@ impl Document {
             if file.is_empty() {
                 file.push("");
             }
-            let ext = path.split('.').last().unwrap_or(&"");
-            Some(Self {
+            let mut doc = Self {
                 rows: file.iter().map(|row| Row::from(*row)).collect(),
                 name: Path::new(path)
                     .file_name()
@@ -97,17 +166,37 @@ impl Document {
                 path: path.to_string(),
                 line_offset: config.general.line_number_padding_right
                     + config.general.line_number_padding_left,
-                undo_stack: EventStack::new(),
-                redo_stack: EventStack::new(),
-                regex: Reader::get_syntax_regex(&config, ext),
-                kind: Self::identify(path).0.to_string(),
-                icon: Self::identify(path).1.to_string(),
+                undo_stack: EventStack::new(
+                    config.general.max_undo_history,
+                    config.general.undo_period,
+                ),
+                regex: Arc::default(),
+                kind: String::new(),
+                icon: String::new(),
                 show_welcome: false,
                 graphemes: 0,
                 cursor: Position { x: 0, y: OFFSET },
                 offset: Position { x: 0, y: 0 },
                 tabs: file.contains(&"\n\t"),
-            })
+                mime: None,
+                tab_width: config.general.tab_width,
+                use_tabs: config.general.use_tabs,
+                selection: None,
+                selection_mode: SelectionMode::Normal,
+                final_newline: ends_with_newline,
+                comment_line: None,
+                comment_block: None,
+                search: None,
+                highlighter: Highlighter::default(),
+                bracket_pairs: Language::default_bracket_pairs(),
+                indent_after: vec![],
+                use_icons: config.general.use_icons,
+                line_ending: crlf.then_some("\r\n"),
+                bom,
+                modified: modified_time(path),
+            };
+            doc.identify_language_with(config, &found);
+            Some(doc)
         } else {
             // File doesn't exist
             None
@@ -119,8 +208,7 @@ impl Document {
             doc
         } else {
             // Create blank document
-            let ext = path.split('.').last().unwrap_or(&"");
-            Self {
+            let mut doc = Self {
                 rows: vec![Row::from("")],
                 name: path.to_string(),
                 path: path.to_string(),
@@ -128,19 +216,607 @@ impl Document {
                 cmd_line: Document::config_to_commandline(&status),
                 line_offset: config.general.line_number_padding_right
                     + config.general.line_number_padding_left,
-                undo_stack: EventStack::new(),
-                redo_stack: EventStack::new(),
-                regex: Reader::get_syntax_regex(&config, ext),
-                kind: Self::identify(path).0.to_string(),
-                icon: Self::identify(path).1.to_string(),
+                undo_stack: EventStack::new(
+                    config.general.max_undo_history,
+                    config.general.undo_period,
+                ),
+                regex: Arc::default(),
+                kind: String::new(),
+                icon: String::new(),
                 show_welcome: false,
                 graphemes: 0,
                 cursor: Position { x: 0, y: OFFSET },
                 offset: Position { x: 0, y: 0 },
                 tabs: false,
+                mime: None,
+                tab_width: config.general.tab_width,
+                use_tabs: config.general.use_tabs,
+                selection: None,
+                selection_mode: SelectionMode::Normal,
+                final_newline: true,
+                comment_line: None,
+                comment_block: None,
+                search: None,
+                highlighter: Highlighter::default(),
+                bracket_pairs: Language::default_bracket_pairs(),
+                indent_after: vec![],
+                use_icons: config.general.use_icons,
+                line_ending: None,
+                bom: false,
+                modified: None,
+            };
+            doc.identify_language(config);
+            doc
+        }
+    }
+    pub fn identify_language(&mut self, config: &Reader) {
+        // Work out the language of the document for the icon and syntax highlighting
+        self.identify_language_with(config, &EditorConfig::find(&self.path));
+    }
+    fn identify_language_with(&mut self, config: &Reader, found: &EditorConfig) {
+        // Work out the language of the document, with the .editorconfig settings already found
+        let first_line = self.rows.first().map_or("", |row| row.string.as_str());
+        let mime = self.mime.as_deref();
+        let (kind, icon) = Self::identify(&self.path);
+        let lang = Reader::detect_language(config, &self.path, first_line, mime);
+        (self.tab_width, self.use_tabs) = config.effective_indent(found, lang);
+        self.line_ending = found.line_ending().or(self.line_ending);
+        self.bom = found.bom().unwrap_or(self.bom);
+        self.comment_line = lang.and_then(|lang| lang.comment_line.clone());
+        self.comment_block = lang.and_then(|lang| lang.comment_block.clone());
+        self.bracket_pairs = lang.map_or_else(Language::default_bracket_pairs, |lang| {
+            lang.bracket_pairs.clone()
+        });
+        self.indent_after = lang.map_or_else(Vec::new, |lang| {
+            lang.indent_after
+                .iter()
+                .filter_map(|expr| Regex::new(expr).ok())
+                .collect()
+        });
+        match lang {
+            // Files without a known extension can still be recognised by their shebang
+            Some(lang) if kind == "Unknown" => {
+                self.kind = lang.name.clone();
+                self.icon = lang.icon.clone();
+            }
+            _ => {
+                self.kind = kind.to_string();
+                self.icon = icon.to_string();
+            }
+        }
+        self.regex = Reader::get_syntax_regex(config, &self.path, first_line, mime);
+        self.highlighter.invalidate_from(0);
+    }
+    pub fn toggle_selection(&mut self, mode: SelectionMode) {
+        // Start a selection at the cursor, or clear the current one
+        if self.selection.take().is_some() {
+            self.set_command_line("Selection cleared".to_string(), Type::Info);
+        } else {
+            self.selection = Some(Position {
+                x: self.cursor.x + self.offset.x,
+                y: self.cursor.y + self.offset.y - OFFSET,
+            });
+            self.selection_mode = mode;
+            let message = match mode {
+                SelectionMode::Normal => "Selection started",
+                SelectionMode::Column => "Column selection started",
+            };
+            self.set_command_line(message.to_string(), Type::Info);
+        }
+    }
+    pub fn column_selection(&self) -> Option<ColumnSelection> {
+        // Get the rectangle that is selected, if the selection is a column one
+        if self.selection_mode != SelectionMode::Column {
+            return None;
+        }
+        let (start, end) = self.selection_range()?;
+        Some(ColumnSelection {
+            top: start.y,
+            bottom: end.y,
+            left: cmp::min(start.x, end.x),
+            right: cmp::max(start.x, end.x),
+        })
+    }
+    pub fn selection_range(&self) -> Option<(Position, Position)> {
+        // Get the start and end of the selection in document order
+        let anchor = self.selection?;
+        let cursor = Position {
+            x: self.cursor.x + self.offset.x,
+            y: self.cursor.y + self.offset.y - OFFSET,
+        };
+        if (anchor.y, anchor.x) <= (cursor.y, cursor.x) {
+            Some((anchor, cursor))
+        } else {
+            Some((cursor, anchor))
+        }
+    }
+    pub fn selected_columns(&self, index: usize) -> Option<(usize, usize)> {
+        // Work out which columns of a line are inside the selection
+        if let Some(sel) = self.column_selection() {
+            // An empty column is still shown so it's clear which lines typing goes to
+            let wanted = (sel.top..=sel.bottom).contains(&index);
+            return wanted.then_some((sel.left, cmp::max(sel.right, sel.left + 1)));
+        }
+        let (start, end) = self.selection_range()?;
+        if index < start.y || index > end.y {
+            return None;
+        }
+        let from = if index == start.y { start.x } else { 0 };
+        let to = if index == end.y { end.x } else { usize::MAX };
+        Some((from, to))
+    }
+    pub fn copy_text(&self) -> String {
+        // Get the selected text, or the current line when nothing is selected
+        if let Some(sel) = self.column_selection() {
+            let lines: Vec<&str> = self.rows[sel.top..=sel.bottom]
+                .iter()
+                .map(|row| &row.string[row.byte_index(sel.left)..row.byte_index(sel.right)])
+                .collect();
+            lines.join("\n")
+        } else if let Some((start, end)) = self.selection_range() {
+            self.text_between(start, end)
+        } else {
+            let y = self.cursor.y + self.offset.y - OFFSET;
+            format!("{}\n", self.rows[y].string)
+        }
+    }
+    pub fn cut_text(&mut self, term: &Size) {
+        // Remove the selected text, or the current line when nothing is selected
+        if let Some(sel) = self.column_selection() {
+            self.selection = None;
+            self.undo_stack.commit();
+            self.apply_column_delete(&sel, term);
+            self.undo_stack.commit();
+            return;
+        }
+        let (start, end) = self.selection_range().unwrap_or_else(|| {
+            let y = self.cursor.y + self.offset.y - OFFSET;
+            if y + 1 < self.rows.len() {
+                (Position { x: 0, y }, Position { x: 0, y: y + 1 })
+            } else if y > 0 {
+                let above = Position {
+                    x: self.rows[y - 1].length(),
+                    y: y - 1,
+                };
+                (
+                    above,
+                    Position {
+                        x: self.rows[y].length(),
+                        y,
+                    },
+                )
+            } else {
+                (
+                    Position { x: 0, y },
+                    Position {
+                        x: self.rows[y].length(),
+                        y,
+                    },
+                )
+            }
+        });
+        self.selection = None;
+        self.replace_between(start, end, "", term);
+    }
+    pub fn paste_text(&mut self, text: &str, term: &Size) {
+        // Insert text at the cursor, in place of the selection if there is one
+        let cursor = Position {
+            x: self.cursor.x + self.offset.x,
+            y: self.cursor.y + self.offset.y - OFFSET,
+        };
+        if let Some(sel) = self.column_selection() {
+            // Each line of the column gets a line of the text, going round if there are too few
+            let lines: Vec<&str> = text.trim_end_matches('\n').split('\n').collect();
+            self.undo_stack.commit();
+            self.replace_columns(&sel, &lines, term);
+            self.undo_stack.commit();
+            self.selection = None;
+            return;
+        }
+        let (start, end) = self.selection_range().unwrap_or((cursor, cursor));
+        self.selection = None;
+        self.replace_between(start, end, text, term);
+    }
+    pub fn duplicate(&mut self, term: &Size) {
+        // Copy the selection, or the current line, in after itself
+        let line = self.cursor.y + self.offset.y - OFFSET;
+        match (self.selection_range(), self.selection_mode) {
+            (Some((start, end)), SelectionMode::Normal) => {
+                self.duplicate_selection(start, end, term);
+            }
+            (Some((start, end)), SelectionMode::Column) => {
+                self.duplicate_lines(start.y..end.y + 1, term);
+            }
+            (None, _) => self.duplicate_line(line, term),
+        }
+    }
+    pub fn duplicate_line(&mut self, line: usize, term: &Size) {
+        // Put a copy of a line below it
+        self.duplicate_lines(line..line + 1, term);
+    }
+    pub fn duplicate_lines(&mut self, lines: Range<usize>, term: &Size) {
+        // Put a copy of some lines below them, moving the cursor and selection onto the copy
+        let count = lines.len();
+        let mut rows = self.rows[lines.clone()].to_vec();
+        rows.extend_from_slice(&self.rows[lines.clone()]);
+        self.undo_stack.commit();
+        self.splice_rows(lines.start, count, rows);
+        self.undo_stack.commit();
+        self.shift_cursor(count, true, term);
+    }
+    pub fn convert_case(&mut self, mode: CaseMode, term: &Size) {
+        // Convert the case of the selection, or the word the cursor is on
+        if let Some(sel) = self.column_selection() {
+            let converted = convert_case(&self.copy_text(), mode);
+            let lines: Vec<&str> = converted.split('\n').collect();
+            self.undo_stack.commit();
+            self.replace_columns(&sel, &lines, term);
+            self.undo_stack.commit();
+            return;
+        }
+        let selected = self.selection_range();
+        let (start, end) = selected.unwrap_or_else(|| self.word_at_cursor());
+        let text = self.text_between(start, end);
+        let converted = convert_case(&text, mode);
+        if converted != text {
+            self.replace_between(start, end, &converted, term);
+            // Keep the converted text selected
+            if selected.is_some() {
+                self.selection = Some(start);
             }
         }
     }
+    fn word_at_cursor(&self) -> (Position, Position) {
+        // Find the start and end of the identifier the cursor is on
+        let y = self.cursor.y + self.offset.y - OFFSET;
+        let string = &self.rows[y].string;
+        let index = self.rows[y].byte_index(self.cursor.x + self.offset.x);
+        let is_word = |c: char| c.is_alphanumeric() || c == '_';
+        let start = string[..index]
+            .char_indices()
+            .rev()
+            .take_while(|&(_, c)| is_word(c))
+            .last()
+            .map_or(index, |(i, _)| i);
+        let end = string[index..]
+            .char_indices()
+            .find(|&(_, c)| !is_word(c))
+            .map_or(string.len(), |(i, _)| index + i);
+        (
+            Position {
+                x: UnicodeWidthStr::width(&string[..start]),
+                y,
+            },
+            Position {
+                x: UnicodeWidthStr::width(&string[..end]),
+                y,
+            },
+        )
+    }
+    pub fn move_lines_up(&mut self, lines: Range<usize>, term: &Size) -> bool {
+        // Swap some lines with the one above them, the first line can't go any higher
+        if lines.start == 0 || lines.end > self.rows.len() {
+            return false;
+        }
+        let mut rows = self.rows[lines.clone()].to_vec();
+        rows.push(self.rows[lines.start - 1].clone());
+        self.undo_stack.commit();
+        self.splice_rows(lines.start - 1, lines.len() + 1, rows);
+        self.undo_stack.commit();
+        self.shift_cursor(1, false, term);
+        true
+    }
+    pub fn move_lines_down(&mut self, lines: Range<usize>, term: &Size) -> bool {
+        // Swap some lines with the one below them, the last line can't go any lower
+        if lines.end >= self.rows.len() {
+            return false;
+        }
+        let mut rows = vec![self.rows[lines.end].clone()];
+        rows.extend_from_slice(&self.rows[lines.clone()]);
+        self.undo_stack.commit();
+        self.splice_rows(lines.start, lines.len() + 1, rows);
+        self.undo_stack.commit();
+        self.shift_cursor(1, true, term);
+        true
+    }
+    pub fn sort_lines(&mut self, lines: Range<usize>, descending: bool, term: &Size) {
+        // Sort some lines alphabetically as one undo step, equal lines keep their order
+        let mut order: Vec<usize> = lines.clone().collect();
+        if descending {
+            order.sort_by(|&a, &b| self.rows[b].string.cmp(&self.rows[a].string));
+        } else {
+            order.sort_by(|&a, &b| self.rows[a].string.cmp(&self.rows[b].string));
+        }
+        self.reorder_lines(lines, &order, term);
+    }
+    pub fn sort_lines_by_length(&mut self, lines: Range<usize>, descending: bool, term: &Size) {
+        // Sort some lines from shortest to longest as one undo step
+        let mut order: Vec<usize> = lines.clone().collect();
+        if descending {
+            order.sort_by_key(|&y| Reverse(self.rows[y].length()));
+        } else {
+            order.sort_by_key(|&y| self.rows[y].length());
+        }
+        self.reorder_lines(lines, &order, term);
+    }
+    fn reorder_lines(&mut self, lines: Range<usize>, order: &[usize], term: &Size) {
+        // Put lines in a new order, keeping the cursor on the line it was on
+        let current = self.cursor.y + self.offset.y - OFFSET;
+        let rows = order.iter().map(|&y| self.rows[y].clone()).collect();
+        self.undo_stack.commit();
+        self.splice_rows(lines.start, lines.len(), rows);
+        self.undo_stack.commit();
+        self.selection = None;
+        if let Some(moved) = order.iter().position(|&y| y == current) {
+            let cursor = Position {
+                x: self.cursor.x + self.offset.x,
+                y: lines.start + moved,
+            };
+            self.goto(cursor, term);
+        }
+        self.recalculate_graphemes();
+    }
+    fn shift_cursor(&mut self, lines: usize, down: bool, term: &Size) {
+        // Move the cursor and the selection along with lines that moved, keeping the column
+        let shift = |y: usize| {
+            if down {
+                y + lines
+            } else {
+                y.saturating_sub(lines)
+            }
+        };
+        if let Some(anchor) = &mut self.selection {
+            anchor.y = shift(anchor.y);
+        }
+        let cursor = Position {
+            x: self.cursor.x + self.offset.x,
+            y: shift(self.cursor.y + self.offset.y - OFFSET),
+        };
+        self.goto(cursor, term);
+        self.recalculate_graphemes();
+    }
+    pub fn selected_lines(&self) -> Range<usize> {
+        // Get the lines that the selection covers, or the current line
+        self.selection_range().map_or_else(
+            || {
+                let y = self.cursor.y + self.offset.y - OFFSET;
+                y..y + 1
+            },
+            |(start, end)| start.y..end.y + 1,
+        )
+    }
+    pub fn duplicate_selection(&mut self, start: Position, end: Position, term: &Size) {
+        // Put a copy of the selected text straight after it and select the copy
+        let text = self.text_between(start, end);
+        self.replace_between(end, end, &text, term);
+        self.selection = Some(end);
+    }
+    pub fn apply_column_insert(&mut self, sel: &ColumnSelection, text: &str, term: &Size) {
+        // Put the same text in place of the column on every line of it
+        self.replace_columns(sel, &[text], term);
+    }
+    pub fn apply_column_delete(&mut self, sel: &ColumnSelection, term: &Size) {
+        // Remove the column from every line of it
+        self.replace_columns(sel, &[""], term);
+    }
+    pub fn column_backspace(&mut self, sel: &ColumnSelection, term: &Size) {
+        // Delete the column, or the characters just before it when it's empty
+        if sel.left == sel.right {
+            if sel.left == 0 {
+                return;
+            }
+            let sel = ColumnSelection {
+                left: sel.left - 1,
+                ..*sel
+            };
+            self.apply_column_delete(&sel, term);
+        } else {
+            self.apply_column_delete(sel, term);
+        }
+    }
+    fn replace_columns(&mut self, sel: &ColumnSelection, lines: &[&str], term: &Size) {
+        // Replace the column on each line, leaving an empty column after the new text
+        let mut right = sel.left;
+        for (i, y) in (sel.top..=sel.bottom).enumerate() {
+            let text = lines[i % lines.len()];
+            let before = self.rows[y].clone();
+            let mut string = before.string.clone();
+            // Short lines are padded out to the column, unless nothing goes in it
+            let pad = sel.left.saturating_sub(before.length());
+            if pad > 0 && text.is_empty() {
+                continue;
+            }
+            string.replace_range(
+                before.byte_index(sel.left)..before.byte_index(sel.right),
+                &format!("{}{text}", " ".repeat(pad)),
+            );
+            right = cmp::max(right, sel.left + UnicodeWidthStr::width(text));
+            if string != before.string {
+                let after = Row::from(string.as_str());
+                self.undo_stack.push(Event::UpdateLine(
+                    Position { x: 0, y },
+                    0,
+                    Box::new(before),
+                    Box::new(after.clone()),
+                ));
+                self.rows[y] = after;
+                self.dirty = true;
+            }
+        }
+        // Keep the anchor and the cursor on their own lines
+        let cursor_y = self.cursor.y + self.offset.y - OFFSET;
+        if let Some(anchor) = &mut self.selection {
+            anchor.x = right;
+        }
+        self.goto(
+            Position {
+                x: right,
+                y: cursor_y,
+            },
+            term,
+        );
+        self.snap_cursor(term);
+        self.prevent_unicode_hell();
+        self.recalculate_graphemes();
+    }
+    fn text_between(&self, start: Position, end: Position) -> String {
+        // Get the text from one display position to another
+        let first = &self.rows[start.y];
+        if start.y == end.y {
+            return first.string[first.byte_index(start.x)..first.byte_index(end.x)].to_string();
+        }
+        let last = &self.rows[end.y];
+        let mut result = first.string[first.byte_index(start.x)..].to_string();
+        for row in &self.rows[start.y + 1..end.y] {
+            result.push('\n');
+            result.push_str(&row.string);
+        }
+        result.push('\n');
+        result.push_str(&last.string[..last.byte_index(end.x)]);
+        result
+    }
+    fn replace_between(&mut self, start: Position, end: Position, text: &str, term: &Size) {
+        // Replace the text between two display positions as one undo step
+        let first = &self.rows[start.y];
+        let last = &self.rows[end.y];
+        let before = &first.string[..first.byte_index(start.x)];
+        let after = &last.string[last.byte_index(end.x)..];
+        let mut lines: Vec<String> = text.split('\n').map(str::to_string).collect();
+        let cursor_x = UnicodeWidthStr::width(lines.last().map_or("", String::as_str))
+            + if lines.len() == 1 {
+                UnicodeWidthStr::width(before)
+            } else {
+                0
+            };
+        lines[0].insert_str(0, before);
+        if let Some(line) = lines.last_mut() {
+            line.push_str(after);
+        }
+        let cursor = Position {
+            x: cursor_x,
+            y: start.y + lines.len() - 1,
+        };
+        let rows: Vec<Row> = lines.iter().map(|line| Row::from(line.as_str())).collect();
+        self.undo_stack.commit();
+        self.splice_rows(start.y, end.y - start.y + 1, rows);
+        self.undo_stack.commit();
+        self.goto(cursor, term);
+        self.recalculate_graphemes();
+    }
+    fn splice_rows(&mut self, y: usize, count: usize, rows: Vec<Row>) {
+        // Put rows in place of some lines, recording the changes so they can be undone
+        let kept = cmp::min(count, rows.len());
+        for (i, after) in rows.into_iter().enumerate() {
+            let pos = Position { x: 0, y: y + i };
+            if i >= kept {
+                self.undo_stack.push(Event::InsertLineAbove(pos));
+                self.rows.insert(pos.y, Row::from(""));
+            }
+            if self.rows[pos.y].string != after.string {
+                let before = self.rows[pos.y].clone();
+                self.undo_stack.push(Event::UpdateLine(
+                    pos,
+                    0,
+                    Box::new(before),
+                    Box::new(after.clone()),
+                ));
+                self.rows[pos.y] = after;
+            }
+        }
+        // Lines that are left over are removed
+        let pos = Position { x: 0, y: y + kept };
+        for _ in kept..count {
+            let before = self.rows.remove(pos.y);
+            self.undo_stack
+                .push(Event::DeleteLine(pos, 0, Box::new(before)));
+        }
+        self.dirty = true;
+    }
+    pub fn search_columns(&self, index: usize) -> Vec<(usize, usize)> {
+        // Work out which columns of a line match the current search
+        let (Some(search), Some(row)) = (&self.search, self.rows.get(index)) else {
+            return vec![];
+        };
+        search
+            .find_iter(&row.string)
+            .map(|m| {
+                let start = UnicodeWidthStr::width(&row.string[..m.start()]);
+                (start, start + UnicodeWidthStr::width(m.as_str()))
+            })
+            .collect()
+    }
+    pub fn matching_brackets(&self) -> Option<(Position, Position)> {
+        // Find the bracket under or just before the cursor and the one it pairs up with
+        let y = self.cursor.y + self.offset.y - OFFSET;
+        let x = self.cursor.x + self.offset.x;
+        let before = self
+            .rows
+            .get(y)?
+            .boundaries()
+            .into_iter()
+            .rev()
+            .find(|&b| b < x);
+        [Some(x), before].iter().flatten().find_map(|&x| {
+            let pos = Position { x, y };
+            Some((pos, self.find_matching_bracket(pos)?))
+        })
+    }
+    pub fn find_matching_bracket(&self, pos: Position) -> Option<Position> {
+        // Find the partner of a bracket, skipping over nested pairs, strings and comments
+        let quoted = |y: usize, x: usize| {
+            self.highlighter.line(y).is_some_and(|tokens| {
+                tokens.values().any(|t| {
+                    QUOTED.contains(&t.group.as_str()) && (t.span.0..t.span.1).contains(&x)
+                })
+            })
+        };
+        let columns = |y: usize| -> Vec<(usize, &str)> {
+            let row = &self.rows[y];
+            row.boundaries().into_iter().zip(row.chars()).collect()
+        };
+        let (_, bracket) = columns(pos.y).into_iter().find(|(x, _)| *x == pos.x)?;
+        let bracket = bracket.chars().next()?;
+        if quoted(pos.y, pos.x) {
+            return None;
+        }
+        let (open, close, forwards) = self.bracket_pairs.iter().find_map(|&(open, close)| {
+            if bracket == open {
+                Some((open, close, true))
+            } else if bracket == close {
+                Some((close, open, false))
+            } else {
+                None
+            }
+        })?;
+        // Walk away from the bracket in its direction, keeping track of how deep we are
+        let mut depth = 0;
+        let lines: Vec<usize> = if forwards {
+            (pos.y..self.rows.len()).collect()
+        } else {
+            (0..=pos.y).rev().collect()
+        };
+        for y in lines {
+            let mut line = columns(y);
+            if !forwards {
+                line.reverse();
+            }
+            for (x, ch) in line {
+                let passed = if forwards { x > pos.x } else { x < pos.x };
+                if (y == pos.y && !passed) || quoted(y, x) {
+                    continue;
+                }
+                if ch.starts_with(open) && ch.len() == open.len_utf8() {
+                    depth += 1;
+                } else if ch.starts_with(close) && ch.len() == close.len_utf8() {
+                    if depth == 0 {
+                        return Some(Position { x, y });
+                    }
+                    depth -= 1;
+                }
+            }
+        }
+        None
+    }
     pub fn set_command_line(&mut self, text: String, msg: Type) {
         // Function to update the command line
         self.cmd_line = CommandLine { text, msg };
@@ -148,42 +824,60 @@ impl Document {
     fn config_to_commandline(status: &Status) -> CommandLine {
         CommandLine {
             text: match status {
-                Status::Success => "Welcome to Ox".to_string(),
+                Status::Success(path) => format!("Welcome to Ox, using config from {path}"),
                 Status::File => "Config file not found, using default values".to_string(),
-                Status::Parse(error) => format!("Failed to parse: {:?}", error),
+                Status::Parse(error) => error.to_string(),
+                Status::Warning(warnings) => warnings.join("; "),
             },
             msg: match status {
-                Status::Success => Type::Info,
-                Status::File => Type::Warning,
+                Status::Success(_) => Type::Info,
+                Status::File | Status::Warning(_) => Type::Warning,
                 Status::Parse(_) => Type::Error,
             },
         }
     }
     pub fn format(&self, template: &str) -> String {
-        // Form data from a template
-        template
-            .replace("%f", &self.name)
-            .replace("%F", &self.path)
-            .replace("%i", &self.icon)
-            .replace(
-                "%I",
-                &if self.icon.is_empty() {
-                    String::new()
-                } else {
-                    format!("{} ", self.icon)
-                },
-            )
-            .replace("%n", &self.kind)
-            .replace(
-                "%l",
-                &format!("{}", self.cursor.y + self.offset.y - OFFSET + 1),
-            )
-            .replace("%L", &format!("{}", self.rows.len()))
-            .replace("%x", &format!("{}", self.cursor.x + self.offset.x))
-            .replace("%y", &format!("{}", self.cursor.y + self.offset.y))
-            .replace("%v", VERSION)
-            .replace("%d", if self.dirty { "[+]" } else { "" })
-            .replace("%D", if self.dirty { "\u{fb12} " } else { "\u{f723} " })
+        // Form data from a template, in one go so that a file name can't be read as a token
+        let mut result = String::new();
+        let mut chars = template.chars().peekable();
+        while let Some(c) = chars.next() {
+            match chars.peek().and_then(|&token| self.token(c, token)) {
+                Some(value) => {
+                    result.push_str(&value);
+                    chars.next();
+                }
+                // Anything that isn't a token is kept as it is
+                None => result.push(c),
+            }
+        }
+        result
+    }
+    fn token(&self, percent: char, token: char) -> Option<String> {
+        // Work out the value of a template token such as %f
+        if percent != '%' {
+            return None;
+        }
+        Some(match token {
+            'f' => self.name.clone(),
+            'F' => self.path.clone(),
+            // Without a Nerd Font the icons would be boxes, so they are left out
+            'i' | 'I' | 'D' if !self.use_icons => String::new(),
+            'i' => self.icon.clone(),
+            'I' if self.icon.is_empty() => String::new(),
+            'I' => format!("{} ", self.icon),
+            'n' | 'Y' => self.kind.clone(),
+            'l' => (self.cursor.y + self.offset.y - OFFSET + 1).to_string(),
+            'c' => (self.cursor.x + self.offset.x + 1).to_string(),
+            'L' => self.rows.len().to_string(),
+            'x' => (self.cursor.x + self.offset.x).to_string(),
+            'y' => (self.cursor.y + self.offset.y).to_string(),
+            'v' => VERSION.to_string(),
+            'd' | 'm' if self.dirty => "[+]".to_string(),
+            'd' | 'm' => String::new(),
+            'D' if self.dirty => "\u{fb12} ".to_string(),
+            'D' => "\u{f723} ".to_string(),
+            _ => return None,
+        })
     }
     pub fn move_cursor(&mut self, direction: Key, term: &Size) {
         // Move the cursor around the editor
@@ -352,13 +1046,207 @@ impl Document {
             + config.general.line_number_padding_right
             + config.general.line_number_padding_left;
     }
-    pub fn tab(&mut self, pos: &Position, config: &Reader, term: &Size) {
+    pub fn tab(&mut self, pos: &Position, term: &Size) {
         // Insert a tab
-        for _ in 0..config.general.tab_width {
+        for _ in 0..self.tab_width {
             self.rows[pos.y].insert(' ', pos.x);
             self.move_cursor(Key::Right, term);
         }
     }
+    pub fn auto_indent(&mut self, y: usize, term: &Size) {
+        // Indent a new line to follow on from the line above it
+        let before = self.rows[y].clone();
+        let rest = before.string.trim_start();
+        let mut indent = compute_indent(
+            &self.rows[y.saturating_sub(1)].string,
+            self.tab_width,
+            &self.indent_after,
+        );
+        if rest.starts_with(&['}', ')'][..]) {
+            // Closing lines go back to the level of the line that opened them
+            indent.truncate(indent.len().saturating_sub(self.tab_width));
+        }
+        let after = Row::from(format!("{indent}{rest}").as_str());
+        if after.string != before.string {
+            self.undo_stack.push(Event::UpdateLine(
+                Position { x: 0, y },
+                0,
+                Box::new(before),
+                Box::new(after.clone()),
+            ));
+            self.undo_stack.commit();
+            self.rows[y] = after;
+            self.goto(Position { x: indent.len(), y }, term);
+            self.recalculate_graphemes();
+        }
+    }
+    pub fn soft_tab_before(&self, pos: &Position) -> Option<Position> {
+        // Find the start of the level of indentation that ends at a position
+        let before = &self.rows[pos.y].string;
+        let level = self.tab_width > 0
+            && pos.x >= self.tab_width
+            && pos.x.is_multiple_of(self.tab_width)
+            && before.chars().take(pos.x).all(|c| c == ' ');
+        level.then(|| Position {
+            x: pos.x - self.tab_width,
+            y: pos.y,
+        })
+    }
+    pub fn dedent_closer(&mut self, pos: &Position, term: &Size) -> Position {
+        // Take a level of indentation off a line that is being closed
+        let before = &self.rows[pos.y].string;
+        if pos.x >= self.tab_width
+            && self.tab_width > 0
+            && before.chars().take(pos.x).all(|c| c == ' ')
+        {
+            let start = Position {
+                x: pos.x - self.tab_width,
+                y: pos.y,
+            };
+            self.execute(Event::DeleteTab(start), false, term);
+            return start;
+        }
+        *pos
+    }
+    pub fn ensure_final_newline(&mut self) -> bool {
+        // Make sure the document ends with a newline, as its own undo step
+        let empty = self.rows.len() == 1 && self.rows[0].string.is_empty();
+        if self.final_newline || empty {
+            return false;
+        }
+        self.undo_stack.commit();
+        self.undo_stack.push(Event::FinalNewline(false, true));
+        self.undo_stack.commit();
+        self.final_newline = true;
+        self.dirty = true;
+        true
+    }
+    pub fn trim_final_newlines(&mut self, term: &Size) -> bool {
+        // Remove the blank lines from the end of the document, as its own undo step
+        let keep = self
+            .rows
+            .iter()
+            .rposition(|row| !row.string.is_empty())
+            .map_or(1, |y| y + 1);
+        if keep >= self.rows.len() {
+            return false;
+        }
+        self.undo_stack.commit();
+        self.splice_rows(keep, self.rows.len() - keep, vec![]);
+        self.undo_stack.commit();
+        // Bring the cursor back if its line was removed
+        if self.cursor.y + self.offset.y - OFFSET >= keep {
+            let end = Position {
+                x: self.rows[keep - 1].length(),
+                y: keep - 1,
+            };
+            self.goto(end, term);
+        }
+        self.recalculate_graphemes();
+        true
+    }
+    pub fn trim_trailing_whitespace(&mut self, term: &Size, cursor_line: bool) {
+        // Remove whitespace from the ends of lines as one undo step
+        let current = self.cursor.y + self.offset.y - OFFSET;
+        self.undo_stack.commit();
+        for y in 0..self.rows.len() {
+            if y == current && !cursor_line {
+                continue;
+            }
+            let before = &self.rows[y];
+            if before.string.trim_end().len() != before.string.len() {
+                let after = Row::from(before.string.trim_end());
+                self.undo_stack.push(Event::UpdateLine(
+                    Position { x: 0, y },
+                    0,
+                    Box::new(before.clone()),
+                    Box::new(after.clone()),
+                ));
+                self.rows[y] = after;
+                self.dirty = true;
+            }
+        }
+        self.undo_stack.commit();
+        // Keep the cursor inside its line
+        self.snap_cursor(term);
+        self.prevent_unicode_hell();
+        self.recalculate_graphemes();
+    }
+    pub fn toggle_comment(&mut self, term: &Size) {
+        // Toggle comments on the selected lines, or the current line
+        let lines = self.selected_lines();
+        if self.toggle_line_comment(lines) {
+            self.undo_stack.commit();
+            self.snap_cursor(term);
+            self.prevent_unicode_hell();
+            self.recalculate_graphemes();
+        } else {
+            self.set_command_line(
+                format!("No comment syntax for {}", self.kind),
+                Type::Warning,
+            );
+        }
+    }
+    fn toggle_line_comment(&mut self, lines: Range<usize>) -> bool {
+        // Comment out lines, or uncomment them if they are all commented already
+        let (start, end) = if let Some(start) = &self.comment_line {
+            (start.clone(), String::new())
+        } else if let Some((start, end)) = &self.comment_block {
+            (start.clone(), end.clone())
+        } else {
+            return false;
+        };
+        let lines = lines.start..cmp::min(lines.end, self.rows.len());
+        // Blank lines are left alone
+        let filled: Vec<usize> = lines
+            .filter(|&y| !self.rows[y].string.trim().is_empty())
+            .collect();
+        let commented = filled.iter().all(|&y| {
+            let line = self.rows[y].string.trim();
+            line.len() >= start.len() + end.len()
+                && line.starts_with(&start)
+                && line.ends_with(&end)
+        });
+        // Comments are lined up with the least indented line
+        let indent = filled
+            .iter()
+            .map(|&y| {
+                let line = &self.rows[y].string;
+                line.len() - line.trim_start().len()
+            })
+            .min()
+            .unwrap_or(0);
+        for y in filled {
+            let before = self.rows[y].clone();
+            let line = &before.string;
+            let after = if commented {
+                let body = line.trim_end();
+                let at = body.len() - body.trim_start().len();
+                let body = &body[at + start.len()..body.len() - end.len()];
+                let body = body.strip_prefix(' ').unwrap_or(body);
+                let body = if end.is_empty() {
+                    body
+                } else {
+                    body.strip_suffix(' ').unwrap_or(body)
+                };
+                format!("{}{}", &line[..at], body)
+            } else if end.is_empty() {
+                format!("{}{} {}", &line[..indent], start, &line[indent..])
+            } else {
+                format!("{}{} {} {}", &line[..indent], start, &line[indent..], end)
+            };
+            let after = Row::from(after.as_str());
+            self.undo_stack.push(Event::UpdateLine(
+                Position { x: 0, y },
+                0,
+                Box::new(before),
+                Box::new(after.clone()),
+            ));
+            self.rows[y] = after;
+            self.dirty = true;
+        }
+        true
+    }
     fn overwrite(&mut self, after: &[Row]) {
         // Override the entire contents of the document
         self.dirty = true;
@@ -426,7 +1314,7 @@ impl Document {
             self.undo_stack.commit();
         }
     }
-    pub fn execute(&mut self, event: Event, reversed: bool, term: &Size, config: &Reader) {
+    pub fn execute(&mut self, event: Event, reversed: bool, term: &Size) {
         // Document edit event executor
         match event {
             Event::Overwrite(_, ref after) => {
@@ -498,7 +1386,14 @@ impl Document {
             Event::InsertTab(pos) => {
                 self.dirty = true;
                 self.goto(pos, term);
-                self.tab(&pos, &config, term);
+                self.tab(&pos, term);
+                if !reversed {
+                    self.undo_stack.push(event);
+                }
+            }
+            Event::FinalNewline(_, after) => {
+                self.dirty = true;
+                self.final_newline = after;
                 if !reversed {
                     self.undo_stack.push(event);
                 }
@@ -506,7 +1401,7 @@ impl Document {
             Event::DeleteTab(pos) => {
                 self.dirty = true;
                 self.goto(pos, term);
-                for _ in 0..config.general.tab_width {
+                for _ in 0..self.tab_width {
                     self.rows[pos.y].delete(pos.x);
                 }
                 if !reversed {
@@ -517,20 +1412,108 @@ impl Document {
         }
     }
     pub fn word_left(&mut self, term: &Size) {
-        self.move_cursor(Key::Left, term);
-        let row = self.rows[self.cursor.y + self.offset.y - OFFSET].clone();
-        while self.cursor.x + self.offset.x != 0
-            && row.chars()[self.graphemes.saturating_sub(1)] != " "
-        {
-            self.move_cursor(Key::Left, term);
-        }
+        // Move the cursor back to the start of the word before it
+        let target = self.word_boundary(false);
+        self.goto(target, term);
+        self.recalculate_graphemes();
     }
     pub fn word_right(&mut self, term: &Size) {
-        let row = self.rows[self.cursor.y + self.offset.y - OFFSET].clone();
-        while self.cursor.x + self.offset.x != row.length() && row.chars()[self.graphemes] != " " {
-            self.move_cursor(Key::Right, term);
+        // Move the cursor on to the end of the word after it
+        let target = self.word_boundary(true);
+        self.goto(target, term);
+        self.recalculate_graphemes();
+    }
+    pub fn delete_word_left(&mut self, term: &Size) {
+        // Delete back to the start of the word before the cursor, as one undo step
+        let cursor = Position {
+            x: self.cursor.x + self.offset.x,
+            y: self.cursor.y + self.offset.y - OFFSET,
+        };
+        let target = self.word_boundary(false);
+        if target != cursor {
+            self.replace_between(target, cursor, "", term);
+        }
+    }
+    pub fn delete_word_right(&mut self, term: &Size) {
+        // Delete on to the end of the word after the cursor, as one undo step
+        let cursor = Position {
+            x: self.cursor.x + self.offset.x,
+            y: self.cursor.y + self.offset.y - OFFSET,
+        };
+        let target = self.word_boundary(true);
+        if target != cursor {
+            self.replace_between(cursor, target, "", term);
+        }
+    }
+    fn word_boundary(&self, forward: bool) -> Position {
+        // Find the next change between word characters, punctuation and spaces from the cursor
+        let y = self.cursor.y + self.offset.y - OFFSET;
+        let row = &self.rows[y];
+        let index = row.byte_index(self.cursor.x + self.offset.x);
+        // The ends of lines are boundaries of their own
+        if forward && index >= row.string.len() {
+            return if y + 1 < self.rows.len() {
+                Position { x: 0, y: y + 1 }
+            } else {
+                Position { x: row.length(), y }
+            };
+        } else if !forward && index == 0 {
+            return y
+                .checked_sub(1)
+                .map_or(Position { x: 0, y }, |above| Position {
+                    x: self.rows[above].length(),
+                    y: above,
+                });
+        }
+        let class = |c: char| {
+            if c.is_whitespace() {
+                0
+            } else if c.is_alphanumeric() || c == '_' {
+                1
+            } else {
+                2
+            }
+        };
+        let chars: Box<dyn Iterator<Item = (usize, char)>> = if forward {
+            Box::new(
+                row.string[index..]
+                    .char_indices()
+                    .map(|(i, c)| (index + i, c)),
+            )
+        } else {
+            Box::new(row.string[..index].char_indices().rev())
+        };
+        // Spaces are skipped over on the way to the word
+        let mut kind = None;
+        let mut end = if forward { row.string.len() } else { 0 };
+        for (i, c) in chars {
+            match kind {
+                None if class(c) != 0 => kind = Some(class(c)),
+                Some(kind) if class(c) != kind => {
+                    end = if forward { i } else { i + c.len_utf8() };
+                    break;
+                }
+                _ => (),
+            }
+        }
+        Position {
+            x: UnicodeWidthStr::width(&row.string[..end]),
+            y,
         }
-        self.move_cursor(Key::Right, term);
+    }
+    pub fn restore_view(&mut self, pos: Position, scroll: usize, term: &Size) {
+        // Put the cursor back on a line with that line scrolled to where it was, if it still fits
+        let y = cmp::min(pos.y, self.rows.len() - 1);
+        let x = cmp::min(pos.x, self.rows[y].length());
+        self.goto(Position { x, y }, term);
+        let max_y = term.height.saturating_sub(3);
+        let scroll = cmp::min(scroll, y);
+        if y + OFFSET - scroll < max_y {
+            self.offset.y = scroll;
+            self.cursor.y = y + OFFSET - scroll;
+        }
+        self.prevent_unicode_hell();
+        self.recalculate_graphemes();
     }
     pub fn goto(&mut self, mut pos: Position, term: &Size) {
         // Move the cursor to a specific location
@@ -564,25 +1547,96 @@ impl Document {
             }
         }
     }
-    pub fn save(&self, path: &str, tab: usize) -> std::io::Result<()> {
-        // Save a file
-        let contents = self.render(true, tab);
+    pub fn save(&self, path: &str) -> std::io::Result<()> {
+        // Save a file, with the line endings and byte order mark .editorconfig asks for
+        let mut contents = self.render(self.use_tabs, self.tab_width);
+        if let Some(ending) = self.line_ending {
+            contents = set_line_endings(&contents, ending);
+        }
+        if self.bom {
+            contents.insert(0, BOM);
+        }
         fs::write(path, contents)
     }
-    pub fn scan(&self, needle: &str, offset: usize) -> Vec<Position> {
-        // Find all the points where "needle" occurs
-        let mut result = vec![];
-        if let Ok(re) = Regex::new(needle) {
-            for (i, row) in self.rows.iter().enumerate() {
-                for o in re.find_iter(&row.string) {
-                    result.push(Position {
-                        x: o.start(),
-                        y: i + offset,
-                    });
-                }
-            }
+    pub fn backup_path(&self) -> Option<String> {
+        // The backup sits next to the file, documents without a path can't have one
+        (!self.path.is_empty()).then(|| format!("{}{BACKUP_EXTENSION}", self.path))
+    }
+    pub fn backup(&self) -> std::io::Result<()> {
+        // Write the unsaved changes to the backup file
+        match self.backup_path() {
+            Some(path) => self.save(&path),
+            None => Ok(()),
         }
-        result
+    }
+    pub fn remove_backup(&self) {
+        // Delete the backup once it's no longer needed
+        if let Some(path) = self.backup_path() {
+            let _ = fs::remove_file(path);
+        }
+    }
+    pub fn recover(&mut self, term: &Size) -> bool {
+        // Replace the contents with those of the backup, as a change that can be undone
+        let Some(contents) = self
+            .backup_path()
+            .and_then(|path| fs::read_to_string(path).ok())
+        else {
+            return false;
+        };
+        self.replace_contents(&contents, term);
+        true
+    }
+    pub fn changed_on_disk(&self) -> Option<SystemTime> {
+        // Find out if another program has written to the file since Ox last read or saved it
+        let modified = modified_time(&self.path)?;
+        self.modified
+            .is_some_and(|known| known != modified)
+            .then_some(modified)
+    }
+    pub fn record_modified(&mut self) {
+        // Remember when the file was written so that only other programs' changes are noticed
+        self.modified = modified_time(&self.path);
+    }
+    pub fn reload(&mut self, term: &Size) -> bool {
+        // Read the file again, keeping the cursor where it was unless the length changed a lot
+        let Ok(contents) = fs::read_to_string(&self.path) else {
+            return false;
+        };
+        let cursor = Position {
+            x: self.cursor.x + self.offset.x,
+            y: self.cursor.y + self.offset.y - OFFSET,
+        };
+        let (scroll, length) = (self.offset.y, self.rows.len());
+        self.replace_contents(&contents, term);
+        if self.rows.len().abs_diff(length) <= length / 2 {
+            self.restore_view(cursor, scroll, term);
+        }
+        self.dirty = false;
+        self.record_modified();
+        true
+    }
+    fn replace_contents(&mut self, contents: &str, term: &Size) {
+        // Swap in new contents for the whole document, as a change that can be undone
+        self.bom = contents.starts_with(BOM);
+        let contents = contents.strip_prefix(BOM).unwrap_or(contents);
+        let contents = tabs_to_spaces(&set_line_endings(contents, "\n"), self.tab_width);
+        let final_newline = contents.ends_with('\n');
+        let mut rows: Vec<Row> = contents.split('\n').map(Row::from).collect();
+        if final_newline {
+            rows.pop();
+        }
+        if rows.is_empty() {
+            rows.push(Row::from(""));
+        }
+        self.execute(Event::Overwrite(self.rows.clone(), rows), false, term);
+        if final_newline != self.final_newline {
+            self.execute(
+                Event::FinalNewline(self.final_newline, final_newline),
+                false,
+                term,
+            );
+        }
+        self.undo_stack.commit();
     }
     pub fn render(&self, replace_tab: bool, tab_width: usize) -> String {
         // Render the lines of a document for writing
@@ -592,7 +1646,7 @@ impl Document {
             .map(|x| x.string.clone())
             .collect::<Vec<String>>()
             .join("\n")
-            + "\n";
+            + if self.final_newline { "\n" } else { "" };
         if replace_tab {
             spaces_to_tabs(&render, tab_width)
         } else {
@@ -601,8 +1655,8 @@ impl Document {
     }
     pub fn identify(path: &str) -> (&str, &str) {
         // Identify which type of file the current buffer is
-        match path.split('.').last() {
-            Some(ext) => match ext {
+        match path.rsplit('.').next().map(str::to_lowercase) {
+            Some(ext) => match ext.as_str() {
                 "asm" => ("Assembly ", "\u{f471} "),
                 "b" => ("B", "\u{e7a3} "),
                 "bf" => ("Brainfuck", "\u{e28c} "),
@@ -653,3 +1707,188 @@ impl Document {
         }
     }
 }
+
+fn modified_time(path: &str) -> Option<SystemTime> {
+    // Find out when a file was last written to
+    fs::metadata(path).ok()?.modified().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn document(lines: &[&str]) -> Document {
+        // A document that isn't backed by a file
+        let mut doc = Document::new(&Reader::default(), &Status::File);
+        doc.rows = lines.iter().map(|&line| Row::from(line)).collect();
+        doc
+    }
+
+    #[test]
+    fn status_templates_are_expanded() {
+        let mut doc = document(&["fn main() {", "    println!();", "}"]);
+        doc.name = String::from("main.rs");
+        doc.kind = String::from("Rust");
+        doc.dirty = true;
+        doc.cursor = Position {
+            x: 4,
+            y: OFFSET + 1,
+        };
+        assert_eq!(doc.format("%f%m %Y %l:%c/%L"), "main.rs[+] Rust 2:5/3");
+        doc.dirty = false;
+        assert_eq!(doc.format("%f%m (%n)"), "main.rs (Rust)");
+    }
+
+    #[test]
+    fn unknown_tokens_are_kept() {
+        let mut doc = document(&[""]);
+        doc.name = String::from("100%l.txt");
+        assert_eq!(doc.format("%f %q 50% %"), "100%l.txt %q 50% %");
+    }
+
+    #[test]
+    fn soft_tabs_end_on_tab_stops() {
+        let mut doc = document(&["        let x = 1;", "  a   b"]);
+        doc.tab_width = 4;
+        let at = |x, y| Position { x, y };
+        assert_eq!(doc.soft_tab_before(&at(8, 0)), Some(at(4, 0)));
+        assert_eq!(doc.soft_tab_before(&at(4, 0)), Some(at(0, 0)));
+        // Between tab stops a backspace only takes one space
+        assert_eq!(doc.soft_tab_before(&at(6, 0)), None);
+        assert_eq!(doc.soft_tab_before(&at(2, 0)), None);
+        // Spaces after text aren't indentation
+        assert_eq!(doc.soft_tab_before(&at(4, 1)), None);
+        doc.tab_width = 2;
+        assert_eq!(doc.soft_tab_before(&at(6, 0)), Some(at(4, 0)));
+    }
+
+    #[test]
+    fn syntax_follows_the_language_of_the_file() {
+        let mut doc = document(&["def main():"]);
+        doc.path = String::from("main.py");
+        doc.identify_language(&Reader::default());
+        let keywords = doc
+            .regex
+            .iter()
+            .find_map(|token| match token {
+                TokenType::SingleLine(name, regex) if name == "keywords" => regex.first(),
+                _ => None,
+            })
+            .unwrap();
+        // Rust is the first language, its keywords mustn't be used for Python
+        assert!(keywords.is_match("def") && !keywords.is_match("fn"));
+        assert_eq!(doc.kind, "Python");
+    }
+
+    #[test]
+    fn trailing_whitespace_is_trimmed() {
+        let lines = ["let x = 1;   ", "    \t", "let y = 2;", "  code  "];
+        let term = Size {
+            width: 80,
+            height: 24,
+        };
+        let trimmed = |doc: &Document| -> Vec<String> {
+            doc.rows.iter().map(|row| row.string.clone()).collect()
+        };
+        let mut doc = document(&lines);
+        doc.cursor = Position {
+            x: 0,
+            y: OFFSET + 3,
+        };
+        doc.trim_trailing_whitespace(&term, false);
+        // The line being typed on is left for the cursor
+        assert_eq!(trimmed(&doc), ["let x = 1;", "", "let y = 2;", "  code  "]);
+        assert!(doc.dirty);
+        doc.trim_trailing_whitespace(&term, true);
+        assert_eq!(trimmed(&doc), ["let x = 1;", "", "let y = 2;", "  code"]);
+        let mut clean = document(&["let y = 2;"]);
+        clean.trim_trailing_whitespace(&term, true);
+        assert!(!clean.dirty);
+    }
+
+    #[test]
+    fn files_end_with_one_newline() {
+        let term = Size {
+            width: 80,
+            height: 24,
+        };
+        let mut none = document(&["x = 1"]);
+        none.final_newline = false;
+        assert!(!none.trim_final_newlines(&term));
+        assert!(none.ensure_final_newline());
+        assert_eq!(none.render(false, 4), "x = 1\n");
+        let mut one = document(&["x = 1"]);
+        assert!(!one.trim_final_newlines(&term));
+        assert!(!one.ensure_final_newline());
+        assert_eq!(one.render(false, 4), "x = 1\n");
+        let mut several = document(&["x = 1", "", ""]);
+        several.cursor.y = OFFSET + 2;
+        assert!(several.trim_final_newlines(&term));
+        assert!(!several.ensure_final_newline());
+        assert_eq!(several.render(false, 4), "x = 1\n");
+        // The cursor moves back onto a line that's still there
+        assert_eq!(several.cursor.y + several.offset.y - OFFSET, 0);
+        // An empty file doesn't need a newline
+        let mut empty = document(&[""]);
+        empty.final_newline = false;
+        assert!(!empty.ensure_final_newline());
+        assert_eq!(empty.render(false, 4), "");
+    }
+
+    #[test]
+    fn equal_lines_keep_their_order_when_sorted() {
+        let term = Size {
+            width: 80,
+            height: 24,
+        };
+        let lines = |doc: &Document| -> Vec<String> {
+            doc.rows.iter().map(|row| row.string.clone()).collect()
+        };
+        let mut doc = document(&["bb", "a", "cc", "ddd", "e"]);
+        doc.sort_lines_by_length(0..5, true, &term);
+        assert_eq!(lines(&doc), ["ddd", "bb", "cc", "a", "e"]);
+        doc.sort_lines_by_length(0..5, false, &term);
+        assert_eq!(lines(&doc), ["a", "e", "bb", "cc", "ddd"]);
+        // The cursor stays with its own copy of a repeated line
+        let mut doc = document(&["b", "a", "b", "c"]);
+        doc.cursor.y = OFFSET + 2;
+        doc.sort_lines(0..4, true, &term);
+        assert_eq!(lines(&doc), ["c", "b", "b", "a"]);
+        assert_eq!(doc.cursor.y + doc.offset.y - OFFSET, 2);
+    }
+
+    #[test]
+    fn byte_order_marks_survive_a_reload() {
+        let term = Size {
+            width: 80,
+            height: 24,
+        };
+        let folder = std::env::temp_dir().join(format!("ox-document-{}", std::process::id()));
+        fs::create_dir_all(&folder).unwrap();
+        let path = folder.join("bom.txt").to_string_lossy().to_string();
+        fs::write(&path, "\u{feff}first\nsecond\n").unwrap();
+        let mut doc = Document::open(&Reader::default(), &Status::File, &path).unwrap();
+        fs::write(&path, "\u{feff}changed\nsecond\n").unwrap();
+        assert!(doc.reload(&term));
+        // The mark isn't part of the first line, and it's written back on save
+        assert_eq!(doc.rows[0].string, "changed");
+        assert!(doc.bom);
+        doc.save(&path).unwrap();
+        let saved = fs::read_to_string(&path).unwrap();
+        fs::remove_dir_all(&folder).unwrap();
+        assert_eq!(saved, "\u{feff}changed\nsecond\n");
+    }
+
+    #[test]
+    fn icons_are_left_out_without_a_nerd_font() {
+        let mut doc = document(&["fn main() {}"]);
+        doc.path = String::from("main.rs");
+        doc.identify_language(&Reader::default());
+        doc.use_icons = true;
+        assert!(!doc.icon.is_empty());
+        assert_eq!(doc.format("%i|%I"), format!("{0}|{0} ", doc.icon));
+        assert_eq!(doc.format("%D"), "\u{f723} ");
+        doc.use_icons = false;
+        assert_eq!(doc.format("%i|%I|%D"), "||");
+    }
+}
@@ -1,16 +1,24 @@
 // Editor.rs - Controls the editor and brings everything together
-use crate::config::{KeyBinding, Reader, Status};
-use crate::document::Type;
+use crate::clipboard::{self, Clipboard};
+use crate::config::{KeyBinding, Reader, Status, PROJECT_CONFIG, SYSTEM_CONFIG};
+use crate::document::{SelectionMode, Type};
+use crate::editorconfig::EditorConfig;
+use crate::finder::FileFinder;
 use crate::oxa::interpret_line;
+use crate::pane::{Area, Orientation, Pane, SplitPane};
+use crate::row::{RenderOptions, Span};
+use crate::search::{SearchEngine, SearchReplace};
+use crate::session::Session;
 use crate::undo::{reverse, BankType};
-use crate::util::{is_ahead, is_behind, title, trim_end, Exp};
-use crate::{Document, Event, Row, Terminal, VERSION};
-use clap::App;
-use regex::Regex;
-use std::time::{Duration, Instant};
+use crate::util::{is_ahead, is_behind, title, trim_end, CaseMode, Exp};
+use crate::{Document, Event, Row, Size, Terminal, VERSION};
+use clap::ArgMatches;
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver};
+use std::time::{Duration, Instant, SystemTime};
 use std::{collections::HashMap, io::Error, thread};
-use termion::event::Key;
-use termion::input::{Keys, TermRead};
+use termion::event::{Event as InputEvent, Key, MouseButton, MouseEvent};
+use termion::input::{Events, TermRead};
 use termion::{async_stdin, color, style, AsyncReader};
 
 // Set up color resets
@@ -20,6 +28,12 @@ pub const RESET_FG: color::Fg<color::Reset> = color::Fg(color::Reset);
 // Set up offset rules
 pub const OFFSET: usize = 1;
 
+// How far the mouse wheel scrolls
+const SCROLL_LINES: isize = 3;
+
+// How often open files are checked for changes made by other programs
+const DISK_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
 // Enum for holding prompt events
 enum PromptEvent {
     Update,
@@ -28,7 +42,7 @@ enum PromptEvent {
 }
 
 // For representing positions
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Position {
     pub x: usize,
     pub y: usize,
@@ -47,67 +61,179 @@ pub enum Direction {
 pub struct Editor {
     pub config: Reader,                      // Storage for configuration
     pub status: Status,                      // Holding the status of the config
+    reloads: Receiver<(Reader, Status)>,     // For receiving configs that changed on disk
     quit: bool,                              // Toggle for cleanly quitting the editor
     term: Terminal,                          // For the handling of the terminal
     doc: Vec<Document>,                      // For holding our document
     tab: usize,                              // Holds the number of the current tab
-    last_keypress: Option<Instant>,          // For holding the time of the last input event
-    stdin: Keys<AsyncReader>,                // Asynchronous stdin
+    last_backup: Instant,                    // For holding the time unsaved changes were backed up
+    last_disk_check: Instant,                // For holding the time files were checked for changes
+    idle: bool,                              // True while waiting for a key outside of any prompt
+    stdin: Events<AsyncReader>,              // Asynchronous stdin
     exp: Exp,                                // For holding expressions
     position_bank: HashMap<usize, Position>, // Bank for cursor positions
     row_bank: HashMap<usize, Row>,           // Bank for lines
     theme: String,                           // Currently used theme
+    finder: Option<FileFinder>,              // The file finder while it is open
+    switcher: Option<usize>,                 // The document picked in the switcher while it is open
+    panes: SplitPane,                        // The panes the screen is split into
+    area: Area,                              // The part of the screen the current document is in
+    view: Size,                              // The size of that part, for moving the cursor around
+    search_options: SearchReplace,           // The options used by the last search or replace
+    clipboard: Box<dyn Clipboard>,           // Where copied text goes and pasted text comes from
 }
 
 // Implementing methods for our editor struct / class
 impl Editor {
-    pub fn new(args: App) -> Result<Self, Error> {
+    pub fn new(args: &ArgMatches) -> Result<Self, Error> {
         // Create a new editor instance
-        let args = args.get_matches();
         // Set up the arguments
         let files: Vec<&str> = args.values_of("files").unwrap_or_default().collect();
-        let config = Reader::read(args.value_of("config").unwrap_or_default());
+        // Use the config file that was asked for, otherwise search for one
+        let user_config = args
+            .value_of("config")
+            .map_or_else(Reader::user_location, ToString::to_string);
+        // Find the project config for the first file, if there is one
+        let project_config = Reader::find_project(files.first().unwrap_or(&""))
+            .unwrap_or_else(|| PROJECT_CONFIG.to_string());
+        // Layer the user config between the system and project configs
+        let config_paths = vec![SYSTEM_CONFIG.to_string(), user_config, project_config];
+        let config = Self::read_config(&config_paths);
+        // Watch the config layers for changes
+        let (sender, reloads) = mpsc::channel();
+        Reader::watch(config_paths, move |config, status| {
+            let _ = sender.send((config, status));
+        });
         let mut documents = vec![];
-        if files.is_empty() {
-            documents.push(Document::new(&config.0, &config.1));
+        for file in &files {
+            let mut doc = Document::from(&config.0, &config.1, file);
+            // Use the MIME type hint for files that can't be identified otherwise
+            if let Some(mime) = args.value_of("mime-type") {
+                doc.mime = Some(mime.to_string());
+                doc.identify_language(&config.0);
+            }
+            documents.push(doc);
+        }
+        // Reopen the files from last time when none are given
+        let session = if files.is_empty() && config.0.general.restore_session {
+            Session::load()
         } else {
-            for file in &files {
-                documents.push(Document::from(&config.0, &config.1, file));
+            None
+        };
+        if let Some(session) = &session {
+            for file in &session.open_files {
+                if Path::new(file).is_file() {
+                    documents.push(Document::from(&config.0, &config.1, file));
+                }
             }
         }
+        if documents.is_empty() {
+            documents.push(Document::new(&config.0, &config.1));
+        }
+        // Warn about any config values that don't make sense
+        let problems = Self::config_problems(&config.0, &config.1);
+        if !problems.is_empty() {
+            documents[0].set_command_line(problems.join("; "), Type::Warning);
+        }
         // Create the new editor instance
-        Ok(Self {
+        let mut term = Terminal::new()?;
+        term.set_mouse(config.0.general.mouse_support);
+        let mut editor = Self {
             quit: false,
             // Display information about the config file into text for the status line
-            term: Terminal::new()?,
+            area: Area {
+                x: 0,
+                y: 0,
+                width: term.size.width,
+                height: term.size.height.saturating_sub(3),
+            },
+            view: term.size,
+            term,
             tab: 0,
             doc: documents,
-            last_keypress: None,
-            stdin: async_stdin().keys(),
+            last_backup: Instant::now(),
+            last_disk_check: Instant::now(),
+            idle: false,
+            stdin: async_stdin().events(),
             config: config.0.clone(),
             status: config.1,
+            reloads,
             exp: Exp::new(),
             position_bank: HashMap::new(),
             row_bank: HashMap::new(),
             theme: config.0.theme.default_theme,
-        })
+            finder: None,
+            switcher: None,
+            panes: SplitPane::new(Pane {
+                doc: 0,
+                cursor: Position { x: 0, y: OFFSET },
+                offset: Position { x: 0, y: 0 },
+            }),
+            search_options: SearchReplace::default(),
+            clipboard: clipboard::detect(),
+        };
+        if let Some(session) = session {
+            editor.restore_session(&session);
+        }
+        Ok(editor)
+    }
+    fn restore_session(&mut self, session: &Session) {
+        // Put the cursors of the reopened files back where they were left
+        for doc in &mut self.doc {
+            let cursor = session.cursor_positions.get(&doc.path);
+            let scroll = session.scroll_offsets.get(&doc.path);
+            if let (Some(&(x, y)), Some(&scroll)) = (cursor, scroll) {
+                doc.restore_view(Position { x, y }, scroll, &self.view);
+            }
+        }
+    }
+    fn session(&self) -> Session {
+        // Gather the open files and where they were left
+        let mut session = Session::default();
+        for doc in &self.doc {
+            let cursor = (
+                doc.cursor.x + doc.offset.x,
+                doc.cursor.y + doc.offset.y - OFFSET,
+            );
+            session.add(&doc.path, cursor, doc.offset.y);
+        }
+        session
+    }
+    fn save_session(&self, session: &Session) {
+        // Remember a session for the next time Ox starts without any files
+        if self.config.general.restore_session {
+            let _ = session.save();
+        }
     }
     pub fn run(&mut self) {
         // Run the editor instance
         // TODO: Render entire document row here
+        self.term.set_cursor_colour(self.config.theme.cursor_color);
+        for index in 0..self.doc.len() {
+            self.offer_recovery(index);
+        }
+        self.tab = 0;
         while !self.quit {
             self.update();
             self.process_input();
         }
+        self.term.reset_cursor_colour();
     }
     fn read_key(&mut self) -> Key {
         // Wait until a key is pressed and then return it
         loop {
-            if let Some(key) = self.stdin.next() {
+            if let InputEvent::Key(key) = self.read_event() {
+                return key;
+            }
+        }
+    }
+    fn read_event(&mut self) -> InputEvent {
+        // Wait until a key is pressed or the mouse is used and then return it
+        loop {
+            if let Some(event) = self.stdin.next() {
                 // When a keypress was detected
-                self.last_keypress = Some(Instant::now());
-                if let Ok(key) = key {
-                    return key;
+                if let Ok(event) = event {
+                    return event;
                 } else {
                     continue;
                 }
@@ -115,30 +241,124 @@ impl Editor {
                 // Run code that we want to run when the key isn't pressed
                 if self.term.check_resize() {
                     // The terminal has changed in size
-                    if self.doc[self.tab].cursor.y > self.term.size.height.saturating_sub(3) {
+                    self.layout();
+                    if self.doc[self.tab].cursor.y > self.view.height.saturating_sub(3) {
                         // Prevent cursor going off the screen and breaking everything
-                        self.doc[self.tab].cursor.y = self.term.size.height.saturating_sub(3);
+                        self.doc[self.tab].cursor.y = self.view.height.saturating_sub(3);
                     }
                     // Re-render everything to the new size
                     self.update();
                 }
-                // Check for a period of inactivity
-                if let Some(time) = self.last_keypress {
-                    // Check to see if it's over the config undo period
-                    if time.elapsed().as_secs() >= self.config.general.undo_period {
-                        // Commit the undo changes to the stack
-                        self.doc[self.tab].undo_stack.commit();
-                        self.last_keypress = None;
+                // Check for changes to the config file
+                if let Ok((config, status)) = self.reloads.try_recv() {
+                    self.reload_config(config, status);
+                    self.update();
+                }
+                // Back up unsaved changes every so often
+                if let Some(interval) = self.config.general.autosave_interval {
+                    if self.last_backup.elapsed().as_secs() >= interval.max(1) {
+                        self.backup_documents();
                     }
                 }
+                // Notice files that other programs have changed, unless a prompt is open
+                if self.idle && self.last_disk_check.elapsed() >= DISK_CHECK_INTERVAL {
+                    self.idle = false;
+                    self.check_disk();
+                    self.idle = true;
+                }
                 // FPS cap to stop using the entire CPU
                 thread::sleep(Duration::from_millis(16));
             }
         }
     }
+    fn backup_documents(&mut self) {
+        // Write the documents with unsaved changes to their backup files
+        for doc in &self.doc {
+            if doc.dirty {
+                let _ = doc.backup();
+            }
+        }
+        self.last_backup = Instant::now();
+    }
+    fn offer_recovery(&mut self, index: usize) {
+        // Ask to bring back changes that were backed up but never saved
+        let Some(backup) = self.doc[index].backup_path() else {
+            return;
+        };
+        if !Path::new(&backup).is_file() {
+            return;
+        }
+        self.tab = index;
+        let name = self.doc[index].name.clone();
+        self.doc[index].set_command_line(
+            format!("Unsaved changes to {name} were found in {backup}, Enter to recover them"),
+            Type::Warning,
+        );
+        self.update();
+        if let Key::Char('\n' | 'y') = self.read_key() {
+            if self.doc[index].recover(&self.view) {
+                self.doc[index].set_command_line(
+                    format!("Recovered unsaved changes from {backup}"),
+                    Type::Info,
+                );
+                return;
+            }
+        }
+        self.doc[index].set_command_line(format!("Left {name} as it was saved"), Type::Info);
+    }
+    fn check_disk(&mut self) {
+        // Ask what to do about each open file that was changed by another program
+        for index in 0..self.doc.len() {
+            if let Some(modified) = self.doc[index].changed_on_disk() {
+                self.offer_reload(index, modified);
+            }
+        }
+        self.last_disk_check = Instant::now();
+    }
+    fn offer_reload(&mut self, index: usize, modified: SystemTime) {
+        // Reload a file that changed on disk, or keep what's in the editor
+        self.tab = index;
+        let name = self.doc[index].name.clone();
+        self.doc[index].set_command_line(
+            format!("{name} was changed by another program, [R]eload or [I]gnore"),
+            Type::Warning,
+        );
+        self.update();
+        loop {
+            match self.read_key() {
+                Key::Char('r' | 'R') => {
+                    if self.doc[index].reload(&self.view) {
+                        self.doc[index].set_command_line(format!("Reloaded {name}"), Type::Info);
+                    } else {
+                        self.doc[index]
+                            .set_command_line(format!("{name} couldn't be reloaded"), Type::Error);
+                    }
+                    break;
+                }
+                Key::Char('i' | 'I') | Key::Esc => {
+                    // Don't ask again until the file changes another time
+                    self.doc[index].modified = Some(modified);
+                    self.doc[index].set_command_line(
+                        format!("Kept the version of {name} in the editor"),
+                        Type::Info,
+                    );
+                    break;
+                }
+                _ => (),
+            }
+        }
+        self.update();
+    }
     fn process_input(&mut self) {
         // Read a key and act on it
-        let key = self.read_key();
+        self.idle = true;
+        let event = self.read_event();
+        self.idle = false;
+        let key = match event {
+            InputEvent::Key(key) => key,
+            InputEvent::Mouse(event) => return self.mouse(event),
+            InputEvent::Unsupported(_) => return,
+        };
         self.doc[self.tab].show_welcome = false;
         let cursor = self.doc[self.tab].cursor;
         let offset = self.doc[self.tab].offset;
@@ -146,29 +366,41 @@ impl Editor {
             x: cursor.x + offset.x,
             y: cursor.y + offset.y - OFFSET,
         };
+        if self.column_key(key) {
+            return;
+        }
         let config = &self.config;
         match key {
             Key::Char(c) => {
-                self.doc[self.tab].redo_stack.empty();
                 match c {
                     '\n' => {
                         if current.x == 0 {
                             // Return key pressed at the start of the line
                             self.execute(Event::InsertLineAbove(current), false);
-                        } else if current.x == self.doc[self.tab].rows[current.y].length() {
-                            // Return key pressed at the end of the line
-                            self.execute(Event::InsertLineBelow(current), false);
-                            self.execute(Event::MoveCursor(1, Direction::Down), false);
-                            self.doc[self.tab].recalculate_graphemes();
                         } else {
-                            // Return key pressed in the middle of the line
-                            self.execute(Event::SplitDown(current, current), false);
+                            if current.x == self.doc[self.tab].rows[current.y].length() {
+                                // Return key pressed at the end of the line
+                                self.execute(Event::InsertLineBelow(current), false);
+                                self.execute(Event::MoveCursor(1, Direction::Down), false);
+                                self.doc[self.tab].recalculate_graphemes();
+                            } else {
+                                // Return key pressed in the middle of the line
+                                self.execute(Event::SplitDown(current, current), false);
+                            }
+                            if self.config.general.auto_indent {
+                                self.doc[self.tab].auto_indent(current.y + 1, &self.view);
+                            }
                         }
                     }
                     '\t' => {
                         // The user pressed the tab key
                         self.execute(Event::InsertTab(current), false);
                     }
+                    '}' | ')' if self.config.general.auto_indent => {
+                        // Closing brackets at the start of a line take off a level
+                        let current = self.doc[self.tab].dedent_closer(&current, &self.view);
+                        self.execute(Event::Insertion(current, c), false);
+                    }
                     _ => {
                         // Other characters
                         self.execute(Event::Insertion(current, c), false);
@@ -176,24 +408,8 @@ impl Editor {
                 }
             }
             Key::Backspace => {
-                self.doc[self.tab].redo_stack.empty();
-                self.execute(
-                    if current.x == 0 {
-                        // Backspace at the start of a line
-                        Event::SpliceUp(current, current)
-                    } else {
-                        // Backspace in the middle of a line
-                        let row = self.doc[self.tab].rows[current.y].clone();
-                        let boundaries = row.boundaries();
-                        let chars = row.string.chars();
-                        let current = Position {
-                            x: current.x.saturating_sub(1),
-                            y: current.y,
-                        };
-                        Event::Deletion(current, chars.collect::<Vec<_>>()[boundaries[current.x]])
-                    },
-                    false,
-                );
+                let event = self.backspace(current);
+                self.execute(event, false);
             }
             // Detect control key binding
             Key::Ctrl(c) => {
@@ -222,6 +438,93 @@ impl Editor {
             _ => (),
         }
     }
+    fn column_key(&mut self, key: Key) -> bool {
+        // Typing in a column selection changes every line of it
+        let column = self.doc[self.tab].column_selection();
+        let doc = &mut self.doc[self.tab];
+        match (key, column) {
+            (Key::Char(c), Some(column)) if !c.is_control() => {
+                doc.apply_column_insert(&column, &c.to_string(), &self.view);
+                if c == ' ' {
+                    doc.undo_stack.commit();
+                }
+            }
+            (Key::Backspace, Some(column)) => doc.column_backspace(&column, &self.view),
+            _ => return false,
+        }
+        true
+    }
+    fn backspace(&self, current: Position) -> Event {
+        // Work out what the backspace key removes at the cursor
+        if current.x == 0 {
+            // Backspace at the start of a line
+            Event::SpliceUp(current, current)
+        } else if let Some(start) = self.doc[self.tab].soft_tab_before(&current) {
+            // Backspace after indentation takes off a whole level
+            Event::DeleteTab(start)
+        } else {
+            // Backspace in the middle of a line
+            let row = &self.doc[self.tab].rows[current.y];
+            let boundaries = row.boundaries();
+            let chars = row.string.chars();
+            let current = Position {
+                x: current.x.saturating_sub(1),
+                y: current.y,
+            };
+            Event::Deletion(current, chars.collect::<Vec<_>>()[boundaries[current.x]])
+        }
+    }
+    fn read_config(paths: &[String]) -> (Reader, Status) {
+        // Read every config layer and report on the most important status
+        let paths: Vec<&str> = paths.iter().map(String::as_str).collect();
+        let (config, statuses) = Reader::read_layered(&paths);
+        (config, Status::summarise(statuses))
+    }
+    fn config_problems(config: &Reader, status: &Status) -> Vec<String> {
+        // List the warnings from reading the config along with any values that don't make sense
+        let mut result = vec![];
+        if let Status::Warning(warnings) = status {
+            result.extend(warnings.iter().cloned());
+        }
+        result.extend(config.validate());
+        result
+    }
+    fn reload_config(&mut self, config: Reader, status: Status) {
+        // Switch to a reloaded config, keeping the old config if one is broken
+        match status {
+            Status::Success(_) | Status::Warning(_) => {
+                // Keep the current theme unless it no longer exists
+                if !config.highlights.contains_key(&self.theme) {
+                    self.theme = config.theme.default_theme.clone();
+                }
+                self.config = config;
+                self.status = status;
+                self.term.set_cursor_colour(self.config.theme.cursor_color);
+                self.term.set_mouse(self.config.general.mouse_support);
+                // Bring the highlighting and settings of every document up to date
+                for doc in &mut self.doc {
+                    let first_line = doc.rows.first().map_or("", |row| row.string.as_str());
+                    let mime = doc.mime.as_deref();
+                    doc.regex = Reader::get_syntax_regex(&self.config, &doc.path, first_line, mime);
+                    doc.highlighter.invalidate_from(0);
+                    let general = &self.config.general;
+                    doc.undo_stack
+                        .configure(general.max_undo_history, general.undo_period);
+                    doc.use_icons = self.config.general.use_icons;
+                }
+                let problems = Self::config_problems(&self.config, &self.status);
+                if problems.is_empty() {
+                    self.doc[self.tab]
+                        .set_command_line("Config file reloaded".to_string(), Type::Info);
+                } else {
+                    self.doc[self.tab].set_command_line(problems.join("; "), Type::Warning);
+                }
+            }
+            Status::Parse(error) => self.doc[self.tab]
+                .set_command_line(format!("Config not reloaded, {error}"), Type::Error),
+            Status::File => (),
+        }
+    }
     fn new_document(&mut self) {
         // Create a new document
         self.doc.push(Document::new(&self.config, &self.status));
@@ -243,10 +546,33 @@ impl Editor {
             // Overwrite the current document
             self.doc.push(doc);
             self.tab = self.doc.len().saturating_sub(1);
+            self.offer_recovery(self.tab);
         } else {
             self.doc[self.tab].set_command_line("File couldn't be opened".to_string(), Type::Error);
         }
     }
+    fn find_file(&mut self) {
+        // Pick a file from the current directory by typing part of its name
+        self.finder = Some(FileFinder::new(".", &self.config.general.finder_ignore));
+        let query = self.prompt("Find file", ": ", &|s, e, t| {
+            if let Some(finder) = &mut s.finder {
+                match e {
+                    PromptEvent::KeyPress(Key::Up) => finder.select_previous(),
+                    PromptEvent::KeyPress(Key::Down) => finder.select_next(),
+                    PromptEvent::CharPress => finder.filter(t),
+                    _ => (),
+                }
+            }
+        });
+        let finder = self.finder.take();
+        if query.is_none() {
+            self.doc[self.tab].set_command_line("Find file cancelled".to_string(), Type::Info);
+        } else if let Some(file) = finder.as_ref().and_then(FileFinder::selection) {
+            self.open_document(Some(file.to_string()));
+        } else {
+            self.doc[self.tab].set_command_line("No matching files".to_string(), Type::Warning);
+        }
+    }
     fn save_document(&mut self, file: Option<String>, prompt: bool) {
         // Save the document
         let save = if let Some(file) = file {
@@ -267,20 +593,35 @@ impl Editor {
                 self.doc[self.tab].path.clone()
             }
         };
+        // A .editorconfig next to the file can change what happens on save
+        let general = self.config.apply_editorconfig(&EditorConfig::find(&save));
+        if general.trim_trailing_whitespace {
+            self.doc[self.tab].trim_trailing_whitespace(&self.view, general.trim_cursor_line);
+        }
+        if general.trim_final_newlines {
+            self.doc[self.tab].trim_final_newlines(&self.view);
+        }
+        let added_newline =
+            general.ensure_final_newline && self.doc[self.tab].ensure_final_newline();
         // Attempt document save
-        let tab_width = self.config.general.tab_width;
-        if self.doc[self.tab].save(&save, tab_width).is_ok() {
+        if self.doc[self.tab].save(&save).is_ok() {
             // The document saved successfully
-            let ext = save.split('.').last().unwrap_or(&"");
             self.doc[self.tab].dirty = false;
-            self.doc[self.tab]
-                .set_command_line(format!("File saved to {} successfully", save), Type::Info);
+            self.doc[self.tab].remove_backup();
+            let note = if added_newline {
+                ", added final newline"
+            } else {
+                ""
+            };
+            self.doc[self.tab].set_command_line(
+                format!("File saved to {save} successfully{note}"),
+                Type::Info,
+            );
             // Update the current documents details in case of filetype change
-            self.doc[self.tab].kind = Document::identify(&save).0.to_string();
-            self.doc[self.tab].icon = Document::identify(&save).1.to_string();
             self.doc[self.tab].name = save.clone();
             self.doc[self.tab].path = save.clone();
-            self.doc[self.tab].regex = Reader::get_syntax_regex(&self.config, ext);
+            self.doc[self.tab].record_modified();
+            self.doc[self.tab].identify_language(&self.config);
         } else {
             // The document couldn't save due to permission errors / invalid name
             self.doc[self.tab]
@@ -291,14 +632,25 @@ impl Editor {
     }
     fn save_every_document(&mut self) {
         // Save every document in the editor
-        let tab_width = self.config.general.tab_width;
         let mut successes = 0;
         let mut failiures = 0;
         for i in 0..self.doc.len() {
             let path = self.doc[i].path.clone();
-            if self.doc[i].save(&path, tab_width).is_ok() {
+            let general = self.config.apply_editorconfig(&EditorConfig::find(&path));
+            if general.trim_trailing_whitespace {
+                self.doc[i].trim_trailing_whitespace(&self.view, general.trim_cursor_line);
+            }
+            if general.trim_final_newlines {
+                self.doc[i].trim_final_newlines(&self.view);
+            }
+            if general.ensure_final_newline {
+                self.doc[i].ensure_final_newline();
+            }
+            if self.doc[i].save(&path).is_ok() {
                 // The document saved successfully
                 self.doc[i].dirty = false;
+                self.doc[i].remove_backup();
+                self.doc[i].record_modified();
                 successes += 1;
             } else {
                 // The document couldn't save due to permission errors
@@ -315,66 +667,285 @@ impl Editor {
     fn quit_document(&mut self, force: bool) {
         // For handling a quit event
         if force || self.dirty_prompt('q', "quit") {
+            // Changes that are thrown away don't need recovering
+            self.doc[self.tab].remove_backup();
             if self.doc.len() <= 1 {
                 // Quit Ox
+                self.save_session(&self.session());
                 self.quit = true;
                 return;
             } else if self.tab == self.doc.len().saturating_sub(1) {
                 // Close current tab and move right
                 self.doc.remove(self.tab);
+                self.panes.remove_document(self.tab);
                 self.tab -= 1;
             } else {
                 // Close current tab and move left
                 self.doc.remove(self.tab);
+                self.panes.remove_document(self.tab);
             }
             self.doc[self.tab].set_command_line("Closed tab".to_string(), Type::Info);
         }
     }
     fn quit_all(&mut self, force: bool) {
-        // Quit all the documents in the editor
+        // Quit all the documents in the editor, remembering all of them for the next session
+        let session = self.session();
         self.tab = 0;
         while !self.quit {
             self.execute(Event::Quit(force), false);
         }
+        self.save_session(&session);
     }
     fn next_tab(&mut self) {
-        // Move to the next tab
-        if self.tab.saturating_add(1) < self.doc.len() {
-            self.tab = self.tab.saturating_add(1);
-        }
+        // Move to the next tab, going round to the first after the last
+        self.tab = (self.tab + 1) % self.doc.len();
     }
     fn prev_tab(&mut self) {
-        // Move to the previous tab
-        self.tab = self.tab.saturating_sub(1);
+        // Move to the previous tab, going round to the last before the first
+        self.tab = self.tab.checked_sub(1).unwrap_or(self.doc.len() - 1);
+    }
+    fn store_pane(&mut self) {
+        // Remember where the focused pane was before leaving it
+        let doc = &self.doc[self.tab];
+        self.panes.panes[self.panes.focus] = Pane {
+            doc: self.tab,
+            cursor: doc.cursor,
+            offset: doc.offset,
+        };
+    }
+    fn load_pane(&mut self) {
+        // Show the document of the focused pane where it was left
+        let pane = self.panes.panes[self.panes.focus];
+        self.tab = pane.doc;
+        self.doc[self.tab].cursor = pane.cursor;
+        self.doc[self.tab].offset = pane.offset;
+        self.layout();
+        // The pane may have shrunk or the document may have got shorter since it was left
+        let doc = &mut self.doc[self.tab];
+        let line = pane.cursor.y + pane.offset.y - OFFSET;
+        let outside = doc.cursor.x + doc.line_offset >= self.view.width
+            || doc.cursor.y > self.view.height.saturating_sub(3);
+        if line >= doc.rows.len() || outside {
+            let y = line.min(doc.rows.len().saturating_sub(1));
+            let x = pane.cursor.x + pane.offset.x;
+            doc.goto(Position { x, y }, &self.view);
+        }
+        doc.snap_cursor(&self.view);
+        doc.recalculate_graphemes();
+    }
+    fn split_pane(&mut self, orientation: Orientation) {
+        // Show the current document in a new pane next to this one
+        self.store_pane();
+        let pane = self.panes.panes[self.panes.focus];
+        match orientation {
+            Orientation::Horizontal => self.panes.split_horizontal(pane),
+            Orientation::Vertical => self.panes.split_vertical(pane),
+        }
+        self.load_pane();
+    }
+    fn close_pane(&mut self) {
+        // Close the focused pane, leaving its document open
+        if self.panes.close_pane(self.panes.focus) {
+            self.load_pane();
+        } else {
+            self.doc[self.tab]
+                .set_command_line("The last pane can't be closed".to_string(), Type::Warning);
+        }
+    }
+    fn next_pane(&mut self) {
+        // Move to the next pane
+        self.store_pane();
+        self.panes.focus_next();
+        self.load_pane();
+    }
+    fn mouse(&mut self, event: MouseEvent) {
+        // Move the cursor to a click, select by dragging and scroll with the wheel
+        match event {
+            MouseEvent::Press(MouseButton::Left, x, y) => {
+                if !self.focus_at(x, y) {
+                    return;
+                }
+                let pos = self.position_at(x, y);
+                self.doc[self.tab].selection = None;
+                self.place_cursor(pos);
+            }
+            MouseEvent::Hold(x, y) => {
+                let pos = self.position_at(x, y);
+                let doc = &mut self.doc[self.tab];
+                if doc.selection.is_none() {
+                    doc.selection = Some(Position {
+                        x: doc.cursor.x + doc.offset.x,
+                        y: doc.cursor.y + doc.offset.y - OFFSET,
+                    });
+                    doc.selection_mode = SelectionMode::Normal;
+                }
+                self.place_cursor(pos);
+            }
+            MouseEvent::Press(MouseButton::WheelUp, x, y) => {
+                self.focus_at(x, y);
+                self.scroll(-SCROLL_LINES);
+            }
+            MouseEvent::Press(MouseButton::WheelDown, x, y) => {
+                self.focus_at(x, y);
+                self.scroll(SCROLL_LINES);
+            }
+            _ => (),
+        }
+    }
+    fn focus_at(&mut self, x: u16, y: u16) -> bool {
+        // Focus the pane at a point of the screen, false if there is no text there
+        let (x, y) = (
+            usize::from(x).saturating_sub(1),
+            usize::from(y).saturating_sub(1),
+        );
+        let height = self.term.size.height.saturating_sub(3);
+        let areas = self.panes.areas(self.term.size.width, height);
+        let Some(idx) = areas.iter().position(|area| {
+            (area.x..area.x + area.width).contains(&x)
+                && (area.y + OFFSET..area.y + OFFSET + area.height).contains(&y)
+        }) else {
+            return false;
+        };
+        if idx != self.panes.focus {
+            self.store_pane();
+            self.panes.focus = idx;
+            self.load_pane();
+        }
+        true
+    }
+    fn position_at(&self, x: u16, y: u16) -> Position {
+        // Find the place in the document under a point of the focused pane
+        let doc = &self.doc[self.tab];
+        let row = (usize::from(y).saturating_sub(1 + OFFSET + self.area.y))
+            .min(self.area.height.saturating_sub(1));
+        let column = (usize::from(x).saturating_sub(1 + self.area.x))
+            .min(self.area.width.saturating_sub(1))
+            .saturating_sub(doc.line_offset);
+        let (line, column) = if self.config.general.wrap_lines {
+            let rows = self.screen_rows();
+            match rows.get(row).or_else(|| rows.last()) {
+                Some(&(line, part)) => (line, part * self.wrap_width() + column),
+                None => (0, 0),
+            }
+        } else {
+            (doc.offset.y + row, doc.offset.x + column)
+        };
+        // Clicks past the end of the document or a line go to the end of it
+        let line = line.min(doc.rows.len().saturating_sub(1));
+        Position {
+            x: column.min(doc.rows[line].length()),
+            y: line,
+        }
+    }
+    fn place_cursor(&mut self, pos: Position) {
+        // Move the cursor to a place that is already on the screen
+        let doc = &mut self.doc[self.tab];
+        doc.cursor = Position {
+            x: pos.x.saturating_sub(doc.offset.x),
+            y: pos.y.saturating_sub(doc.offset.y) + OFFSET,
+        };
+        doc.prevent_unicode_hell();
+        doc.recalculate_graphemes();
+    }
+    fn scroll(&mut self, lines: isize) {
+        // Scroll the view, taking the cursor along when it would go off the screen
+        let height = self.view.height.saturating_sub(3);
+        let margin = self
+            .config
+            .general
+            .scroll_off
+            .min(height.saturating_sub(1) / 2);
+        let doc = &mut self.doc[self.tab];
+        let line = doc.cursor.y + doc.offset.y - OFFSET;
+        let last = doc.rows.len().saturating_sub(height);
+        let offset = doc.offset.y.saturating_add_signed(lines).min(last);
+        // Keep the cursor away from the edges so the view doesn't scroll back
+        let top = if offset == 0 { 0 } else { offset + margin };
+        let bottom = if offset + height >= doc.rows.len() {
+            doc.rows.len().saturating_sub(1)
+        } else {
+            (offset + height).saturating_sub(margin + 1)
+        };
+        let line = line.clamp(top.min(bottom), bottom);
+        doc.offset.y = offset;
+        doc.cursor.y = line - offset + OFFSET;
+        doc.snap_cursor(&self.view);
+        doc.prevent_unicode_hell();
+        doc.recalculate_graphemes();
+    }
+    fn switch_document(&mut self) {
+        // Pick one of the open documents from a list
+        let mut selected = self.tab;
+        self.doc[self.tab].set_command_line(
+            "Switch document: arrows or Tab to pick, Return to open, Esc to cancel".to_string(),
+            Type::Info,
+        );
+        loop {
+            self.switcher = Some(selected);
+            self.update();
+            match self.read_key() {
+                Key::Up | Key::BackTab => {
+                    selected = selected.checked_sub(1).unwrap_or(self.doc.len() - 1);
+                }
+                Key::Down | Key::Char('\t') => selected = (selected + 1) % self.doc.len(),
+                Key::Char('\n') => {
+                    self.tab = selected;
+                    break;
+                }
+                Key::Esc => break,
+                _ => (),
+            }
+        }
+        self.switcher = None;
+        let name = self.format(self.tab, "%t %f");
+        self.doc[self.tab].set_command_line(format!("Switched to {name}"), Type::Info);
+    }
+    fn format(&self, num: usize, template: &str) -> String {
+        // Fill in a status or tab template for one of the open documents
+        let position = format!("[{}/{}]", num + 1, self.doc.len());
+        self.doc[num].format(&template.replace("%t", &position))
     }
     pub fn execute(&mut self, event: Event, reversed: bool) {
         // Event executor
         match event {
             Event::New => self.new_document(),
             Event::Open(file) => self.open_document(file),
+            Event::Find => self.find_file(),
             Event::Save(file, prompt) => self.save_document(file, prompt),
             Event::SaveAll => self.save_every_document(),
             Event::Quit(force) => self.quit_document(force),
             Event::QuitAll(force) => self.quit_all(force),
             Event::NextTab => self.next_tab(),
             Event::PrevTab => self.prev_tab(),
+            Event::Switch => self.switch_document(),
+            Event::SplitPane(orientation) => self.split_pane(orientation),
+            Event::ClosePane => self.close_pane(),
+            Event::NextPane => self.next_pane(),
             Event::Search => self.search(),
             Event::Replace => self.replace(),
             Event::ReplaceAll => self.replace_all(),
             Event::Cmd => self.cmd(),
-            Event::Theme(name) => {
-                self.theme = name;
-                self.update();
-            }
+            Event::GotoLine => self.goto_line(),
+            Event::Select(mode) => self.doc[self.tab].toggle_selection(mode),
+            Event::Comment => self.doc[self.tab].toggle_comment(&self.view),
+            Event::Copy => self.copy(false),
+            Event::Cut => self.copy(true),
+            Event::Paste => self.paste(),
+            Event::MoveLines(direction) => self.move_lines(direction),
+            Event::Sort(length, descending) => self.sort(length, descending),
+            Event::ConvertCase(mode) => self.convert_case(mode),
+            Event::Duplicate => self.doc[self.tab].duplicate(&self.view),
+            Event::Theme(name) => self.switch_theme(name),
             Event::MoveWord(direction) => match direction {
-                Direction::Left => self.doc[self.tab].word_left(&self.term.size),
-                Direction::Right => self.doc[self.tab].word_right(&self.term.size),
-                _ => {},
+                Direction::Left => self.doc[self.tab].word_left(&self.view),
+                Direction::Right => self.doc[self.tab].word_right(&self.view),
+                _ => {}
             },
+            Event::DeleteWord(direction) => self.delete_word(direction),
             Event::GotoCursor(pos) => {
                 let rows = &self.doc[self.tab].rows;
                 if rows.len() > pos.y && rows[pos.y].length() >= pos.x {
-                    self.doc[self.tab].goto(pos, &self.term.size);
+                    self.doc[self.tab].goto(pos, &self.view);
                 }
             }
             Event::MoveCursor(magnitude, direction) => {
@@ -386,55 +957,114 @@ impl Editor {
                             Direction::Left => Key::Left,
                             Direction::Right => Key::Right,
                         },
-                        &self.term.size,
+                        &self.view,
                     );
                 }
             }
             Event::Commit => self.doc[self.tab].undo_stack.commit(),
-            Event::Store(kind, bank) => {
-                let cursor = self.doc[self.tab].cursor;
-                let offset = self.doc[self.tab].offset;
-                let current = Position {
-                    x: cursor.x + offset.x,
-                    y: cursor.y + offset.y - OFFSET,
-                };
-                match kind {
-                    BankType::Cursor => {
-                        self.position_bank.insert(bank, current);
-                    }
-                    BankType::Line => {
-                        self.row_bank
-                            .insert(bank, self.doc[self.tab].rows[current.y].clone());
-                    }
-                }
+            Event::Store(kind, bank) => self.store(kind, bank),
+            Event::Load(kind, bank) => self.load(kind, bank),
+            Event::Home => self.doc[self.tab].leap_cursor(Key::Home, &self.view),
+            Event::End => self.doc[self.tab].leap_cursor(Key::End, &self.view),
+            Event::PageUp => self.doc[self.tab].leap_cursor(Key::PageUp, &self.view),
+            Event::PageDown => self.doc[self.tab].leap_cursor(Key::PageDown, &self.view),
+            Event::Undo => self.undo(),
+            Event::Redo => self.redo(None),
+            Event::RedoBranch(None) => self.redo_branch(),
+            Event::RedoBranch(branch) => self.redo(branch),
+            // Event is a document event, send to current document
+            _ => self.doc[self.tab].execute(event, reversed, &self.view),
+        }
+    }
+    fn switch_theme(&mut self, name: String) {
+        // Switch the colours, the syntax highlights, or both if they share the name
+        let highlights = self.config.highlights.contains_key(&name);
+        match self.config.set_theme(&name) {
+            Ok(()) => self.term.set_cursor_colour(self.config.theme.cursor_color),
+            Err(error) if !highlights => {
+                self.doc[self.tab].set_command_line(error, Type::Warning);
             }
-            Event::Load(kind, bank) => {
-                let cursor = self.doc[self.tab].cursor;
-                let offset = self.doc[self.tab].offset;
-                let current = Position {
-                    x: cursor.x + offset.x,
-                    y: cursor.y + offset.y - OFFSET,
-                };
-                match kind {
-                    BankType::Cursor => {
-                        let cursor = *self.position_bank.get(&bank).unwrap_or(&current);
-                        self.doc[self.tab].goto(cursor, &self.term.size);
-                    }
-                    BankType::Line => {
-                        if let Some(row) = self.row_bank.get(&bank) {
-                            self.doc[self.tab].rows[current.y] = row.clone();
-                        }
-                    }
+            Err(_) => (),
+        }
+        if highlights {
+            self.theme = name;
+            for doc in &mut self.doc {
+                doc.highlighter.invalidate_from(0);
+            }
+        }
+        self.update();
+    }
+    fn store(&mut self, kind: BankType, bank: usize) {
+        // Keep the cursor position or the current line in a bank
+        let cursor = self.doc[self.tab].cursor;
+        let offset = self.doc[self.tab].offset;
+        let current = Position {
+            x: cursor.x + offset.x,
+            y: cursor.y + offset.y - OFFSET,
+        };
+        match kind {
+            BankType::Cursor => {
+                self.position_bank.insert(bank, current);
+            }
+            BankType::Line => {
+                self.row_bank
+                    .insert(bank, self.doc[self.tab].rows[current.y].clone());
+            }
+        }
+    }
+    fn load(&mut self, kind: BankType, bank: usize) {
+        // Go back to a stored cursor position or put back a stored line
+        let cursor = self.doc[self.tab].cursor;
+        let offset = self.doc[self.tab].offset;
+        let current = Position {
+            x: cursor.x + offset.x,
+            y: cursor.y + offset.y - OFFSET,
+        };
+        match kind {
+            BankType::Cursor => {
+                let cursor = *self.position_bank.get(&bank).unwrap_or(&current);
+                self.doc[self.tab].goto(cursor, &self.view);
+            }
+            BankType::Line => {
+                if let Some(row) = self.row_bank.get(&bank) {
+                    self.doc[self.tab].rows[current.y] = row.clone();
                 }
             }
-            Event::Home => self.doc[self.tab].leap_cursor(Key::Home, &self.term.size),
-            Event::End => self.doc[self.tab].leap_cursor(Key::End, &self.term.size),
-            Event::PageUp => self.doc[self.tab].leap_cursor(Key::PageUp, &self.term.size),
-            Event::PageDown => self.doc[self.tab].leap_cursor(Key::PageDown, &self.term.size),
-            Event::Undo => self.undo(),
-            Event::Redo => self.redo(),
-            // Event is a document event, send to current document
-            _ => self.doc[self.tab].execute(event, reversed, &self.term.size, &self.config),
+        }
+    }
+    fn move_lines(&mut self, direction: Direction) {
+        // Move the selected lines, or the cursor's line, up or down past the next one
+        let lines = self.doc[self.tab].selected_lines();
+        match direction {
+            Direction::Up => self.doc[self.tab].move_lines_up(lines, &self.view),
+            Direction::Down => self.doc[self.tab].move_lines_down(lines, &self.view),
+            _ => false,
+        };
+    }
+    fn sort(&mut self, length: bool, descending: bool) {
+        // Sort the selected lines, or the whole document when nothing is selected
+        let doc = &mut self.doc[self.tab];
+        let lines = if doc.selection.is_some() {
+            doc.selected_lines()
+        } else {
+            0..doc.rows.len()
+        };
+        if length {
+            doc.sort_lines_by_length(lines, descending, &self.view);
+        } else {
+            doc.sort_lines(lines, descending, &self.view);
+        }
+    }
+    fn convert_case(&mut self, mode: CaseMode) {
+        // Change the case of the selection, or the word under the cursor
+        self.doc[self.tab].convert_case(mode, &self.view);
+    }
+    fn delete_word(&mut self, direction: Direction) {
+        // Delete from the cursor to the start or end of a word
+        match direction {
+            Direction::Left => self.doc[self.tab].delete_word_left(&self.view),
+            Direction::Right => self.doc[self.tab].delete_word_right(&self.view),
+            _ => {}
         }
     }
     fn cmd(&mut self) {
@@ -491,20 +1121,6 @@ impl Editor {
                 // Execute the instruction
                 if let Some(instruct) = instruction {
                     for i in instruct {
-                        match i {
-                            Event::SpliceUp(_, _)
-                                | Event::SplitDown(_, _)
-                                | Event::InsertLineAbove(_)
-                                | Event::InsertLineBelow(_)
-                                | Event::Deletion(_, _)
-                                | Event::Insertion(_, _)
-                                | Event::InsertTab(_)
-                                | Event::DeleteTab(_)
-                                | Event::DeleteLine(_, _, _)
-                                | Event::UpdateLine(_, _, _, _)
-                                | Event::Overwrite(_, _) => self.doc[self.tab].redo_stack.empty(),
-                            _ => (),
-                        }
                         self.execute(i, false);
                     }
                     self.doc[self.tab].undo_stack.commit();
@@ -512,10 +1128,31 @@ impl Editor {
             }
         }
     }
+    fn copy(&mut self, cut: bool) {
+        // Copy the selection, or the current line, and remove it as well when cutting
+        let text = self.doc[self.tab].copy_text();
+        if let Err(error) = self.clipboard.copy(&text) {
+            self.doc[self.tab].set_command_line(format!("Couldn't copy, {error}"), Type::Error);
+        } else if cut {
+            self.doc[self.tab].cut_text(&self.view);
+            self.doc[self.tab].set_command_line("Cut".to_string(), Type::Info);
+        } else {
+            self.doc[self.tab].set_command_line("Copied".to_string(), Type::Info);
+        }
+    }
+    fn paste(&mut self) {
+        // Insert the text in the clipboard at the cursor
+        match self.clipboard.paste() {
+            Ok(text) => self.doc[self.tab].paste_text(&text, &self.view),
+            Err(error) => {
+                self.doc[self.tab]
+                    .set_command_line(format!("Couldn't paste, {error}"), Type::Error);
+            }
+        }
+    }
     pub fn undo(&mut self) {
-        self.doc[self.tab].undo_stack.commit();
-        if let Some(events) = self.doc[self.tab].undo_stack.pop() {
-            for event in events.clone() {
+        if let Some(events) = self.doc[self.tab].undo_stack.undo() {
+            for event in events {
                 if let Some(reversed) = reverse(event, self.doc[self.tab].rows.len()) {
                     for i in reversed {
                         self.execute(i, true);
@@ -523,247 +1160,279 @@ impl Editor {
                     self.update();
                 }
             }
-            self.doc[self.tab]
-                .redo_stack
-                .append(events.into_iter().rev().collect());
         } else {
             self.doc[self.tab].set_command_line("Empty Undo Stack".to_string(), Type::Error);
         }
     }
-    pub fn redo(&mut self) {
-        if let Some(events) = self.doc[self.tab].redo_stack.pop() {
-            for event in events {
+    pub fn redo(&mut self, branch: Option<usize>) {
+        if let Some(events) = self.doc[self.tab].undo_stack.redo(branch) {
+            // The events are already in the history, so don't record them again
+            self.doc[self.tab].undo_stack.replay(true);
+            for event in events.into_iter().rev() {
                 self.execute(event, false);
                 self.update();
             }
+            self.doc[self.tab].undo_stack.replay(false);
         } else {
             self.doc[self.tab].set_command_line("Empty Redo Stack".to_string(), Type::Error);
         }
     }
+    fn redo_branch(&mut self) {
+        // Let the user pick which branch of the undo history to redo
+        let branches = self.doc[self.tab].undo_stack.branches();
+        if branches == 0 {
+            self.doc[self.tab].set_command_line("Empty Redo Stack".to_string(), Type::Error);
+            return;
+        }
+        let prefix = format!("Redo branch (1-{branches}, newest is {branches})");
+        if let Some(branch) = self.prompt(&prefix, ": ", &|_, _, _| {}) {
+            match branch.parse::<usize>() {
+                Ok(branch @ 1..) if branch <= branches => self.redo(Some(branch - 1)),
+                _ => self.doc[self.tab]
+                    .set_command_line(format!("There is no branch {branch}"), Type::Error),
+            }
+        }
+    }
+    fn goto_line(&mut self) {
+        // Go to a line number, or a number of lines up or down with - or +
+        let lines = self.doc[self.tab].rows.len();
+        if let Some(line) = self.prompt(
+            &format!("Go to line (1-{lines}, +N or -N)"),
+            ": ",
+            &|_, _, _| {},
+        ) {
+            let doc = &mut self.doc[self.tab];
+            let current = doc.cursor.y + doc.offset.y - OFFSET;
+            let line = line.trim();
+            let target = if let Some(down) = line.strip_prefix('+') {
+                down.parse().map(|down| current.saturating_add(down))
+            } else if let Some(up) = line.strip_prefix('-') {
+                up.parse().map(|up| current.saturating_sub(up))
+            } else {
+                line.parse().map(|line: usize| line.saturating_sub(1))
+            };
+            if let Ok(y) = target {
+                // Numbers past the end go to the last line
+                let y = y.min(lines - 1);
+                doc.goto(Position { x: 0, y }, &self.view);
+                doc.recalculate_graphemes();
+            } else {
+                doc.set_command_line(format!("`{line}` isn't a line number"), Type::Error);
+            }
+        }
+    }
     fn search(&mut self) {
-        // For searching the file
+        // For searching the file, Alt + R, C and W toggle the options like in replace
         let initial_cursor = self.doc[self.tab].cursor;
         let initial_offset = self.doc[self.tab].offset;
+        let initial = Position {
+            x: initial_cursor.x + initial_offset.x,
+            y: initial_cursor.y + initial_offset.y - OFFSET,
+        };
+        let label = |s: &Self| format!("Search ({})", s.search_options.describe());
         // Ask for a search term after saving the current cursor position
-        self.prompt("Search", ": ", &|s, e, t| {
-            // Find all occurances in the document
-            let search_points = s.doc[s.tab].scan(t, OFFSET);
-            let cursor = s.doc[s.tab].cursor;
-            let offset = s.doc[s.tab].offset;
+        self.prompt(&label(self), ": ", &|s, e, t| {
+            if let PromptEvent::KeyPress(Key::Alt(k)) = e {
+                s.search_options.toggle(k);
+            }
+            // Highlight every match of the search so far
+            let options = SearchReplace {
+                pattern: t.to_string(),
+                ..s.search_options.clone()
+            };
+            let engine = options.engine().ok().filter(|_| !t.is_empty());
+            let search_points = engine
+                .as_ref()
+                .map(|e| s.search_points(e))
+                .unwrap_or_default();
+            s.doc[s.tab].search = engine.map(|e| e.regex().clone());
             match e {
-                PromptEvent::KeyPress(k) => match k {
-                    Key::Left | Key::Up => {
-                        // User wants to search backwards
-                        for p in search_points.iter().rev() {
-                            if is_behind(
-                                &Position {
-                                    x: cursor.x + offset.x,
-                                    y: cursor.y + offset.y,
-                                },
-                                &p,
-                            ) {
-                                s.doc[s.tab].goto(*p, &s.term.size);
-                                s.doc[s.tab].recalculate_graphemes();
-                                break;
-                            }
-                        }
-                    }
-                    Key::Right | Key::Down => {
-                        // User wants to search forwards
-                        for p in search_points {
-                            if is_ahead(
-                                &Position {
-                                    x: cursor.x + offset.x,
-                                    y: cursor.y + offset.y,
-                                },
-                                &p,
-                            ) {
-                                s.doc[s.tab].goto(p, &s.term.size);
-                                s.doc[s.tab].recalculate_graphemes();
-                                break;
-                            }
-                        }
-                    }
-                    Key::Esc => {
-                        // Restore cursor and offset position
-                        s.doc[s.tab].cursor = initial_cursor;
-                        s.doc[s.tab].offset = initial_offset;
-                        s.doc[s.tab].recalculate_graphemes();
-                    }
-                    _ => (),
-                },
-                PromptEvent::CharPress => {
-                    // When the user is typing the search query
+                PromptEvent::KeyPress(Key::Left | Key::Up) => {
+                    // User wants to search backwards
+                    s.goto_match(&search_points, false);
+                }
+                PromptEvent::KeyPress(Key::Right | Key::Down) => {
+                    // User wants to search forwards
+                    s.goto_match(&search_points, true);
+                }
+                PromptEvent::KeyPress(Key::Esc) => {
+                    // Restore cursor and offset position
                     s.doc[s.tab].cursor = initial_cursor;
                     s.doc[s.tab].offset = initial_offset;
-                    if t != "" {
-                        // Search forward as the user searches
-                        for p in search_points {
-                            if is_ahead(
-                                &Position {
-                                    x: cursor.x + offset.x,
-                                    y: cursor.y + offset.y,
-                                },
-                                &p,
-                            ) {
-                                s.doc[s.tab].goto(p, &s.term.size);
-                                s.doc[s.tab].recalculate_graphemes();
-                                break;
-                            }
-                        }
-                    }
+                    s.doc[s.tab].recalculate_graphemes();
                 }
-                PromptEvent::Update => (),
+                PromptEvent::KeyPress(Key::Alt(_)) | PromptEvent::CharPress => {
+                    // Search forward from where the search started as the query changes
+                    s.doc[s.tab].cursor = initial_cursor;
+                    s.doc[s.tab].offset = initial_offset;
+                    s.goto_match_from(&search_points, initial);
+                }
+                PromptEvent::Update => {
+                    let line = format!("{}: {}", label(s), t);
+                    s.doc[s.tab].set_command_line(line, Type::Info);
+                }
+                PromptEvent::KeyPress(_) => (),
             }
         });
         // User cancelled or found what they were looking for
+        self.doc[self.tab].search = None;
         self.doc[self.tab].set_command_line("Search exited".to_string(), Type::Info);
     }
+    fn replace_prompt(&mut self) -> Option<SearchEngine> {
+        // Ask for what to replace and what with, Alt + R, C and W toggle the options
+        let label = |s: &Self| format!("Replace ({})", s.search_options.describe());
+        let pattern = self.prompt(&label(self), ": ", &|s, e, t| match e {
+            PromptEvent::KeyPress(Key::Alt(k)) => s.search_options.toggle(k),
+            PromptEvent::Update => {
+                // Show the options and highlight what they match so far
+                let options = SearchReplace {
+                    pattern: t.to_string(),
+                    ..s.search_options.clone()
+                };
+                s.doc[s.tab].search = options
+                    .engine()
+                    .ok()
+                    .filter(|_| !t.is_empty())
+                    .map(|e| e.regex().clone());
+                let line = format!("{}: {}", label(s), t);
+                s.doc[s.tab].set_command_line(line, Type::Info);
+            }
+            _ => (),
+        });
+        self.doc[self.tab].search = None;
+        self.search_options.pattern = pattern?;
+        let with = format!("Replace '{}' with", self.search_options.pattern);
+        self.search_options.replacement = self.prompt(&with, ": ", &|_, _, _| {})?;
+        match self.search_options.engine() {
+            Ok(engine) => Some(engine),
+            Err(e) => {
+                let error = e.to_string();
+                let reason = error.lines().last().unwrap_or_default();
+                self.doc[self.tab]
+                    .set_command_line(format!("Invalid pattern: {reason}"), Type::Error);
+                None
+            }
+        }
+    }
+    fn search_points(&self, engine: &SearchEngine) -> Vec<Position> {
+        // Find the start of every match in the current document
+        engine
+            .find_all(&self.doc[self.tab].rows)
+            .into_iter()
+            .map(|(y, x, _)| Position { x, y })
+            .collect()
+    }
+    fn goto_match(&mut self, points: &[Position], forwards: bool) {
+        // Move the cursor to the next or previous match
+        let doc = &self.doc[self.tab];
+        let current = Position {
+            x: doc.cursor.x + doc.offset.x,
+            y: doc.cursor.y + doc.offset.y - OFFSET,
+        };
+        let point = if forwards {
+            points.iter().find(|p| is_ahead(&current, p))
+        } else {
+            points.iter().rev().find(|p| is_behind(&current, p))
+        };
+        if let Some(point) = point {
+            self.doc[self.tab].goto(*point, &self.view);
+            self.doc[self.tab].recalculate_graphemes();
+        }
+    }
+    fn goto_match_from(&mut self, points: &[Position], from: Position) {
+        // Move the cursor to the first match at or after a position
+        if let Some(point) = points.iter().find(|p| (p.y, p.x) >= (from.y, from.x)) {
+            self.doc[self.tab].goto(*point, &self.view);
+            self.doc[self.tab].recalculate_graphemes();
+        }
+    }
     fn replace(&mut self) {
         // Replace text within the document
         let initial_cursor = self.doc[self.tab].cursor;
         let initial_offset = self.doc[self.tab].offset;
         // After saving the cursor position, ask the user for the information
-        if let Some(target) = self.prompt("Replace", ": ", &|_, _, _| {}) {
-            if let Some(arrow) = self.prompt("With", ": ", &|_, _, _| {}) {
-                // Construct a regular expression for searching
-                let re = Regex::new(&target).unwrap();
-                let mut search_points = self.doc[self.tab].scan(&target, OFFSET);
-                // Search forward as the user types
-                for p in &search_points {
-                    if is_ahead(
-                        &Position {
-                            x: self.doc[self.tab].cursor.x + self.doc[self.tab].offset.x,
-                            y: self.doc[self.tab].cursor.y + self.doc[self.tab].offset.y,
-                        },
-                        &p,
-                    ) {
-                        self.doc[self.tab].goto(*p, &self.term.size);
-                        self.doc[self.tab].recalculate_graphemes();
-                        self.update();
-                        break;
-                    }
-                }
-                loop {
-                    // Handle key press events while in replace mode
-                    let key = self.read_key();
-                    match key {
-                        Key::Up | Key::Left => {
-                            // User wishes to search backwards
-                            for p in (&search_points).iter().rev() {
-                                if is_behind(
-                                    &Position {
-                                        x: self.doc[self.tab].cursor.x
-                                            + self.doc[self.tab].offset.x,
-                                        y: self.doc[self.tab].cursor.y
-                                            + self.doc[self.tab].offset.y,
-                                    },
-                                    &p,
-                                ) {
-                                    self.doc[self.tab].goto(*p, &self.term.size);
-                                    self.doc[self.tab].recalculate_graphemes();
-                                    self.update();
-                                    break;
-                                }
-                            }
-                        }
-                        Key::Down | Key::Right => {
-                            // User wishes to search forwards
-                            for p in &search_points {
-                                if is_ahead(
-                                    &Position {
-                                        x: self.doc[self.tab].cursor.x
-                                            + self.doc[self.tab].offset.x,
-                                        y: self.doc[self.tab].cursor.y
-                                            + self.doc[self.tab].offset.y
-                                            - OFFSET,
-                                    },
-                                    &p,
-                                ) {
-                                    self.doc[self.tab].goto(*p, &self.term.size);
-                                    self.doc[self.tab].recalculate_graphemes();
-                                    self.update();
-                                    break;
-                                }
-                            }
-                        }
-                        Key::Char('\n') | Key::Char('y') | Key::Char(' ') => {
-                            let cursor = self.doc[self.tab].cursor;
-                            let offset = self.doc[self.tab].offset;
-                            // Commit current changes to undo stack
-                            self.doc[self.tab].undo_stack.commit();
-                            // Calculate the new line after the replacement
-                            let line = self.doc[self.tab].rows[self.doc[self.tab].cursor.y
-                                + self.doc[self.tab].offset.y
-                                - OFFSET]
-                                .clone();
-                            let before = self.doc[self.tab].rows[self.doc[self.tab].cursor.y
-                                + self.doc[self.tab].offset.y
-                                - OFFSET]
-                                .clone();
-                            let after = Row::from(&*re.replace_all(&line.string[..], &arrow[..]));
-                            // Check there was actually a change
-                            if before.string != after.string {
-                                // Push the replace event to the undo stack
-                                self.doc[self.tab].undo_stack.push(Event::UpdateLine(
-                                    Position {
-                                        x: cursor.x + offset.x,
-                                        y: cursor.y + offset.y - OFFSET,
-                                    },
-                                    0,
-                                    Box::new(before.clone()),
-                                    Box::new(after.clone()),
-                                ));
-                                // TODO: Update relavent lines here
-                                self.doc[self.tab].rows[cursor.y + offset.y - OFFSET] = after;
-                            }
-                            self.update();
-                            self.doc[self.tab].snap_cursor(&self.term.size);
-                            self.doc[self.tab].prevent_unicode_hell();
-                            self.doc[self.tab].recalculate_graphemes();
-                            // Update search locations
-                            search_points = self.doc[self.tab].scan(&target, OFFSET);
+        if let Some(engine) = self.replace_prompt() {
+            self.doc[self.tab].search = Some(engine.regex().clone());
+            let mut search_points = self.search_points(&engine);
+            // Start at the first match from the cursor onwards
+            let doc = &self.doc[self.tab];
+            let cursor = Position {
+                x: doc.cursor.x + doc.offset.x,
+                y: doc.cursor.y + doc.offset.y - OFFSET,
+            };
+            self.goto_match_from(&search_points, cursor);
+            self.doc[self.tab].set_command_line(
+                "Enter to replace, arrows to move, Esc to finish".to_string(),
+                Type::Info,
+            );
+            self.update();
+            loop {
+                // Handle key press events while in replace mode
+                match self.read_key() {
+                    Key::Up | Key::Left => self.goto_match(&search_points, false),
+                    Key::Down | Key::Right => self.goto_match(&search_points, true),
+                    Key::Char('\n' | 'y' | ' ') => {
+                        let doc = &mut self.doc[self.tab];
+                        let pos = Position {
+                            x: doc.cursor.x + doc.offset.x,
+                            y: doc.cursor.y + doc.offset.y - OFFSET,
+                        };
+                        // Only the match under the cursor is replaced
+                        if let Some((after, end)) = engine.replace_one(&doc.rows[pos.y], pos.x) {
+                            let before = doc.rows[pos.y].clone();
+                            doc.undo_stack.commit();
+                            doc.undo_stack.push(Event::UpdateLine(
+                                pos,
+                                0,
+                                Box::new(before.clone()),
+                                Box::new(after.clone()),
+                            ));
+                            doc.rows[pos.y] = after;
+                            doc.dirty = true;
+                            doc.prevent_unicode_hell();
+                            doc.recalculate_graphemes();
+                            // Carry on from the end of the replaced text
+                            search_points = self.search_points(&engine);
+                            self.goto_match_from(&search_points, Position { x: end, y: pos.y });
                         }
-                        Key::Esc => break,
-                        _ => (),
                     }
+                    Key::Esc => break,
+                    _ => (),
                 }
-                // Restore cursor position and exit
-                self.doc[self.tab].cursor = initial_cursor;
-                self.doc[self.tab].offset = initial_offset;
-                self.doc[self.tab].set_command_line("Replace finished".to_string(), Type::Info);
+                self.update();
             }
+            // Restore cursor position and exit
+            self.doc[self.tab].cursor = initial_cursor;
+            self.doc[self.tab].offset = initial_offset;
+            self.doc[self.tab].snap_cursor(&self.view);
+            self.doc[self.tab].recalculate_graphemes();
+            self.doc[self.tab].search = None;
+            self.doc[self.tab].set_command_line("Replace finished".to_string(), Type::Info);
         }
     }
     fn replace_all(&mut self) {
         // Replace all occurances of a substring
-        if let Some(target) = self.prompt("Replace", ": ", &|_, _, _| {}) {
-            if let Some(arrow) = self.prompt("With", ": ", &|_, _, _| {}) {
-                // Commit undo stack changes
-                self.doc[self.tab].undo_stack.commit();
-                let re = Regex::new(&target).unwrap();
-                let lines = self.doc[self.tab].rows.clone();
-                // Replace every occurance
-                for (c, line) in lines.iter().enumerate() {
-                    let before = self.doc[self.tab].rows[c].clone();
-                    let after = Row::from(&*re.replace_all(&line.string[..], &arrow[..]));
-                    if before.string != after.string {
-                        self.doc[self.tab].undo_stack.push(Event::UpdateLine(
-                            Position { x: 0, y: c },
-                            0,
-                            Box::new(before.clone()),
-                            Box::new(after.clone()),
-                        ));
-                        // TODO: Update relavent lines here
-                        self.doc[self.tab].rows[c] = after;
-                    }
-                }
+        if let Some(engine) = self.replace_prompt() {
+            // Commit undo stack changes
+            self.doc[self.tab].undo_stack.commit();
+            let (changed, count) = engine.replace_all(&self.doc[self.tab].rows);
+            for (y, after) in changed {
+                let before = std::mem::replace(&mut self.doc[self.tab].rows[y], after.clone());
+                self.doc[self.tab].undo_stack.push(Event::UpdateLine(
+                    Position { x: 0, y },
+                    0,
+                    Box::new(before),
+                    Box::new(after),
+                ));
+                self.doc[self.tab].dirty = true;
             }
+            self.doc[self.tab].snap_cursor(&self.view);
+            self.doc[self.tab].prevent_unicode_hell();
+            self.doc[self.tab].recalculate_graphemes();
+            let plural = if count == 1 { "" } else { "s" };
+            self.doc[self.tab]
+                .set_command_line(format!("Replaced {count} occurrence{plural}"), Type::Info);
         }
-        self.doc[self.tab].snap_cursor(&self.term.size);
-        self.doc[self.tab].prevent_unicode_hell();
-        self.doc[self.tab].recalculate_graphemes();
-        self.doc[self.tab].set_command_line("Replaced targets".to_string(), Type::Info);
     }
     fn dirty_prompt(&mut self, key: char, subject: &str) -> bool {
         // For events that require changes to the document
@@ -804,6 +1473,9 @@ impl Editor {
     ) -> Option<String> {
         // Create a new prompt
         self.doc[self.tab].set_command_line(format!("{}{}", prompt, ending), Type::Info);
+        let theme = &self.config.theme;
+        let colour = theme.cursor_insert_color.unwrap_or(theme.cursor_color);
+        self.term.set_cursor_colour(colour);
         self.update();
         let mut result = String::new();
         'p: loop {
@@ -827,6 +1499,7 @@ impl Editor {
                 Key::Esc => {
                     // Handle escape key
                     func(self, PromptEvent::KeyPress(key), &result);
+                    self.term.set_cursor_colour(self.config.theme.cursor_color);
                     return None;
                 }
                 _ => func(self, PromptEvent::KeyPress(key), &result),
@@ -836,42 +1509,143 @@ impl Editor {
             func(self, PromptEvent::Update, &result);
             self.update();
         }
+        self.term.set_cursor_colour(self.config.theme.cursor_color);
         Some(result)
     }
     fn update(&mut self) {
         // Move the cursor and render the screen
         self.term.hide_cursor();
         self.term.goto(&Position { x: 0, y: 0 });
+        self.layout();
         self.doc[self.tab].recalculate_offset(&self.config);
+        self.scroll_to_cursor();
         self.render();
-        self.term.goto(&Position {
-            x: self.doc[self.tab]
-                .cursor
-                .x
-                .saturating_add(self.doc[self.tab].line_offset),
-            y: self.doc[self.tab].cursor.y,
-        });
+        self.term.goto(&self.screen_cursor());
         self.term.show_cursor();
         self.term.flush();
     }
-    fn welcome_message(&self, text: &str, colour: color::Fg<color::Rgb>) -> String {
+    fn wrap_width(&self) -> usize {
+        // Work out the space that is left for text next to the line numbers
+        let space = self
+            .view
+            .width
+            .saturating_sub(self.doc[self.tab].line_offset);
+        self.config
+            .general
+            .wrap_column
+            .map_or(space, |column| column.min(space))
+            .max(1)
+    }
+    fn row_height(&self, index: usize) -> usize {
+        // Work out how many rows of the screen a line takes up
+        if !self.config.general.wrap_lines {
+            return 1;
+        }
+        let doc = &self.doc[self.tab];
+        let width = self.wrap_width();
+        let height = doc.rows[index].length().saturating_sub(1) / width + 1;
+        if index == doc.cursor.y + doc.offset.y - OFFSET {
+            // Make room for the cursor when it sits after a full row
+            height.max((doc.cursor.x + doc.offset.x) / width + 1)
+        } else {
+            height
+        }
+    }
+    fn screen_rows(&self) -> Vec<(usize, usize)> {
+        // Work out which line, and which wrapped part of it, goes on each row
+        let doc = &self.doc[self.tab];
+        let height = self.view.height.saturating_sub(3);
+        let mut result = vec![];
+        for index in doc.offset.y..doc.rows.len() {
+            for part in 0..self.row_height(index) {
+                if result.len() == height {
+                    return result;
+                }
+                result.push((index, part));
+            }
+        }
+        result
+    }
+    fn cursor_row(&self) -> (usize, usize) {
+        // Work out which line, and which wrapped part of it, the cursor is on
+        let doc = &self.doc[self.tab];
+        let line = doc.cursor.y + doc.offset.y - OFFSET;
+        if self.config.general.wrap_lines {
+            (line, doc.cursor.x / self.wrap_width())
+        } else {
+            (line, 0)
+        }
+    }
+    fn screen_cursor(&self) -> Position {
+        // Work out where the cursor is on the screen
+        let doc = &self.doc[self.tab];
+        if !self.config.general.wrap_lines {
+            return Position {
+                x: doc.cursor.x.saturating_add(doc.line_offset) + self.area.x,
+                y: doc.cursor.y + self.area.y,
+            };
+        }
+        let width = self.wrap_width();
+        let target = self.cursor_row();
+        let row = self.screen_rows().iter().position(|r| *r == target);
+        Position {
+            x: (doc.cursor.x % width).saturating_add(doc.line_offset) + self.area.x,
+            y: row.unwrap_or(0) + OFFSET + self.area.y,
+        }
+    }
+    fn scroll_to_cursor(&mut self) {
+        // Keep the cursor on the screen and away from the top and bottom edges
+        let height = self.view.height.saturating_sub(3);
+        let margin = self
+            .config
+            .general
+            .scroll_off
+            .min(height.saturating_sub(1) / 2);
+        let doc = &mut self.doc[self.tab];
+        if self.config.general.wrap_lines {
+            // Wrapped lines don't scroll sideways, so keep the whole column in the cursor
+            doc.cursor.x += doc.offset.x;
+            doc.offset.x = 0;
+        }
+        // Scroll up when the cursor is too close to the top
+        while doc.cursor.y < OFFSET + margin && doc.offset.y > 0 {
+            doc.offset.y -= 1;
+            doc.cursor.y += 1;
+        }
+        // Scroll down when the cursor is too close to the bottom
+        loop {
+            let doc = &self.doc[self.tab];
+            let end = doc.rows.len().saturating_sub(1);
+            let end = (end, self.row_height(end).saturating_sub(1));
+            let screen = self.screen_rows();
+            let row = screen.iter().position(|r| *r == self.cursor_row());
+            let fits = row.is_some_and(|row| row + margin < screen.len());
+            if fits || doc.cursor.y <= OFFSET || screen.last() == Some(&end) {
+                break;
+            }
+            let doc = &mut self.doc[self.tab];
+            doc.offset.y += 1;
+            doc.cursor.y -= 1;
+        }
+    }
+    fn welcome_message(&self, text: &str, colour: &str) -> String {
         // Render the welcome message
-        let pad = " ".repeat((self.term.size.width / 2).saturating_sub(text.len() / 2));
+        let pad = " ".repeat((self.view.width / 2).saturating_sub(text.len() / 2));
         let pad_right = " ".repeat(
-            (self.term.size.width.saturating_sub(1))
+            (self.view.width.saturating_sub(1))
                 .saturating_sub(text.len() + pad.len())
                 .saturating_sub(self.config.general.line_number_padding_left),
         );
         format!(
             "{}{}{}~{}{}{}{}{}{}",
-            Reader::rgb_bg(self.config.theme.editor_bg),
+            self.config.theme.line_background(false),
             Reader::rgb_fg(self.config.theme.line_number_fg),
             " ".repeat(self.config.general.line_number_padding_left),
             RESET_FG,
             colour,
             trim_end(
                 &format!("{}{}", pad, text),
-                self.term.size.width.saturating_sub(1)
+                self.view.width.saturating_sub(1)
             ),
             pad_right,
             RESET_FG,
@@ -881,9 +1655,9 @@ impl Editor {
     fn status_line(&mut self) -> String {
         // Produce the status line
         // Create the left part of the status line
-        let left = self.doc[self.tab].format(&self.config.general.status_left);
+        let left = self.format(self.tab, &self.config.general.status_left);
         // Create the right part of the status line
-        let right = self.doc[self.tab].format(&self.config.general.status_right);
+        let right = self.format(self.tab, &self.config.general.status_right);
         // Get the padding value
         let padding = self.term.align_break(&left, &right);
         // Generate it
@@ -901,15 +1675,46 @@ impl Editor {
             style::Reset,
         )
     }
+    fn spans(&self, index: usize, brackets: &[Position]) -> Vec<Span> {
+        // Work out the parts of a line that are drawn in their own colours
+        let doc = &self.doc[self.tab];
+        let theme = &self.config.theme;
+        let mut spans = vec![];
+        if let Some((from, to)) = doc.selected_columns(index) {
+            spans.push((from, to, theme.selection_bg, theme.selection_fg));
+        }
+        // The match that the search moved the cursor to stands out from the others
+        let cursor = (
+            doc.cursor.x + doc.offset.x,
+            doc.cursor.y + doc.offset.y - OFFSET,
+        );
+        for (from, to) in doc.search_columns(index) {
+            let bg = if (from, index) == cursor {
+                theme.search_current_bg
+            } else {
+                theme.search_highlight_bg
+            };
+            spans.push((from, to, bg, theme.search_highlight_fg));
+        }
+        for bracket in brackets.iter().filter(|b| b.y == index) {
+            spans.push((
+                bracket.x,
+                bracket.x + 1,
+                theme.matching_bracket_bg,
+                theme.editor_fg,
+            ));
+        }
+        spans
+    }
     fn add_background(&self, text: &str) -> String {
-        // Add a background colour to a line
-        format!(
-            "{}{}{}{}",
-            Reader::rgb_bg(self.config.theme.editor_bg),
-            text,
-            self.term.align_left(&text),
-            RESET_BG
-        )
+        // Add the editor background colour to a line across the screen
+        let background = self.config.theme.line_background(false);
+        self.fill_background(text, &background, self.term.size.width)
+    }
+    fn fill_background(&self, text: &str, background: &str, width: usize) -> String {
+        // Add a background colour to a line, padding it out to a width
+        let padding = width.saturating_sub(self.exp.ansi_len(text));
+        format!("{}{}{}{}", background, text, " ".repeat(padding), RESET_BG)
     }
     fn command_line(&self) -> String {
         // Render the command line
@@ -952,7 +1757,7 @@ impl Editor {
                 } else {
                     format!("{}{}", inactive_background, inactive_foreground)
                 },
-                self.doc[num].format(&self.config.general.tab),
+                self.format(num, &self.config.general.tab),
                 style::Reset,
                 inactive_background.to_string(),
                 inactive_foreground.to_string(),
@@ -986,81 +1791,242 @@ impl Editor {
             RESET_BG,
         )
     }
-    fn render(&mut self) {
-        // Draw the screen to the terminal
+    fn list_overlay(&self, title: &str, items: &[&str], selected: usize) -> String {
+        // Draw a list to pick from in a box in the middle of the screen
+        let theme = &self.config.theme;
+        let width = self.term.size.width.saturating_sub(4).min(70);
+        let height = self.term.size.height.saturating_sub(6).clamp(2, 15);
+        let left = (self.term.size.width.saturating_sub(width) / 2) as u16 + 1;
+        let top = (self.term.size.height.saturating_sub(height) / 2) as u16 + 1;
+        let start = (selected + 2).saturating_sub(height);
+        let line = |text: &str| {
+            format!(
+                " {:<1$}",
+                trim_end(text, width.saturating_sub(2)),
+                width.saturating_sub(1)
+            )
+        };
+        let mut result = vec![format!(
+            "{}{}{}{}{}{}",
+            termion::cursor::Goto(left, top),
+            style::Bold,
+            Reader::rgb_bg(theme.active_tab_bg),
+            Reader::rgb_fg(theme.active_tab_fg),
+            line(title),
+            style::Reset,
+        )];
+        for row in 1..height {
+            let index = start + row - 1;
+            let (bg, fg) = if index == selected {
+                (theme.selection_bg, theme.selection_fg)
+            } else {
+                (theme.status_bg, theme.status_fg)
+            };
+            result.push(format!(
+                "{}{}{}{}",
+                termion::cursor::Goto(left, top + row as u16),
+                Reader::rgb_bg(bg),
+                Reader::rgb_fg(fg),
+                line(items.get(index).unwrap_or(&"")),
+            ));
+        }
+        format!("{}{RESET_FG}{RESET_BG}", result.join(""))
+    }
+    fn render_unfocused(&mut self, index: usize, area: Area) -> Vec<String> {
+        // Draw a pane that isn't focused with its own cursor and scroll position
+        let pane = self.panes.panes[index];
+        let focused = (self.tab, self.area, self.view);
+        let doc = &mut self.doc[pane.doc];
+        let saved = (doc.cursor, doc.offset);
+        doc.cursor = pane.cursor;
+        doc.offset = pane.offset;
+        self.tab = pane.doc;
+        self.set_area(area);
+        self.doc[self.tab].recalculate_offset(&self.config);
+        let lines = self.render_pane();
+        let doc = &mut self.doc[pane.doc];
+        (doc.cursor, doc.offset) = saved;
+        (self.tab, self.area, self.view) = focused;
+        self.doc[self.tab].recalculate_offset(&self.config);
+        lines
+    }
+    fn render_pane(&mut self) -> Vec<String> {
+        // Draw the lines of the document in the pane being drawn
         let offset = self.doc[self.tab].offset;
-        let mut frame = vec![self.tab_line()];
-        let rendered = self.doc[self.tab].render(false, 0);
-        let reg = self.doc[self.tab].regex.clone();
-        for row in OFFSET..self.term.size.height {
-            let row = row.saturating_sub(OFFSET);
-            if let Some(r) = self.doc[self.tab].rows.get_mut(offset.y + row) {
-                r.update_syntax(&self.config, &reg, &rendered, offset.y + row, &self.theme);
+        let mut frame = vec![];
+        // Only the lines that changed and the ones that came into view are highlighted
+        let doc = &mut self.doc[self.tab];
+        doc.highlighter.update_until_viewport(
+            &doc.rows,
+            &doc.regex,
+            &self.config.highlights[&self.theme],
+            offset.y + self.view.height,
+        );
+        let brackets: Vec<Position> = doc
+            .matching_brackets()
+            .map_or(vec![], |(bracket, partner)| vec![bracket, partner]);
+        let screen = self.screen_rows();
+        let width = self.wrap_width();
+        // Wrapped parts stop at the wrap column, which may be before the edge of the pane
+        let columns = if self.config.general.wrap_lines {
+            self.doc[self.tab].line_offset + width
+        } else {
+            self.view.width
+        };
+        for row in 0..self.view.height.saturating_sub(3) {
+            let doc = &mut self.doc[self.tab];
+            if let (Some(r), Some(tokens)) = (
+                doc.rows.get_mut(offset.y + row),
+                doc.highlighter.line(offset.y + row),
+            ) {
+                r.syntax.clone_from(tokens);
             }
-            if row == self.term.size.height - 1 - OFFSET {
-                // Render command line
-                frame.push(self.command_line());
-            } else if row == self.term.size.height - 2 - OFFSET {
-                // Render status line
-                frame.push(self.status_line());
-            } else if row == self.term.size.height / 4 - OFFSET && self.doc[self.tab].show_welcome {
-                frame.push(self.welcome_message(
-                    &format!("Ox editor  v{}", VERSION),
-                    Reader::rgb_fg(self.config.theme.editor_fg),
-                ));
-            } else if row == (self.term.size.height / 4).saturating_add(1) - OFFSET
-                && self.doc[self.tab].show_welcome
-            {
-                frame.push(self.welcome_message(
-                    "A Rust powered editor by Luke",
-                    Reader::rgb_fg(self.config.theme.editor_fg),
-                ));
-            } else if row == (self.term.size.height / 4).saturating_add(3) - OFFSET
-                && self.doc[self.tab].show_welcome
-            {
-                frame.push(self.welcome_message(
-                    "Ctrl + Q: Exit   ",
-                    Reader::rgb_fg(self.config.theme.status_fg),
-                ));
-            } else if row == (self.term.size.height / 4).saturating_add(4) - OFFSET
-                && self.doc[self.tab].show_welcome
-            {
-                frame.push(self.welcome_message(
-                    "Ctrl + S: Save   ",
-                    Reader::rgb_fg(self.config.theme.status_fg),
-                ));
-            } else if row == (self.term.size.height / 4).saturating_add(5) - OFFSET
-                && self.doc[self.tab].show_welcome
-            {
-                frame.push(self.welcome_message(
-                    "Ctrl + W: Save as",
-                    Reader::rgb_fg(self.config.theme.status_fg),
-                ));
-            } else if let Some(line) = self.doc[self.tab]
-                .rows
-                .get(self.doc[self.tab].offset.y + row)
-            {
-                // Render lines of code
-                frame.push(self.add_background(&line.render(
-                    self.doc[self.tab].offset.x,
-                    self.term.size.width,
-                    self.doc[self.tab].offset.y + row,
-                    self.doc[self.tab].line_offset,
+            frame.push(self.render_row(row, &screen, &brackets, width, columns));
+        }
+        frame
+    }
+    fn render_row(
+        &self,
+        row: usize,
+        screen: &[(usize, usize)],
+        brackets: &[Position],
+        width: usize,
+        columns: usize,
+    ) -> String {
+        // Draw one line of the pane, the welcome message goes over an empty document
+        let welcome = self.doc[self.tab].show_welcome;
+        if row == self.view.height / 4 - OFFSET && welcome {
+            self.welcome_message(
+                &format!("Ox editor  v{VERSION}"),
+                &Reader::rgb_fg(self.config.theme.editor_fg),
+            )
+        } else if row == (self.view.height / 4).saturating_add(1) - OFFSET && welcome {
+            self.welcome_message(
+                "A Rust powered editor by Luke",
+                &Reader::rgb_fg(self.config.theme.editor_fg),
+            )
+        } else if row == (self.view.height / 4).saturating_add(3) - OFFSET && welcome {
+            self.welcome_message(
+                "Ctrl + Q: Exit   ",
+                &Reader::rgb_fg(self.config.theme.status_fg),
+            )
+        } else if row == (self.view.height / 4).saturating_add(4) - OFFSET && welcome {
+            self.welcome_message(
+                "Ctrl + S: Save   ",
+                &Reader::rgb_fg(self.config.theme.status_fg),
+            )
+        } else if row == (self.view.height / 4).saturating_add(5) - OFFSET && welcome {
+            self.welcome_message(
+                "Ctrl + W: Save as",
+                &Reader::rgb_fg(self.config.theme.status_fg),
+            )
+        } else if let Some(&(index, part)) = screen.get(row) {
+            // Render lines of code
+            let doc = &self.doc[self.tab];
+            let cursor = doc.cursor.y + doc.offset.y - OFFSET;
+            let background = self.config.theme.line_background(index == cursor);
+            self.fill_background(
+                &doc.rows[index].render(
+                    &RenderOptions {
+                        start: doc.offset.x + part * width,
+                        width: columns,
+                        index,
+                        cursor,
+                        offset: doc.line_offset,
+                        continued: part != 0,
+                        spans: &self.spans(index, brackets),
+                        tab_width: doc.use_tabs.then_some(doc.tab_width),
+                    },
                     &self.config,
-                )));
-            } else {
-                // Render empty lines
-                frame.push(format!(
-                    "{}{}{}",
-                    Reader::rgb_fg(self.config.theme.line_number_fg),
-                    self.add_background(&format!(
+                ),
+                &background,
+                self.view.width,
+            )
+        } else {
+            // Render empty lines
+            format!(
+                "{}{}{}",
+                Reader::rgb_fg(self.config.theme.line_number_fg),
+                self.fill_background(
+                    &format!(
                         "{}~",
                         " ".repeat(self.config.general.line_number_padding_left)
-                    )),
-                    RESET_FG
-                ));
+                    ),
+                    &self.config.theme.line_background(false),
+                    self.view.width,
+                ),
+                RESET_FG
+            )
+        }
+    }
+    fn layout(&mut self) -> Vec<Area> {
+        // Work out where each pane goes, the focused one always shows the current tab
+        let height = self.term.size.height.saturating_sub(3);
+        let areas = self.panes.areas(self.term.size.width, height);
+        self.panes.panes[self.panes.focus].doc = self.tab;
+        self.set_area(areas[self.panes.focus]);
+        areas
+    }
+    fn set_area(&mut self, area: Area) {
+        // Use a part of the screen for the document, the view counts the lines around it
+        self.area = area;
+        self.view = Size {
+            width: area.width,
+            height: area.height + 3,
+        };
+    }
+    fn render(&mut self) {
+        // Draw the screen to the terminal
+        let mut frame = vec![self.tab_line()];
+        let areas = self.layout();
+        let mut panes = vec![];
+        for (i, area) in areas.iter().enumerate() {
+            if i == self.panes.focus {
+                panes.push(self.render_pane());
+            } else {
+                panes.push(self.render_unfocused(i, *area));
             }
         }
+        self.layout();
+        // Put the panes together with a line between each of them
+        let divider = format!(
+            "{}{}",
+            self.config.theme.line_background(false),
+            Reader::rgb_fg(self.config.theme.line_number_fg)
+        );
+        match self.panes.orientation {
+            Orientation::Vertical => {
+                let line = format!("{divider}\u{2502}{RESET_FG}{RESET_BG}");
+                for row in 0..self.term.size.height.saturating_sub(3) {
+                    let parts: Vec<&str> = panes.iter().map(|p| p[row].as_str()).collect();
+                    frame.push(parts.join(&line));
+                }
+            }
+            Orientation::Horizontal => {
+                let line = "\u{2500}".repeat(self.term.size.width);
+                for (i, pane) in panes.into_iter().enumerate() {
+                    if i != 0 {
+                        frame.push(format!("{divider}{line}{RESET_FG}{RESET_BG}"));
+                    }
+                    frame.extend(pane);
+                }
+            }
+        }
+        frame.push(self.status_line());
+        frame.push(self.command_line());
         print!("{}", frame.join("\r\n"));
+        if let Some(finder) = &self.finder {
+            let matches = finder.matches();
+            let title = format!("Files ({}/{})", matches.len(), finder.total());
+            print!("{}", self.list_overlay(&title, &matches, finder.selected));
+        }
+        if let Some(selected) = self.switcher {
+            let names: Vec<String> = (0..self.doc.len())
+                .map(|num| self.format(num, "%t %f%d  %F"))
+                .collect();
+            let names: Vec<&str> = names.iter().map(String::as_str).collect();
+            let title = format!("Open documents ({})", self.doc.len());
+            print!("{}", self.list_overlay(&title, &names, selected));
+        }
     }
 }
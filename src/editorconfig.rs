@@ -0,0 +1,309 @@
+// Editorconfig.rs - For reading the settings that .editorconfig files give a file
+use regex::Regex;
+use std::path::{Path, PathBuf};
+use std::{env, fs};
+
+// The name of the files that are looked for in the folders above a file
+const FILE_NAME: &str = ".editorconfig";
+
+// The settings that the .editorconfig files above a file give it, None when left unset
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct EditorConfig {
+    pub indent_style: Option<String>,           // Either tab or space
+    pub indent_size: Option<String>,            // Columns per level, or tab to use tab_width
+    pub tab_width: Option<usize>,               // The columns that a tab takes up
+    pub trim_trailing_whitespace: Option<bool>, // Remove whitespace at the ends of lines
+    pub insert_final_newline: Option<bool>,     // Make sure the file ends with a newline
+    pub end_of_line: Option<String>,            // Either lf, crlf or cr
+    pub charset: Option<String>,                // Ox only edits UTF-8, with or without a BOM
+}
+
+impl EditorConfig {
+    pub fn find(file: &str) -> Self {
+        // Gather the settings for a file from the .editorconfig files in the folders above it
+        let mut result = Self::default();
+        if file.is_empty() {
+            return result;
+        }
+        let path = Path::new(file);
+        let path = if path.is_absolute() {
+            path.to_path_buf()
+        } else if let Ok(folder) = env::current_dir() {
+            folder.join(path)
+        } else {
+            return result;
+        };
+        // Files closer to the file take priority, so they are applied last
+        let mut found: Vec<(PathBuf, String)> = vec![];
+        for folder in path.ancestors().skip(1) {
+            if let Ok(contents) = fs::read_to_string(folder.join(FILE_NAME)) {
+                let root = is_root(&contents);
+                found.push((folder.to_path_buf(), contents));
+                if root {
+                    break;
+                }
+            }
+        }
+        for (folder, contents) in found.iter().rev() {
+            if let Ok(relative) = path.strip_prefix(folder) {
+                let relative = relative.to_string_lossy().replace('\\', "/");
+                result.apply(contents, &relative);
+            }
+        }
+        result
+    }
+    fn apply(&mut self, contents: &str, relative: &str) {
+        // Use the settings of every section that matches the file, later ones winning
+        let mut matches = false;
+        for line in contents.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with(&['#', ';'][..]) {
+                continue;
+            }
+            if let Some(glob) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                matches = glob_regex(glob).is_some_and(|regx| regx.is_match(relative));
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            if !matches {
+                continue;
+            }
+            let value = value.trim().to_lowercase();
+            let unset = value == "unset";
+            let flag = match value.as_str() {
+                "true" => Some(true),
+                "false" => Some(false),
+                _ => None,
+            };
+            match key.trim().to_lowercase().as_str() {
+                "indent_style" => self.indent_style = (!unset).then_some(value),
+                "indent_size" => self.indent_size = (!unset).then_some(value),
+                "tab_width" => self.tab_width = value.parse().ok(),
+                "trim_trailing_whitespace" => self.trim_trailing_whitespace = flag,
+                "insert_final_newline" => self.insert_final_newline = flag,
+                "end_of_line" => self.end_of_line = (!unset).then_some(value),
+                "charset" => self.charset = (!unset).then_some(value),
+                _ => (),
+            }
+        }
+    }
+    pub fn use_tabs(&self) -> Option<bool> {
+        // Work out whether indentation should be tabs
+        match self.indent_style.as_deref() {
+            Some("tab") => Some(true),
+            Some("space") => Some(false),
+            _ => None,
+        }
+    }
+    pub fn width(&self) -> Option<usize> {
+        // Work out the width of indentation, which falls back to the width of a tab
+        match self.indent_size.as_deref() {
+            Some("tab") | None => self.tab_width,
+            Some(size) => size.parse().ok().or(self.tab_width),
+        }
+        .filter(|&width| width > 0)
+    }
+    pub fn line_ending(&self) -> Option<&'static str> {
+        // Work out what should go between the lines when the file is saved
+        match self.end_of_line.as_deref() {
+            Some("lf") => Some("\n"),
+            Some("crlf") => Some("\r\n"),
+            Some("cr") => Some("\r"),
+            _ => None,
+        }
+    }
+    pub fn bom(&self) -> Option<bool> {
+        // Work out whether the file should start with a byte order mark, other charsets are left alone
+        match self.charset.as_deref() {
+            Some("utf-8-bom") => Some(true),
+            Some("utf-8") => Some(false),
+            _ => None,
+        }
+    }
+}
+
+fn is_root(contents: &str) -> bool {
+    // Check for root = true before the first section, which stops the search going higher
+    contents
+        .lines()
+        .map(str::trim)
+        .take_while(|line| !line.starts_with('['))
+        .filter_map(|line| line.split_once('='))
+        .any(|(key, value)| {
+            key.trim().eq_ignore_ascii_case("root") && value.trim().eq_ignore_ascii_case("true")
+        })
+}
+
+fn glob_regex(glob: &str) -> Option<Regex> {
+    // Turn a section glob into an expression, globs without a slash match in any folder
+    let (prefix, glob) = if glob.contains('/') {
+        ("^", glob.trim_start_matches('/'))
+    } else {
+        ("^(?:.*/)?", glob)
+    };
+    let chars: Vec<char> = glob.chars().collect();
+    let mut result = prefix.to_string();
+    let mut braces = 0;
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '*' if chars.get(i + 1) == Some(&'*') => {
+                result.push_str(".*");
+                i += 1;
+            }
+            '*' => result.push_str("[^/]*"),
+            '?' => result.push_str("[^/]"),
+            '[' => {
+                // Character classes are copied over, with ! for leaving characters out
+                let end = chars[i..].iter().position(|&c| c == ']')? + i;
+                let class: String = chars[i + 1..end].iter().collect();
+                let (negate, class) = class
+                    .strip_prefix('!')
+                    .map_or(("", class.as_str()), |rest| ("^", rest));
+                result.push('[');
+                result.push_str(negate);
+                result.push_str(&class.replace('\\', "\\\\"));
+                result.push(']');
+                i = end;
+            }
+            '{' => {
+                let end = chars[i..].iter().position(|&c| c == '}').map(|end| end + i);
+                let inner: String =
+                    end.map_or_else(String::new, |end| chars[i + 1..end].iter().collect());
+                if let Some(range) = number_range(&inner) {
+                    // {1..3} matches any whole number in the range
+                    result.push_str(&range);
+                    i = end?;
+                } else {
+                    result.push_str("(?:");
+                    braces += 1;
+                }
+            }
+            '}' if braces > 0 => {
+                result.push(')');
+                braces -= 1;
+            }
+            ',' if braces > 0 => result.push('|'),
+            '\\' => {
+                i += 1;
+                result.push_str(&regex::escape(&chars.get(i)?.to_string()));
+            }
+            c => result.push_str(&regex::escape(&c.to_string())),
+        }
+        i += 1;
+    }
+    result.push('$');
+    Regex::new(&result).ok()
+}
+
+fn number_range(inner: &str) -> Option<String> {
+    // Expand {start..end} into the numbers between, for ranges small enough to list
+    let (start, end) = inner.split_once("..")?;
+    let (start, end): (i64, i64) = (start.parse().ok()?, end.parse().ok()?);
+    let (start, end) = (start.min(end), start.max(end));
+    if end - start > 1000 {
+        return Some(r"-?\d+".to_string());
+    }
+    let numbers: Vec<String> = (start..=end).map(|n| n.to_string()).collect();
+    Some(format!("(?:{})", numbers.join("|")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(glob: &str, path: &str) -> bool {
+        // Check a glob against a path relative to the .editorconfig file
+        glob_regex(glob).unwrap().is_match(path)
+    }
+
+    #[test]
+    fn globs_without_a_slash_match_in_any_folder() {
+        assert!(matches("*.rs", "main.rs"));
+        assert!(matches("*.rs", "src/main.rs"));
+        assert!(!matches("*.rs", "main.rsx"));
+        assert!(matches("Makefile", "lib/Makefile"));
+    }
+
+    #[test]
+    fn globs_with_a_slash_match_from_the_folder() {
+        assert!(matches("src/*.rs", "src/main.rs"));
+        assert!(!matches("src/*.rs", "src/bin/main.rs"));
+        assert!(!matches("src/*.rs", "lib/src/main.rs"));
+        assert!(matches("/src/**.rs", "src/bin/main.rs"));
+        assert!(matches("**/test.py", "a/b/test.py"));
+    }
+
+    #[test]
+    fn globs_have_alternatives_and_classes() {
+        assert!(matches("*.{js,ts}", "index.ts"));
+        assert!(!matches("*.{js,ts}", "index.rs"));
+        assert!(matches("file?.[ch]", "file1.h"));
+        assert!(!matches("[!a]*.c", "abc.c"));
+        assert!(matches("[!a]*.c", "bcd.c"));
+        assert!(matches(r"\*.txt", "*.txt"));
+        assert!(!matches(r"\*.txt", "a.txt"));
+    }
+
+    #[test]
+    fn number_ranges_are_expanded() {
+        assert_eq!(number_range("1..3").as_deref(), Some("(?:1|2|3)"));
+        assert_eq!(number_range("3..1").as_deref(), Some("(?:1|2|3)"));
+        assert_eq!(number_range("-1..1").as_deref(), Some("(?:-1|0|1)"));
+        assert_eq!(number_range("0..5000").as_deref(), Some(r"-?\d+"));
+        assert_eq!(number_range("a..b"), None);
+        assert_eq!(number_range("js,ts"), None);
+        assert!(matches("file{1..3}.txt", "file2.txt"));
+        assert!(!matches("file{1..3}.txt", "file4.txt"));
+    }
+
+    #[test]
+    fn later_sections_win() {
+        let contents = "
+            [*]
+            indent_size = 2
+            tab_width = 8
+
+            [*.rs]
+            indent_size = 4
+            indent_style = space
+
+            [*.{rs,py}]
+            trim_trailing_whitespace = true
+        ";
+        let mut config = EditorConfig::default();
+        config.apply(contents, "src/main.rs");
+        assert_eq!(config.indent_size.as_deref(), Some("4"));
+        assert_eq!(config.tab_width, Some(8));
+        assert_eq!(config.use_tabs(), Some(false));
+        assert_eq!(config.trim_trailing_whitespace, Some(true));
+        let mut config = EditorConfig::default();
+        config.apply(contents, "README.md");
+        assert_eq!(config.width(), Some(2));
+        assert_eq!(config.use_tabs(), None);
+        // A later section can also unset what an earlier one gave
+        config.apply("[*.md]\nindent_size = unset\n", "README.md");
+        assert_eq!(config.width(), Some(8));
+    }
+
+    #[test]
+    fn closer_files_win_up_to_the_root() {
+        let base = env::temp_dir().join(format!("ox-editorconfig-{}", std::process::id()));
+        let folder = base.join("project").join("src");
+        fs::create_dir_all(&folder).unwrap();
+        fs::write(base.join(FILE_NAME), "[*]\ncharset = utf-8-bom\n").unwrap();
+        fs::write(
+            base.join("project").join(FILE_NAME),
+            "root = true\n[*]\nindent_style = tab\ntab_width = 8\n",
+        )
+        .unwrap();
+        fs::write(folder.join(FILE_NAME), "[*.rs]\nindent_style = space\n").unwrap();
+        let config = EditorConfig::find(&folder.join("main.rs").to_string_lossy());
+        fs::remove_dir_all(&base).unwrap();
+        assert_eq!(config.use_tabs(), Some(false));
+        assert_eq!(config.width(), Some(8));
+        // The folder above the root isn't read
+        assert_eq!(config.bom(), None);
+    }
+}
@@ -0,0 +1,189 @@
+// Finder.rs - For finding files in the current directory by typing part of their name
+use std::convert::TryFrom;
+use std::fs;
+use std::path::Path;
+
+// Stop walking huge directories after this many files
+const MAX_FILES: usize = 20_000;
+
+// An ignore rule taken from the config or a .gitignore file
+struct Ignore {
+    pattern: String, // The glob to match against
+    anchored: bool,  // True if it matches the whole path rather than a name
+    dir_only: bool,  // True if it should only match directories
+}
+
+// The file finder struct
+pub struct FileFinder {
+    files: Vec<String>,  // Every file that was found, relative to the root
+    matches: Vec<usize>, // Indices of the files that match the query, best first
+    pub selected: usize, // The match that is currently selected
+}
+
+impl FileFinder {
+    pub fn new(root: &str, ignore: &[String]) -> Self {
+        // Find every file below the root that isn't ignored
+        let mut rules: Vec<Ignore> = ignore.iter().filter_map(|p| Ignore::from(p)).collect();
+        if let Ok(gitignore) = fs::read_to_string(Path::new(root).join(".gitignore")) {
+            rules.extend(gitignore.lines().filter_map(Ignore::from));
+        }
+        let mut files = vec![];
+        walk(Path::new(root), "", &rules, &mut files);
+        let mut finder = Self {
+            files,
+            matches: vec![],
+            selected: 0,
+        };
+        finder.filter("");
+        finder
+    }
+    pub fn filter(&mut self, query: &str) {
+        // Rank the files against the query, dropping the ones that don't match
+        let mut scored: Vec<(i64, usize)> = self
+            .files
+            .iter()
+            .enumerate()
+            .filter_map(|(i, file)| fuzzy_score(query, file).map(|score| (score, i)))
+            .collect();
+        let files = &self.files;
+        scored.sort_by(|a, b| {
+            b.0.cmp(&a.0)
+                .then(files[a.1].len().cmp(&files[b.1].len()))
+                .then(files[a.1].cmp(&files[b.1]))
+        });
+        self.matches = scored.into_iter().map(|(_, i)| i).collect();
+        self.selected = 0;
+    }
+    pub fn select_previous(&mut self) {
+        // Move the selection up the list
+        self.selected = self.selected.saturating_sub(1);
+    }
+    pub fn select_next(&mut self) {
+        // Move the selection down the list
+        if self.selected + 1 < self.matches.len() {
+            self.selected += 1;
+        }
+    }
+    pub fn matches(&self) -> Vec<&str> {
+        // Get the matching files, best first
+        self.matches
+            .iter()
+            .map(|&i| self.files[i].as_str())
+            .collect()
+    }
+    pub fn total(&self) -> usize {
+        // Get the number of files that were found
+        self.files.len()
+    }
+    pub fn selection(&self) -> Option<&str> {
+        // Get the file that is currently selected
+        self.matches
+            .get(self.selected)
+            .map(|&i| self.files[i].as_str())
+    }
+}
+
+impl Ignore {
+    fn from(line: &str) -> Option<Self> {
+        // Read a line of a .gitignore file, negated patterns aren't supported
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with('!') {
+            return None;
+        }
+        let dir_only = line.ends_with('/');
+        let line = line.trim_end_matches('/');
+        let anchored = line.contains('/');
+        Some(Self {
+            pattern: line.trim_start_matches('/').to_string(),
+            anchored,
+            dir_only,
+        })
+    }
+    fn matches(&self, path: &str, name: &str, dir: bool) -> bool {
+        // Check if a file or directory is covered by this rule
+        if self.dir_only && !dir {
+            return false;
+        }
+        glob_match(&self.pattern, if self.anchored { path } else { name })
+    }
+}
+
+fn walk(dir: &Path, prefix: &str, rules: &[Ignore], files: &mut Vec<String>) {
+    // Collect the files in a directory and the directories within it
+    let mut entries: Vec<_> = match fs::read_dir(dir) {
+        Ok(entries) => entries.filter_map(Result::ok).collect(),
+        Err(_) => return,
+    };
+    entries.sort_by_key(fs::DirEntry::file_name);
+    for entry in entries {
+        if files.len() >= MAX_FILES {
+            return;
+        }
+        let name = entry.file_name().to_string_lossy().to_string();
+        let path = format!("{prefix}{name}");
+        // Symbolic links are skipped so that loops can't happen
+        let Ok(kind) = entry.file_type() else {
+            continue;
+        };
+        if rules.iter().any(|r| r.matches(&path, &name, kind.is_dir())) {
+            continue;
+        }
+        if kind.is_dir() {
+            walk(&entry.path(), &format!("{path}/"), rules, files);
+        } else if kind.is_file() {
+            files.push(path);
+        }
+    }
+}
+
+fn glob_match(pattern: &str, text: &str) -> bool {
+    // Match text against a glob, * and ? stop at slashes but ** doesn't
+    fn inner(p: &[char], t: &[char]) -> bool {
+        match p.first() {
+            None => t.is_empty(),
+            Some('*') if p.get(1) == Some(&'*') => {
+                let rest = if p.get(2) == Some(&'/') {
+                    &p[3..]
+                } else {
+                    &p[2..]
+                };
+                (0..=t.len()).any(|i| inner(rest, &t[i..]))
+            }
+            Some('*') => (0..=t.len())
+                .take_while(|&i| i == 0 || t[i - 1] != '/')
+                .any(|i| inner(&p[1..], &t[i..])),
+            Some('?') => t.first().is_some_and(|&c| c != '/') && inner(&p[1..], &t[1..]),
+            Some(&c) => t.first() == Some(&c) && inner(&p[1..], &t[1..]),
+        }
+    }
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    inner(&pattern, &text)
+}
+
+fn fuzzy_score(query: &str, text: &str) -> Option<i64> {
+    // Score how well the query matches the text, None if it isn't a subsequence
+    let text: Vec<char> = text.chars().collect();
+    let name_start = text.iter().rposition(|&c| c == '/').map_or(0, |i| i + 1);
+    let mut score = 0;
+    let mut last: Option<usize> = None;
+    let mut at = 0;
+    for q in query.chars().filter(|c| !c.is_whitespace()) {
+        let found = (at..text.len()).find(|&i| text[i].to_lowercase().eq(q.to_lowercase()))?;
+        // Reward runs of characters, the start of words and the file name itself
+        score += 1;
+        if last.is_some_and(|l| l + 1 == found) {
+            score += 5;
+        }
+        if found == 0 || matches!(text[found - 1], '/' | '_' | '-' | '.' | ' ') {
+            score += 3;
+        }
+        if found >= name_start {
+            score += 2;
+        }
+        score -= i64::try_from(found - at).unwrap_or(0) / 4;
+        last = Some(found);
+        at = found + 1;
+    }
+    Some(score)
+}
@@ -1,5 +1,7 @@
 // Highlight.rs - For syntax highlighting
-use crate::config::{Reader, TokenType};
+use crate::config::{HighlightStyle, Reader, TokenType};
+use crate::Row;
+use regex::Regex;
 use std::collections::HashMap;
 use unicode_width::UnicodeWidthStr;
 
@@ -10,8 +12,16 @@ pub struct Token {
     pub data: String,
     pub kind: String,
     pub priority: bool,
+    pub layered: bool,
+    pub group: String,
 }
 
+// Groups that are drawn on top of the tokens they are found inside
+const LAYERED: [&str; 1] = ["todos"];
+
+// Groups whose text isn't code, so brackets inside them don't count
+pub const QUOTED: [&str; 3] = ["comments", "strings", "characters"];
+
 pub fn cine(token: &Token, hashmap: &mut HashMap<usize, Token>) {
     // Insert a token into a hashmap
     if let Some(t) = hashmap.get(&token.span.0) {
@@ -30,24 +40,242 @@ fn bounds(reg: &regex::Match, line: &str) -> (usize, usize) {
     (pre_length, pre_length + unicode_width)
 }
 
-fn multi_to_single(doc: &str, m: &regex::Match) -> ((usize, usize), (usize, usize)) {
-    // Multiline tokens to single line tokens
-    let b = bounds(&m, &doc);
-    let start_y = doc[..m.start()].matches('\n').count();
-    let end_y = doc[..m.end()].matches('\n').count();
-    let start_x = b.0
-        - UnicodeWidthStr::width(&doc.split('\n').take(start_y).collect::<Vec<_>>().join("\n")[..]);
-    let end_x = b.1
-        - UnicodeWidthStr::width(&doc.split('\n').take(end_y).collect::<Vec<_>>().join("\n")[..]);
-    ((start_x, start_y), (end_x, end_y))
+// Keeps the syntax highlighting of a document up to date without redoing all of it
+#[derive(Debug, Default)]
+pub struct Highlighter {
+    lines: Vec<HashMap<usize, Token>>, // The highlighting of each line
+    text: Vec<String>,                 // The text of each line when it was highlighted
+    multiline: Vec<Multiline>,         // The multiline tokens on each line
+    total: usize,                      // The number of lines the document had back then
+}
+
+// The multiline tokens on a line, with the expressions that found them
+#[derive(Debug, Clone, Default)]
+struct Multiline {
+    tokens: Vec<(usize, Token)>, // Each token and the expression that found it
+    crossing: Vec<usize>,        // The expressions with a token running on from the line above
+}
+
+impl Multiline {
+    fn spans(&self) -> Vec<(usize, (usize, usize))> {
+        // Where the tokens are on the line and the expressions that found them
+        self.tokens
+            .iter()
+            .map(|(id, token)| (*id, token.span))
+            .collect()
+    }
+    fn copy(&mut self, other: &Self, id: usize) {
+        // Take on the tokens that an expression found on a line before
+        let tokens = other.tokens.iter().filter(|(found, _)| *found == id);
+        self.tokens.extend(tokens.cloned());
+        if other.crossing.contains(&id) {
+            self.crossing.push(id);
+        }
+    }
+}
+
+impl Highlighter {
+    pub fn invalidate_from(&mut self, line: usize) {
+        // Forget the highlighting of a line and everything below it
+        self.lines.truncate(line);
+        self.text.truncate(line);
+        self.multiline.truncate(line);
+    }
+    pub fn update_until_viewport(
+        &mut self,
+        rows: &[Row],
+        regex: &[TokenType],
+        highlights: &HashMap<String, HighlightStyle>,
+        viewport_end: usize,
+    ) {
+        // Highlight the lines that changed, down to the bottom of the screen
+        let end = viewport_end.min(rows.len());
+        let dirty = (0..self.text.len())
+            .find(|&y| rows.get(y).is_none_or(|row| row.string != self.text[y]));
+        if dirty.is_none() && self.text.len() >= end {
+            self.total = rows.len();
+            return;
+        }
+        let dirty = dirty.unwrap_or(self.text.len());
+        let multiline = self.multiline_tokens(rows, end, dirty, regex, highlights);
+        for (y, (row, found)) in rows.iter().zip(&multiline).enumerate() {
+            let spans = found.spans();
+            // Lines that are the same as before are safe to keep
+            if self.text.get(y) == Some(&row.string)
+                && self.multiline.get(y).map(Multiline::spans) == Some(spans)
+            {
+                continue;
+            }
+            let tokens: Vec<Token> = found.tokens.iter().map(|(_, t)| t.clone()).collect();
+            let tokens = highlight(&row.string, regex, highlights, &tokens);
+            let tokens = remove_nested_tokens(&tokens, &row.string);
+            if y < self.lines.len() {
+                self.lines[y] = tokens;
+                self.text[y].clone_from(&row.string);
+            } else {
+                self.lines.push(tokens);
+                self.text.push(row.string.clone());
+            }
+        }
+        self.multiline = multiline;
+        self.total = rows.len();
+        // Anything further down could be out of date now
+        self.invalidate_from(end);
+    }
+    pub fn line(&self, index: usize) -> Option<&HashMap<usize, Token>> {
+        // Get the highlighting of a line, if it has been worked out
+        self.lines.get(index)
+    }
+    fn multiline_tokens(
+        &self,
+        rows: &[Row],
+        lines: usize,
+        dirty: usize,
+        regex: &[TokenType],
+        highlights: &HashMap<String, HighlightStyle>,
+    ) -> Vec<Multiline> {
+        // Find the multiline tokens on the first few lines, stopping once they are as they were
+        let mut result = vec![Multiline::default(); lines];
+        let exps = multiline_expressions(regex, highlights);
+        if lines == 0 || exps.is_empty() {
+            return result;
+        }
+        let cached = &self.multiline;
+        // Where a line below the edit was before, lines may have been added or removed since
+        let old = |y: usize| (y + self.total).checked_sub(rows.len());
+        // The lines that were highlighted before, and the ones among them that are unchanged
+        let known = lines.min((cached.len() + rows.len()).saturating_sub(self.total));
+        let mut same_from = known;
+        while same_from > dirty + 1
+            && old(same_from - 1).is_some_and(|o| rows[same_from - 1].string == self.text[o])
+        {
+            same_from -= 1;
+        }
+        // Once nothing runs into an unchanged line, before or now, the rest is as it was
+        let settled = |id: usize, y: usize| {
+            y >= same_from && old(y).is_some_and(|o| !cached[o].crossing.contains(&id))
+        };
+        // A comment can be opened anywhere above an edit, so the search starts from the top
+        let text = rows
+            .iter()
+            .map(|row| row.string.as_str())
+            .collect::<Vec<_>>()
+            .join("\n");
+        let mut starts = vec![0];
+        starts.extend(text.match_indices('\n').map(|(i, _)| i + 1));
+        let line_of = |i: usize| starts.partition_point(|&start| start <= i) - 1;
+        let column = |y: usize, i: usize| UnicodeWidthStr::width(&text[starts[y]..i]);
+        for (id, (name, kind, exp)) in exps.iter().enumerate() {
+            let layered = LAYERED.contains(&name.as_str());
+            let mut locations = exp.capture_locations();
+            let (mut at, mut last) = (0, None);
+            let mut free = 0;
+            let mut stop = None;
+            // Lines above this have their tokens already
+            let mut done = 0;
+            while at <= text.len() {
+                let Some(whole) = exp.captures_read_at(&mut locations, &text, at) else {
+                    break;
+                };
+                // Empty matches move on a character, like captures_iter does
+                let empty = whole.start() == whole.end();
+                at = if empty {
+                    whole.end() + text[whole.end()..].chars().next().map_or(1, char::len_utf8)
+                } else {
+                    whole.end()
+                };
+                if empty && last == Some(whole.end()) {
+                    continue;
+                }
+                last = Some(whole.end());
+                let (first, end) = (line_of(whole.start()), line_of(whole.end()));
+                if stop.is_none() {
+                    stop = (free.max(same_from)..known.min(first + 1)).find(|&y| settled(id, y));
+                    if let Some(stop) = stop {
+                        // The lines from here keep the tokens they had
+                        for (y, line) in result.iter_mut().enumerate().take(known).skip(stop) {
+                            if let Some(o) = old(y) {
+                                line.copy(&cached[o], id);
+                            }
+                        }
+                        // Lines that weren't highlighted before are searched from the stop
+                        done = known;
+                        (at, last) = (starts[stop], None);
+                        continue;
+                    }
+                }
+                if first >= lines || done >= lines {
+                    break;
+                }
+                for y in (first + 1).max(done)..=end.min(lines - 1) {
+                    if starts[y] < whole.end() {
+                        result[y].crossing.push(id);
+                    }
+                }
+                // Nothing runs into the line a match ends on if it ends right at the start
+                free = end + usize::from(starts[end] < whole.end());
+                let Some((cap_start, cap_end)) = locations.get(locations.len() - 1) else {
+                    continue;
+                };
+                let (start_y, end_y) = (line_of(cap_start), line_of(cap_end));
+                for y in start_y.max(done)..=end_y.min(lines - 1) {
+                    let row = rows[y].string.as_str();
+                    let from = if y == start_y {
+                        column(y, cap_start)
+                    } else {
+                        0
+                    };
+                    let to = if y == end_y {
+                        column(y, cap_end)
+                    } else {
+                        UnicodeWidthStr::width(row)
+                    };
+                    let token = Token {
+                        span: (from, to),
+                        data: row.to_string(),
+                        kind: kind.clone(),
+                        priority: true,
+                        layered,
+                        group: (*name).clone(),
+                    };
+                    result[y].tokens.push((id, token));
+                }
+            }
+        }
+        result
+    }
+}
+
+fn multiline_expressions<'a>(
+    regex: &'a [TokenType],
+    highlights: &HashMap<String, HighlightStyle>,
+) -> Vec<(&'a String, String, &'a Regex)> {
+    // List the multiline expressions in order, with the group and colour of each
+    regex
+        .iter()
+        .rev()
+        .filter_map(|exps| match exps {
+            TokenType::MultiLine(name, regex) => Some((exps, name, regex)),
+            TokenType::SingleLine(..) => None,
+        })
+        .filter_map(|(exps, name, regex)| {
+            // Groups that the highlights don't give a style are left uncoloured
+            let kind = highlights.get(name).map(Reader::highlight_style);
+            if kind.is_none() && exps.capture_groups().is_empty() {
+                return None;
+            }
+            let kind = kind.unwrap_or_default();
+            Some(regex.iter().map(move |exp| (name, kind.clone(), exp)))
+        })
+        .flatten()
+        .collect()
 }
 
 pub fn highlight(
     row: &str,
-    doc: &str,
-    index: usize,
     regex: &[TokenType],
-    highlights: &HashMap<String, (u8, u8, u8)>,
+    highlights: &HashMap<String, HighlightStyle>,
+    multiline: &[Token],
 ) -> HashMap<usize, Token> {
     // Generate syntax highlighting information
     let mut syntax: HashMap<usize, Token> = HashMap::new();
@@ -55,104 +283,114 @@ pub fn highlight(
         // Language not found, return empty hashmap
         return syntax;
     }
-    for exps in regex {
-        match exps {
-            TokenType::SingleLine(name, regex) => {
-                if name == "keywords" {
-                    for kw in regex {
-                        // Locate keywords
-                        for cap in kw.captures_iter(row) {
-                            let cap = cap.get(cap.len().saturating_sub(1)).unwrap();
-                            let boundaries = bounds(&cap, &row);
-                            cine(
-                                &Token {
-                                    span: boundaries,
-                                    data: cap.as_str().to_string(),
-                                    kind: Reader::rgb_fg(highlights["keywords"]).to_string(),
-                                    priority: false,
-                                },
-                                &mut syntax,
-                            );
-                        }
-                    }
-                } else {
-                    for exp in regex {
-                        // Locate expressions
-                        for cap in exp.captures_iter(row) {
-                            let cap = cap.get(cap.len().saturating_sub(1)).unwrap();
-                            let boundaries = bounds(&cap, &row);
-                            cine(
-                                &Token {
-                                    span: boundaries,
-                                    data: cap.as_str().to_string(),
-                                    kind: Reader::rgb_fg(highlights[name]).to_string(),
-                                    priority: false,
-                                },
-                                &mut syntax,
-                            );
-                        }
-                    }
+    // The strongest groups go last so that they replace the tokens they clash with
+    for exps in regex.iter().rev() {
+        let TokenType::SingleLine(name, regex) = exps else {
+            continue;
+        };
+        // Groups that the highlights don't give a style are left uncoloured
+        let kind = highlights.get(name).map(Reader::highlight_style);
+        if kind.is_none() && exps.capture_groups().is_empty() {
+            continue;
+        }
+        let kind = kind.unwrap_or_default();
+        let layered = LAYERED.contains(&name.as_str());
+        if name == "keywords" {
+            for kw in regex {
+                // Locate keywords
+                for cap in kw.captures_iter(row) {
+                    let cap = cap.get(cap.len().saturating_sub(1)).unwrap();
+                    let boundaries = bounds(&cap, row);
+                    cine(
+                        &Token {
+                            span: boundaries,
+                            data: cap.as_str().to_string(),
+                            kind: kind.clone(),
+                            priority: false,
+                            layered,
+                            group: name.clone(),
+                        },
+                        &mut syntax,
+                    );
                 }
             }
-            TokenType::MultiLine(name, regex) => {
-                // Multiline token
-                for exp in regex {
-                    for cap in exp.captures_iter(doc) {
-                        let cap = cap.get(cap.len().saturating_sub(1)).unwrap();
-                        let ((start_x, start_y), (end_x, end_y)) = multi_to_single(&doc, &cap);
-                        if start_y == index {
-                            cine(
-                                &Token {
-                                    span: (
-                                        start_x,
-                                        if start_y == end_y {
-                                            end_x
-                                        } else {
-                                            UnicodeWidthStr::width(row)
-                                        },
-                                    ),
-                                    data: row.to_string(),
-                                    kind: Reader::rgb_fg(highlights[name]).to_string(),
-                                    priority: true,
-                                },
-                                &mut syntax,
-                            )
-                        } else if end_y == index {
-                            cine(
-                                &Token {
-                                    span: (0, end_x),
-                                    data: row.to_string(),
-                                    kind: Reader::rgb_fg(highlights[name]).to_string(),
-                                    priority: true,
-                                },
-                                &mut syntax,
-                            )
-                        } else if (start_y..=end_y).contains(&index) {
-                            cine(
-                                &Token {
-                                    span: (0, UnicodeWidthStr::width(row)),
-                                    data: row.to_string(),
-                                    kind: Reader::rgb_fg(highlights[name]).to_string(),
-                                    priority: true,
-                                },
-                                &mut syntax,
-                            )
+        } else {
+            for exp in regex {
+                // Named groups are coloured by the highlight group they are named after
+                let groups: Vec<(&str, String)> = exp
+                    .capture_names()
+                    .flatten()
+                    .filter_map(|group| {
+                        Some((group, Reader::highlight_style(highlights.get(group)?)))
+                    })
+                    .collect();
+                let named = exp.capture_names().flatten().next().is_some();
+                // Locate expressions
+                for cap in exp.captures_iter(row) {
+                    if named {
+                        for (group, kind) in &groups {
+                            if let Some(part) = cap.name(group) {
+                                cine(
+                                    &Token {
+                                        span: bounds(&part, row),
+                                        data: part.as_str().to_string(),
+                                        kind: kind.clone(),
+                                        priority: false,
+                                        layered: LAYERED.contains(group),
+                                        group: group.to_string(),
+                                    },
+                                    &mut syntax,
+                                );
+                            }
                         }
+                        continue;
                     }
+                    let cap = cap.get(cap.len().saturating_sub(1)).unwrap();
+                    let boundaries = bounds(&cap, row);
+                    cine(
+                        &Token {
+                            span: boundaries,
+                            data: cap.as_str().to_string(),
+                            kind: kind.clone(),
+                            priority: false,
+                            layered,
+                            group: name.clone(),
+                        },
+                        &mut syntax,
+                    );
                 }
             }
         }
     }
+    // Multiline tokens were found beforehand, they win over the tokens they start on
+    for token in multiline {
+        cine(token, &mut syntax);
+    }
     syntax
 }
 
 pub fn remove_nested_tokens(tokens: &HashMap<usize, Token>, line: &str) -> HashMap<usize, Token> {
-    // Remove tokens within tokens
+    // Remove tokens within tokens, apart from layered ones
     let mut result = HashMap::new();
     let mut c = 0;
     while c < line.len() {
         if let Some(t) = tokens.get(&c) {
-            result.insert(t.span.0, t.clone());
+            // Layered tokens inside this one cut it into pieces around them
+            let mut rest = t.clone();
+            for i in t.span.0 + 1..t.span.1 {
+                if let Some(inner) = tokens.get(&i) {
+                    if inner.layered && i >= rest.span.0 && inner.span.1 <= t.span.1 {
+                        let mut piece = rest.clone();
+                        piece.span.1 = i;
+                        result.insert(piece.span.0, piece);
+                        result.insert(i, inner.clone());
+                        rest.span.0 = inner.span.1;
+                    }
+                }
+            }
+            if rest.span.0 < rest.span.1 {
+                result.insert(rest.span.0, rest);
+            }
             c += t.span.1 - t.span.0;
         } else {
             c += 1;
@@ -160,3 +398,187 @@ pub fn remove_nested_tokens(tokens: &HashMap<usize, Token>, line: &str) -> HashM
     }
     result
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn language() -> (Vec<TokenType>, HashMap<String, HighlightStyle>) {
+        // Block comments and strings that can span lines, with keywords on top
+        let exp = |expr: &str| vec![Regex::new(expr).unwrap()];
+        let regex = vec![
+            TokenType::SingleLine("keywords".to_string(), exp(r"\b(fn|let)\b")),
+            TokenType::MultiLine("strings".to_string(), exp(r#"(?s)".*?""#)),
+            TokenType::MultiLine("comments".to_string(), exp(r"(?s)/\*.*?\*/")),
+        ];
+        let style = |fg| HighlightStyle {
+            fg,
+            bg: None,
+            bold: false,
+            italic: false,
+            underline: false,
+        };
+        let highlights = [("keywords", 1), ("strings", 2), ("comments", 3)]
+            .iter()
+            .map(|&(name, fg)| (name.to_string(), style((fg, fg, fg))))
+            .collect();
+        (regex, highlights)
+    }
+
+    fn groups(highlighter: &Highlighter, lines: usize) -> Vec<Vec<(usize, usize, String)>> {
+        // The tokens on each line, in order
+        (0..lines)
+            .map(|y| {
+                let mut tokens: Vec<_> = highlighter
+                    .line(y)
+                    .unwrap()
+                    .values()
+                    .map(|t| (t.span.0, t.span.1, t.group.clone()))
+                    .collect();
+                tokens.sort();
+                tokens
+            })
+            .collect()
+    }
+
+    #[test]
+    fn edits_match_highlighting_from_scratch() {
+        type Edit = dyn Fn(&mut Vec<Row>);
+        let (regex, highlights) = language();
+        let mut rows: Vec<Row> = (0..30)
+            .map(|i| Row::from(format!("let x{i} = \"s\"; fn f() {{}}").as_str()))
+            .collect();
+        let edits: [&Edit; 7] = [
+            // Open a comment that runs to the end, then close it further down
+            &|rows| rows[5] = Row::from("/* let"),
+            &|rows| rows[12] = Row::from("fn */ let"),
+            // Add and remove lines in the middle of the comment and below it
+            &|rows| rows.insert(8, Row::from("inside")),
+            &|rows| {
+                rows.remove(20);
+            },
+            // An unclosed string swallows the lines up to the next quote
+            &|rows| rows[2] = Row::from("let s = \"open"),
+            &|rows| rows[2] = Row::from("let s = 1;"),
+            &|rows| rows[12] = Row::from("fn f() {}"),
+        ];
+        let mut highlighter = Highlighter::default();
+        highlighter.update_until_viewport(&rows, &regex, &highlights, 25);
+        for edit in &edits {
+            edit(&mut rows);
+            highlighter.update_until_viewport(&rows, &regex, &highlights, 25);
+            let mut fresh = Highlighter::default();
+            fresh.update_until_viewport(&rows, &regex, &highlights, 25);
+            assert_eq!(groups(&highlighter, 25), groups(&fresh, 25));
+        }
+    }
+
+    #[test]
+    fn comments_are_found_across_lines() {
+        let (regex, highlights) = language();
+        let rows: Vec<Row> = ["let a /* one", "two", "three */ let", "let"]
+            .iter()
+            .map(|&line| Row::from(line))
+            .collect();
+        let mut highlighter = Highlighter::default();
+        highlighter.update_until_viewport(&rows, &regex, &highlights, 4);
+        let comment = |from, to| (from, to, "comments".to_string());
+        let keyword = |from, to| (from, to, "keywords".to_string());
+        assert_eq!(
+            groups(&highlighter, 4),
+            vec![
+                vec![keyword(0, 3), comment(6, 12)],
+                vec![comment(0, 3)],
+                vec![comment(0, 8), keyword(9, 12)],
+                vec![keyword(0, 3)],
+            ]
+        );
+    }
+
+    #[test]
+    fn random_edits_match_highlighting_from_scratch() {
+        let (regex, highlights) = language();
+        let pieces = ["/*", "*/", "\"", "let", "x", " ", "fn"];
+        let mut seed: u64 = 7;
+        let mut random = |limit: usize| {
+            seed = seed.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1);
+            (seed >> 33) as usize % limit
+        };
+        let mut rows: Vec<Row> = (0..40).map(|_| Row::from("let x = 1;")).collect();
+        let mut highlighter = Highlighter::default();
+        let mut end = 20;
+        for _ in 0..500 {
+            // Edits are made where the cursor is, which is on the highlighted part
+            let y = random(rows.len().min(end));
+            let line: String = (0..random(5))
+                .map(|_| pieces[random(pieces.len())])
+                .collect();
+            match random(4) {
+                0 if rows.len() > 1 => {
+                    rows.remove(y);
+                }
+                1 => rows.insert(y, Row::from(line.as_str())),
+                _ => rows[y] = Row::from(line.as_str()),
+            }
+            end = 10 + random(25);
+            highlighter.update_until_viewport(&rows, &regex, &highlights, end);
+            let mut fresh = Highlighter::default();
+            fresh.update_until_viewport(&rows, &regex, &highlights, end);
+            let lines = end.min(rows.len());
+            assert_eq!(groups(&highlighter, lines), groups(&fresh, lines));
+        }
+    }
+
+    fn tokens(row: &str, regex: &[TokenType]) -> Vec<(usize, usize, String)> {
+        // The tokens a single line is highlighted with, in order
+        let (_, mut highlights) = language();
+        for group in ["attributes", "functions"] {
+            highlights.insert(group.to_string(), highlights["keywords"]);
+        }
+        let found = highlight(row, regex, &highlights, &[]);
+        let mut tokens: Vec<_> = remove_nested_tokens(&found, row)
+            .values()
+            .map(|t| (t.span.0, t.span.1, t.group.clone()))
+            .collect();
+        tokens.sort();
+        tokens
+    }
+
+    #[test]
+    fn stronger_groups_win_where_tokens_overlap() {
+        let exp = |name: &str, expr: &str| {
+            TokenType::SingleLine(name.to_string(), vec![Regex::new(expr).unwrap()])
+        };
+        let keywords = exp("keywords", r"\b(define|let)\b");
+        let comments = exp("comments", r"(#.*)");
+        let attributes = exp("attributes", r"(#\w+)");
+        let token = |from, to, group: &str| (from, to, group.to_string());
+        // Tokens that start together go to the stronger group
+        let row = "#define X let";
+        assert_eq!(
+            tokens(
+                row,
+                &[comments.clone(), attributes.clone(), keywords.clone()]
+            ),
+            vec![token(0, 13, "comments")]
+        );
+        assert_eq!(
+            tokens(row, &[attributes, comments, keywords]),
+            vec![token(0, 7, "attributes"), token(10, 13, "keywords")]
+        );
+    }
+
+    #[test]
+    fn named_groups_pick_their_own_colours() {
+        let regex = vec![TokenType::SingleLine(
+            "definitions".to_string(),
+            vec![Regex::new(r"(?P<keywords>fn)\s+(?P<functions>\w+)").unwrap()],
+        )];
+        assert_eq!(regex[0].capture_groups(), vec!["keywords", "functions"]);
+        let token = |from, to, group: &str| (from, to, group.to_string());
+        assert_eq!(
+            tokens("pub fn main() {}", &regex),
+            vec![token(4, 6, "keywords"), token(7, 11, "functions")]
+        );
+    }
+}
@@ -20,23 +20,29 @@
 */
 
 // Bring in the external modules
+mod clipboard;
 mod config;
 mod document;
 mod editor;
+mod editorconfig;
+mod finder;
 mod highlight;
 mod oxa;
+mod pane;
 mod row;
+mod search;
+mod session;
 mod terminal;
 mod undo;
 mod util;
 
 use clap::{App, Arg};
-use directories::BaseDirs;
+use config::Reader;
 use document::Document;
 use editor::{Direction, Editor, Position};
 use row::Row;
 use std::time::Duration;
-use std::{env, panic, thread};
+use std::{env, panic, process, thread};
 use terminal::{Size, Terminal};
 use undo::{Event, EventStack};
 
@@ -46,7 +52,6 @@ pub const VERSION: &str = env!("CARGO_PKG_VERSION");
 fn main() {
     // Attempt to start an editor instance
     let result = panic::catch_unwind(|| {
-        let config_dir = load_config().unwrap_or_else(|| " ~/.config/ox/ox.ron".to_string());
         // Gather the command line arguments
         let cli = App::new("Ox")
             .version(VERSION)
@@ -63,11 +68,43 @@ fn main() {
                     .long("config")
                     .short("c")
                     .takes_value(true)
-                    .default_value(&config_dir)
-                    .help("The directory of the config file"),
+                    .help("The directory of the config file (searched for if not given)"),
+            )
+            .arg(
+                Arg::with_name("mime-type")
+                    .long("mime-type")
+                    .short("m")
+                    .takes_value(true)
+                    .help("The MIME type of the files you wish to edit, for syntax highlighting"),
+            )
+            .arg(
+                Arg::with_name("generate-config")
+                    .long("generate-config")
+                    .help("Write the default config to the config file path and exit"),
+            )
+            .arg(
+                Arg::with_name("force")
+                    .long("force")
+                    .requires("generate-config")
+                    .help("Overwrite an existing config file when generating one"),
             );
+        let args = cli.get_matches();
+        // Write out a starter config if one was asked for
+        if args.is_present("generate-config") {
+            let path = args
+                .value_of("config")
+                .map_or_else(Reader::user_location, ToString::to_string);
+            match Reader::write_default(&path, args.is_present("force")) {
+                Ok(()) => println!("Wrote the default config to {path}"),
+                Err(error) => {
+                    eprintln!("Couldn't write the default config: {error}");
+                    process::exit(1);
+                }
+            }
+            return;
+        }
         // Fire up the editor, ensuring that no start up problems occured
-        if let Ok(mut editor) = Editor::new(cli) {
+        if let Ok(mut editor) = Editor::new(&args) {
             editor.run();
         }
     });
@@ -77,12 +114,3 @@ fn main() {
         thread::sleep(Duration::from_secs(3));
     }
 }
-
-fn load_config() -> Option<String> {
-    // Load the configuration file
-    let base_dirs = BaseDirs::new()?;
-    Some(format!(
-        "{}/ox/ox.ron",
-        base_dirs.config_dir().to_str()?.to_string()
-    ))
-}
@@ -7,10 +7,58 @@
 
     An example usage could be writing a macro to delete the current line
 */
+use crate::document::SelectionMode;
+use crate::pane::Orientation;
 use crate::undo::BankType;
-use crate::util::line_offset;
+use crate::util::{line_offset, CaseMode};
 use crate::{Direction, Event, Position, Row};
 
+// The instructions that interpret_line understands
+const INSTRUCTIONS: &[&str] = &[
+    "new",
+    "open",
+    "find",
+    "undo",
+    "commit",
+    "redo",
+    "quit",
+    "prev",
+    "next",
+    "switch",
+    "pane",
+    "set",
+    "split",
+    "splice",
+    "search",
+    "cmd",
+    "select",
+    "comment",
+    "copy",
+    "cut",
+    "paste",
+    "duplicate",
+    "case",
+    "sort",
+    "replace",
+    "theme",
+    "line",
+    "save",
+    "goto",
+    "move",
+    "put",
+    "delete",
+    "load",
+    "store",
+    "overwrite",
+];
+
+pub fn is_instruction(line: &str) -> bool {
+    // Check if a line starts with an instruction that can be interpreted
+    line.split(' ')
+        .next()
+        .is_some_and(|instruction| INSTRUCTIONS.contains(&instruction))
+}
+
 pub fn interpret_line(
     line: &str,
     cursor: &Position,
@@ -25,17 +73,32 @@ pub fn interpret_line(
         match instruction {
             "new" => events.push(Event::New),
             "open" => events.push(open_command(&args)),
+            "find" => events.push(Event::Find),
             "undo" => events.push(Event::Undo),
             "commit" => events.push(Event::Commit),
-            "redo" => events.push(Event::Redo),
+            "redo" => events.push(redo_command(&args)),
             "quit" => events.push(quit_command(&args)),
             "prev" => events.push(Event::PrevTab),
             "next" => events.push(Event::NextTab),
+            "switch" => events.push(Event::Switch),
+            "pane" => {
+                if let Some(pane) = pane_command(&args) {
+                    events.push(pane);
+                } else {
+                    return None;
+                }
+            }
             "set" => events.push(set_command(&args, &cursor, &rows)),
             "split" => events.push(Event::SplitDown(*cursor, *cursor)),
             "splice" => events.push(Event::SpliceUp(*cursor, *cursor)),
             "search" => events.push(Event::Search),
             "cmd" => events.push(Event::Cmd),
+            "select" => events.push(select_command(&args)),
+            "comment" => events.push(Event::Comment),
+            "copy" => events.push(Event::Copy),
+            "cut" => events.push(Event::Cut),
+            "paste" => events.push(Event::Paste),
+            "duplicate" => events.push(Event::Duplicate),
             "replace" => events.push(replace_command(&args)),
             "theme" => {
                 if let Some(theme) = theme_command(&args) {
@@ -44,6 +107,14 @@ pub fn interpret_line(
                     return None;
                 }
             }
+            "sort" => events.push(sort_command(&args)),
+            "case" => {
+                if let Some(case) = case_command(&args) {
+                    events.push(case);
+                } else {
+                    return None;
+                }
+            }
             "line" => {
                 if let Some(line) = line_command(&args, &cursor) {
                     events.push(line);
@@ -82,6 +153,39 @@ fn theme_command(args: &[&str]) -> Option<Event> {
     }
 }
 
+fn pane_command(args: &[&str]) -> Option<Event> {
+    match args.first() {
+        Some(&"horizontal") => Some(Event::SplitPane(Orientation::Horizontal)),
+        Some(&"vertical") => Some(Event::SplitPane(Orientation::Vertical)),
+        Some(&"close") => Some(Event::ClosePane),
+        Some(&"next") => Some(Event::NextPane),
+        _ => None,
+    }
+}
+
+fn sort_command(args: &[&str]) -> Event {
+    Event::Sort(args.contains(&"length"), args.contains(&"desc"))
+}
+
+fn case_command(args: &[&str]) -> Option<Event> {
+    Some(Event::ConvertCase(match args.first() {
+        Some(&"upper") => CaseMode::Upper,
+        Some(&"lower") => CaseMode::Lower,
+        Some(&"title") => CaseMode::Title,
+        Some(&"snake") => CaseMode::Snake,
+        Some(&"camel") => CaseMode::Camel,
+        _ => return None,
+    }))
+}
+
+fn select_command(args: &[&str]) -> Event {
+    if args.first() == Some(&"column") {
+        Event::Select(SelectionMode::Column)
+    } else {
+        Event::Select(SelectionMode::Normal)
+    }
+}
+
 fn replace_command(args: &[&str]) -> Event {
     if !args.is_empty() && args[0] == "*" {
         Event::ReplaceAll
@@ -151,6 +255,18 @@ fn overwrite_command(args: &[&str], rows: &[Row]) -> Option<Vec<Event>> {
     }])
 }
 
+fn redo_command(args: &[&str]) -> Event {
+    // Redo the last used branch, prompt for one or pick one by its number
+    match args.first() {
+        Some(&"?") => Event::RedoBranch(None),
+        Some(branch) => match branch.parse::<usize>() {
+            Ok(branch @ 1..) => Event::RedoBranch(Some(branch - 1)),
+            _ => Event::Redo,
+        },
+        None => Event::Redo,
+    }
+}
+
 fn save_command(args: &[&str]) -> Option<Vec<Event>> {
     let mut events = vec![];
     if args.is_empty() {
@@ -211,6 +327,7 @@ fn goto_command(args: &[&str]) -> Option<Vec<Event>> {
     let mut events = vec![];
     match args.len() {
         0 => events.push(Event::GotoCursor(Position { x: 0, y: 0 })),
+        1 if args[0] == "?" => events.push(Event::GotoLine),
         1 => {
             if let Ok(y) = args[0].parse::<usize>() {
                 events.push(Event::GotoCursor(Position {
@@ -269,6 +386,12 @@ fn move_command(args: &[&str]) -> Option<Vec<Event>> {
                     _ => return None,
                 },
             ));
+        } else if args[0] == "line" {
+            events.push(Event::MoveLines(match args[1] {
+                "up" => Direction::Up,
+                "down" => Direction::Down,
+                _ => return None,
+            }));
         } else if args[0] == "word" {
             events.push(Event::MoveWord(match args[1] {
                 "left" => Direction::Left,
@@ -309,6 +432,12 @@ fn delete_command(
         {
             events.push(Event::Deletion(*cursor, *ch));
         }
+    } else if args[0] == "word" {
+        events.push(Event::DeleteWord(match args.get(1) {
+            Some(&"left") => Direction::Left,
+            Some(&"right") => Direction::Right,
+            _ => return None,
+        }));
     } else if let Ok(line) = args[0].parse::<i128>() {
         events.push(Event::DeleteLine(
             *cursor,
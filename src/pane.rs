@@ -0,0 +1,130 @@
+// Pane.rs - For showing several documents, or parts of one, at the same time
+use crate::Position;
+
+// The way the screen is split between panes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Orientation {
+    Horizontal, // Panes are stacked on top of each other
+    Vertical,   // Panes sit side by side
+}
+
+// A view onto a document with its own cursor and scroll position
+#[derive(Debug, Clone, Copy)]
+pub struct Pane {
+    pub doc: usize,       // The document shown in the pane
+    pub cursor: Position, // The cursor of the pane when it isn't focused
+    pub offset: Position, // The scroll position of the pane when it isn't focused
+}
+
+// The part of the screen that a pane is drawn in
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Area {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+// The panes on the screen and which one is being edited
+#[derive(Debug)]
+pub struct SplitPane {
+    pub panes: Vec<Pane>,         // The panes, from left to right or top to bottom
+    pub orientation: Orientation, // The way the screen is split
+    pub focus: usize,             // The pane that is being edited
+}
+
+impl SplitPane {
+    pub fn new(pane: Pane) -> Self {
+        // Start with one pane taking up the whole screen
+        Self {
+            panes: vec![pane],
+            orientation: Orientation::Vertical,
+            focus: 0,
+        }
+    }
+    pub fn split_horizontal(&mut self, pane: Pane) {
+        // Open a copy of the focused pane below it
+        self.split(pane, Orientation::Horizontal);
+    }
+    pub fn split_vertical(&mut self, pane: Pane) {
+        // Open a copy of the focused pane to the right of it
+        self.split(pane, Orientation::Vertical);
+    }
+    fn split(&mut self, pane: Pane, orientation: Orientation) {
+        // Splitting again in the other direction turns all the panes that way
+        self.orientation = orientation;
+        self.panes[self.focus] = pane;
+        self.focus += 1;
+        self.panes.insert(self.focus, pane);
+    }
+    pub fn close_pane(&mut self, idx: usize) -> bool {
+        // Close a pane, the last one can't be closed
+        if self.panes.len() == 1 || idx >= self.panes.len() {
+            return false;
+        }
+        self.panes.remove(idx);
+        if self.focus > idx || self.focus == self.panes.len() {
+            self.focus -= 1;
+        }
+        true
+    }
+    pub fn focus_next(&mut self) {
+        // Move to the next pane, going round to the first after the last
+        self.focus = (self.focus + 1) % self.panes.len();
+    }
+    pub fn remove_document(&mut self, doc: usize) {
+        // Close the unfocused panes showing a document that was closed and renumber the rest
+        let focused = self.focus;
+        let mut index = 0;
+        let mut before = 0;
+        self.panes.retain(|pane| {
+            let keep = index == focused || pane.doc != doc;
+            if !keep && index < focused {
+                before += 1;
+            }
+            index += 1;
+            keep
+        });
+        self.focus -= before;
+        for pane in &mut self.panes {
+            if pane.doc > doc {
+                pane.doc -= 1;
+            }
+        }
+    }
+    pub fn areas(&self, width: usize, height: usize) -> Vec<Area> {
+        // Share out the space for text between the panes, with a line between each
+        let count = self.panes.len();
+        let (length, across) = match self.orientation {
+            Orientation::Vertical => (width, height),
+            Orientation::Horizontal => (height, width),
+        };
+        let space = length.saturating_sub(count - 1);
+        let mut start = 0;
+        let mut result = vec![];
+        for i in 0..count {
+            // The last pane takes up whatever is left over
+            let size = if i + 1 == count {
+                space.saturating_sub(start - i)
+            } else {
+                space / count
+            };
+            result.push(match self.orientation {
+                Orientation::Vertical => Area {
+                    x: start,
+                    y: 0,
+                    width: size,
+                    height: across,
+                },
+                Orientation::Horizontal => Area {
+                    x: 0,
+                    y: start,
+                    width: across,
+                    height: size,
+                },
+            });
+            start += size + 1;
+        }
+        result
+    }
+}
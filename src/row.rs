@@ -1,13 +1,16 @@
 // Row.rs - Handling the rows of a document and their appearance
-use crate::config::{Reader, TokenType};
+use crate::config::{LineNumberMode, Reader, WhitespaceGlyphs};
 use crate::editor::RESET_FG;
-use crate::highlight::{highlight, remove_nested_tokens, Token};
+use crate::highlight::Token;
 use crate::util::Exp;
 use std::collections::HashMap;
-use termion::color;
+use termion::style;
 use unicode_segmentation::UnicodeSegmentation;
 use unicode_width::UnicodeWidthStr;
 
+// A run of columns drawn in its own colours: start, end, background and foreground
+pub type Span = (usize, usize, (u8, u8, u8), (u8, u8, u8));
+
 // Ensure we can use the Clone trait to copy row structs for manipulation
 #[derive(Debug, Clone)]
 pub struct Row {
@@ -28,39 +31,111 @@ impl From<&str> for Row {
     }
 }
 
+// The settings a row is drawn with
+pub struct RenderOptions<'a> {
+    pub start: usize,             // The first column of the row to draw
+    pub width: usize,             // The columns there are to draw in, line number included
+    pub index: usize,             // The line of the document that this row is
+    pub cursor: usize,            // The line of the document that the cursor is on
+    pub offset: usize,            // The width of the line number column
+    pub continued: bool,          // True for the wrapped parts of a line
+    pub spans: &'a [Span],        // Columns drawn in their own colours, earlier spans win
+    pub tab_width: Option<usize>, // Given when indentation is tabs, to draw it as tab glyphs
+}
+
+// For drawing the characters of a row in the colours of the spans and glyphs over them
+struct Painter<'a> {
+    spans: &'a [Span],            // Columns drawn in their own colours
+    show: bool,                   // True if whitespace is drawn as glyphs
+    glyphs: &'a WhitespaceGlyphs, // The glyphs that whitespace is drawn as
+    tabbed: usize,                // The columns taken up by tabs at the start of the line
+    tab_width: usize,             // The width of those tabs
+    whitespace: String,           // The colour that whitespace glyphs are drawn in
+    background: String,           // The background of the line
+}
+
+impl Painter<'_> {
+    fn glyph(&self, ch: &str, col: usize) -> Option<&str> {
+        // Whitespace can be swapped for glyphs
+        match ch {
+            _ if !self.show => None,
+            " " if col < self.tabbed => Some(if col.is_multiple_of(self.tab_width) {
+                self.glyphs.tab.as_str()
+            } else {
+                " "
+            }),
+            " " => Some(self.glyphs.space.as_str()),
+            "\t" => Some(self.glyphs.tab.as_str()),
+            _ => None,
+        }
+    }
+    fn paint(&self, ch: &str, col: usize, restore: &str) -> String {
+        // Draw a character in the colours of the span over it, or as a glyph
+        let shown = self.glyph(ch, col);
+        let span = self
+            .spans
+            .iter()
+            .find(|(from, to, _, _)| col >= *from && col < *to);
+        if let Some(&(_, _, bg, fg)) = span {
+            format!(
+                "{}{}{}{}{}",
+                Reader::rgb_bg(bg),
+                Reader::rgb_fg(fg),
+                shown.unwrap_or(ch),
+                self.background,
+                restore,
+            )
+        } else if let Some(shown) = shown {
+            format!("{}{shown}{restore}", self.whitespace)
+        } else {
+            ch.to_string()
+        }
+    }
+}
+
 // Add methods to the Row struct / class
 impl Row {
-    pub fn render(
-        &self,
-        mut start: usize,
-        width: usize,
-        index: usize,
-        offset: usize,
-        config: &Reader,
-    ) -> String {
+    pub fn render(&self, options: &RenderOptions, config: &Reader) -> String {
         // Render the row by trimming it to the correct size
-        let index = index.saturating_add(1);
-        // Padding to align line numbers to the right
-        let post_padding = offset.saturating_sub(
-            index.to_string().len() +         // Length of the number
-            config.general.line_number_padding_right + // Length of the right padding
-            config.general.line_number_padding_left, // Length of the left padding
-        );
-        // Assemble the line number data
-        let line_number = format!(
-            "{}{}{}{}{}{}",
-            Reader::rgb_fg(config.theme.line_number_fg),
-            " ".repeat(config.general.line_number_padding_left),
-            " ".repeat(post_padding),
-            index,
-            " ".repeat(config.general.line_number_padding_right),
-            Reader::rgb_fg(config.theme.editor_fg),
-        );
+        let mut start = options.start;
+        let first = start;
+        // The line the cursor is on stands out from the rest
+        let background = config
+            .theme
+            .line_background(options.index == options.cursor);
+        let line_number = Self::line_number(options, config);
         // Strip ANSI values from the line
         let line_number_len = self.regex.ansi_len(&line_number);
-        let width = width.saturating_sub(line_number_len);
-        let mut initial = start;
+        let width = options.width.saturating_sub(line_number_len);
         let mut result = String::new();
+        // Undo everything a syntax highlighting group can change at the end of a token
+        // 22m also ends bold text, termion's NoBold (21m) is double underline in some terminals
+        let unstyle = format!(
+            "{}{}{}{}{}",
+            RESET_FG,
+            background,
+            style::NoFaint,
+            style::NoItalic,
+            style::NoUnderline
+        );
+        // Whitespace can be swapped for glyphs, tab_width is given when indentation is tabs
+        let indent = self.string.len() - self.string.trim_start_matches(' ').len();
+        let painter = Painter {
+            spans: options.spans,
+            show: config.general.show_whitespace,
+            glyphs: &config.general.whitespace_glyphs,
+            tabbed: options
+                .tab_width
+                .map_or(0, |width| indent / width.max(1) * width),
+            tab_width: options.tab_width.unwrap_or(1),
+            whitespace: Reader::rgb_fg(
+                config
+                    .theme
+                    .whitespace_fg
+                    .unwrap_or(config.theme.line_number_fg),
+            ),
+            background,
+        };
         // Ensure that the render isn't impossible
         if width != 0 && start < UnicodeWidthStr::width(&self.string[..]) {
             // Calculate the character positions
@@ -77,6 +152,7 @@ impl Row {
                 result.push(' ');
                 start += 1;
             }
+            let restore = Reader::rgb_fg(config.theme.editor_fg);
             // Push across characters
             'a: while start < end {
                 if let Some(t) = self.syntax.get(&start) {
@@ -89,75 +165,97 @@ impl Row {
                                 result.push(' ');
                                 break 'a;
                             }
-                            result.push_str(ch);
+                            result.push_str(&painter.paint(ch, start, &t.kind));
                             start += UnicodeWidthStr::width(*ch);
                         } else {
                             break 'a;
                         }
                     }
-                    result.push_str(&color::Fg(color::Reset).to_string());
+                    result.push_str(&unstyle);
                 } else if let Some(ch) = dna.get(&start) {
                     // There is a character here
                     if start + UnicodeWidthStr::width(*ch) > end {
                         result.push(' ');
                         break 'a;
                     }
-                    result.push_str(ch);
+                    result.push_str(&painter.paint(ch, start, &restore));
                     start += UnicodeWidthStr::width(*ch);
                 } else {
                     // The quota has been used up
                     break 'a;
                 }
             }
-            // Correct colourization of tokens that are half off the screen and half on the screen
-            let initial_initial = initial; // Terrible variable naming, I know
-            if initial > 0 {
-                // Calculate the last token start boundary
-                while self.syntax.get(&initial).is_none() && initial > 0 {
-                    initial -= 1;
-                }
-                // Verify that the token actually exists
-                if let Some(t) = self.syntax.get(&initial) {
-                    // Verify that the token isn't up against the far left side
-                    if t.span.0 != initial_initial && t.span.1 >= initial_initial {
-                        // Insert the correct colours
-                        let mut real = 0;
-                        let mut ch = 0;
-                        for i in result.graphemes(true) {
-                            if ch == t.span.1 - initial_initial {
-                                break;
-                            }
-                            real += i.len();
-                            ch += UnicodeWidthStr::width(i);
-                        }
-                        result.insert_str(real, &RESET_FG.to_string());
-                        result.insert_str(0, &t.kind);
-                    }
-                }
-            }
+            self.colour_cut_token(&mut result, first, &unstyle);
+        }
+        // Mark the end of the line when it is on the screen
+        let length = UnicodeWidthStr::width(&self.string[..]);
+        if painter.show && first <= length && length < first + width {
+            result.push_str(&painter.whitespace);
+            result.push_str(&painter.glyphs.newline);
+            result.push_str(&Reader::rgb_fg(config.theme.editor_fg));
         }
         // Return the full line string to be rendered
         line_number + &result
     }
-    pub fn update_syntax(
-        &mut self,
-        config: &Reader,
-        syntax: &[TokenType],
-        doc: &str,
-        index: usize,
-        theme: &str,
-    ) {
-        // Update the syntax highlighting indices for this row
-        self.syntax = remove_nested_tokens(
-            &highlight(
-                &self.string,
-                &doc,
-                index,
-                &syntax,
-                &config.highlights[theme],
-            ),
-            &self.string,
+    fn line_number(options: &RenderOptions, config: &Reader) -> String {
+        // Work out the number to show next to the line
+        let (index, cursor) = (options.index, options.cursor);
+        let index = if options.continued {
+            // Wrapped parts of a line get an indicator instead
+            "\u{21aa}".to_string()
+        } else {
+            match config.general.line_number_mode {
+                LineNumberMode::Relative => index.abs_diff(cursor),
+                LineNumberMode::Hybrid if index != cursor => index.abs_diff(cursor),
+                _ => index.saturating_add(1),
+            }
+            .to_string()
+        };
+        // Padding to align line numbers to the right
+        let post_padding = options.offset.saturating_sub(
+            UnicodeWidthStr::width(&index[..]) + // Length of the number
+            config.general.line_number_padding_right + // Length of the right padding
+            config.general.line_number_padding_left, // Length of the left padding
         );
+        // Assemble the line number data
+        format!(
+            "{}{}{}{}{}{}",
+            Reader::rgb_fg(config.theme.line_number_fg),
+            " ".repeat(config.general.line_number_padding_left),
+            " ".repeat(post_padding),
+            index,
+            " ".repeat(config.general.line_number_padding_right),
+            Reader::rgb_fg(config.theme.editor_fg),
+        )
+    }
+    fn colour_cut_token(&self, result: &mut String, first: usize, unstyle: &str) {
+        // Correct colourization of tokens that are half off the screen and half on the screen
+        if first == 0 {
+            return;
+        }
+        // Calculate the last token start boundary
+        let mut initial = first;
+        while !self.syntax.contains_key(&initial) && initial > 0 {
+            initial -= 1;
+        }
+        // Verify that the token actually exists
+        if let Some(t) = self.syntax.get(&initial) {
+            // Verify that the token isn't up against the far left side
+            if t.span.0 != first && t.span.1 >= first {
+                // Insert the correct colours
+                let mut real = 0;
+                let mut ch = 0;
+                for i in result.graphemes(true) {
+                    if ch == t.span.1 - first {
+                        break;
+                    }
+                    real += i.len();
+                    ch += UnicodeWidthStr::width(i);
+                }
+                result.insert_str(real, unstyle);
+                result.insert_str(0, &t.kind);
+            }
+        }
     }
     pub fn length(&self) -> usize {
         // Get the current length of the row
@@ -193,6 +291,17 @@ impl Row {
         }
         result
     }
+    pub fn byte_index(&self, column: usize) -> usize {
+        // Find where the character at a display column starts in the string
+        let mut width = 0;
+        for (i, ch) in self.string.grapheme_indices(true) {
+            if width >= column {
+                return i;
+            }
+            width += UnicodeWidthStr::width(ch);
+        }
+        self.string.len()
+    }
     pub fn insert(&mut self, ch: char, pos: usize) {
         // Insert a character
         let mut before: String = self.string.graphemes(true).take(pos as usize).collect();
@@ -0,0 +1,282 @@
+// Search.rs - For finding and replacing text within a document
+use crate::Row;
+use regex::{NoExpand, Regex, RegexBuilder};
+use unicode_width::UnicodeWidthStr;
+
+// The options chosen in the replace prompt
+#[derive(Debug, Clone)]
+pub struct SearchReplace {
+    pub pattern: String,      // The text or expression to look for
+    pub replacement: String,  // What to put in place of each match
+    pub use_regex: bool,      // Treat the pattern as a regular expression
+    pub case_sensitive: bool, // Tell upper and lower case letters apart
+    pub whole_word: bool,     // Only match the pattern on word boundaries
+}
+
+// A compiled search that can find and replace matches
+#[derive(Debug)]
+pub struct SearchEngine {
+    regex: Regex,        // The expression built from the options
+    replacement: String, // What to put in place of each match
+    expand: bool,        // True if $1 style capture references are filled in
+}
+
+impl Default for SearchReplace {
+    fn default() -> Self {
+        // Patterns are regular expressions that match case by default
+        Self {
+            pattern: String::new(),
+            replacement: String::new(),
+            use_regex: true,
+            case_sensitive: true,
+            whole_word: false,
+        }
+    }
+}
+
+impl SearchReplace {
+    pub fn describe(&self) -> String {
+        // List the options that are switched on, for showing in the prompt
+        let mut options = vec![];
+        if self.use_regex {
+            options.push("regex");
+        }
+        if self.case_sensitive {
+            options.push("match case");
+        }
+        if self.whole_word {
+            options.push("whole word");
+        }
+        if options.is_empty() {
+            options.push("plain text");
+        }
+        options.join(", ")
+    }
+    pub fn toggle(&mut self, key: char) {
+        // Switch an option on or off from the prompt, Alt + R, C or W
+        match key {
+            'r' => self.use_regex = !self.use_regex,
+            'c' => self.case_sensitive = !self.case_sensitive,
+            'w' => self.whole_word = !self.whole_word,
+            _ => (),
+        }
+    }
+    pub fn engine(&self) -> Result<SearchEngine, regex::Error> {
+        // Build the expression that these options describe
+        let pattern = if self.use_regex {
+            self.pattern.clone()
+        } else {
+            regex::escape(&self.pattern)
+        };
+        let pattern = if self.whole_word {
+            // A side that starts or ends with punctuation has no word boundary to match
+            let (start, end) = self.word_edges();
+            format!(
+                "{}(?:{pattern}){}",
+                if start { r"\b" } else { "" },
+                if end { r"\b" } else { "" }
+            )
+        } else {
+            pattern
+        };
+        Ok(SearchEngine {
+            regex: RegexBuilder::new(&pattern)
+                .case_insensitive(!self.case_sensitive)
+                .build()?,
+            replacement: self.replacement.clone(),
+            expand: self.use_regex,
+        })
+    }
+    fn word_edges(&self) -> (bool, bool) {
+        // Work out whether the pattern starts and ends with a word character
+        let is_word = |c: char| c.is_alphanumeric() || c == '_';
+        let chars: Vec<char> = self.pattern.chars().collect();
+        if !self.use_regex {
+            return (
+                chars.first().is_none_or(|&c| is_word(c)),
+                chars.last().is_none_or(|&c| is_word(c)),
+            );
+        }
+        // Regex syntax at either end is assumed to match a word, escaped punctuation isn't
+        let literal = |c: char, escaped: bool| {
+            if escaped {
+                c.is_alphanumeric()
+            } else {
+                is_word(c) || "\\.+*?()|[]{}^$".contains(c)
+            }
+        };
+        let start = match chars.as_slice() {
+            ['\\', c, ..] => literal(*c, true),
+            [c, ..] => literal(*c, false),
+            [] => true,
+        };
+        let end = match chars.as_slice() {
+            [.., '\\', c] => literal(*c, true),
+            [.., c] => literal(*c, false),
+            [] => true,
+        };
+        (start, end)
+    }
+}
+
+impl SearchEngine {
+    pub fn regex(&self) -> &Regex {
+        // Get the expression, for highlighting the matches
+        &self.regex
+    }
+    pub fn find_all(&self, rows: &[Row]) -> Vec<(usize, usize, usize)> {
+        // Find every match as a line with its start and end display columns
+        let mut result = vec![];
+        for (y, row) in rows.iter().enumerate() {
+            for m in self.regex.find_iter(&row.string) {
+                let start = UnicodeWidthStr::width(&row.string[..m.start()]);
+                result.push((y, start, start + UnicodeWidthStr::width(m.as_str())));
+            }
+        }
+        result
+    }
+    pub fn replace_one(&self, row: &Row, column: usize) -> Option<(Row, usize)> {
+        // Replace the match that starts at a display column, also giving the column after it
+        let string = &row.string;
+        let caps = self.regex.captures_iter(string).find(|c| {
+            c.get(0)
+                .is_some_and(|m| UnicodeWidthStr::width(&string[..m.start()]) == column)
+        })?;
+        let whole = caps.get(0)?;
+        let mut result = string[..whole.start()].to_string();
+        if self.expand {
+            caps.expand(&self.replacement, &mut result);
+        } else {
+            result.push_str(&self.replacement);
+        }
+        let end = UnicodeWidthStr::width(result.as_str());
+        result.push_str(&string[whole.end()..]);
+        Some((Row::from(result.as_str()), end))
+    }
+    pub fn replace_all(&self, rows: &[Row]) -> (Vec<(usize, Row)>, usize) {
+        // Replace every match, returning the lines that changed and the number of matches
+        let mut changed = vec![];
+        let mut count = 0;
+        for (y, row) in rows.iter().enumerate() {
+            let matches = self.regex.find_iter(&row.string).count();
+            if matches == 0 {
+                continue;
+            }
+            count += matches;
+            let after = if self.expand {
+                self.regex
+                    .replace_all(&row.string, self.replacement.as_str())
+            } else {
+                self.regex
+                    .replace_all(&row.string, NoExpand(&self.replacement))
+            };
+            if after != row.string {
+                changed.push((y, Row::from(&*after)));
+            }
+        }
+        (changed, count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn search(pattern: &str, replacement: &str) -> SearchReplace {
+        // A regex search that matches case
+        SearchReplace {
+            pattern: pattern.to_string(),
+            replacement: replacement.to_string(),
+            ..SearchReplace::default()
+        }
+    }
+
+    fn rows(lines: &[&str]) -> Vec<Row> {
+        // Turn lines of text into rows
+        lines.iter().map(|&line| Row::from(line)).collect()
+    }
+
+    #[test]
+    fn options_are_toggled_and_described() {
+        let mut options = search("", "");
+        assert_eq!(options.describe(), "regex, match case");
+        options.toggle('w');
+        options.toggle('c');
+        assert_eq!(options.describe(), "regex, whole word");
+        options.toggle('w');
+        options.toggle('r');
+        assert_eq!(options.describe(), "plain text");
+    }
+
+    #[test]
+    fn matches_are_found_by_display_column() {
+        let engine = search(r"\d+", "").engine().unwrap();
+        let found = engine.find_all(&rows(&["a 12 b 3", "", "\u{65e5}\u{672c} 45"]));
+        assert_eq!(found, vec![(0, 2, 4), (0, 7, 8), (2, 5, 7)]);
+    }
+
+    #[test]
+    fn plain_text_is_escaped() {
+        let mut options = search("a.b", "");
+        options.use_regex = false;
+        let found = options.engine().unwrap().find_all(&rows(&["axb a.b"]));
+        assert_eq!(found, vec![(0, 4, 7)]);
+    }
+
+    #[test]
+    fn case_can_be_ignored() {
+        let mut options = search("ox", "");
+        options.case_sensitive = false;
+        let found = options.engine().unwrap().find_all(&rows(&["Ox OX ox"]));
+        assert_eq!(found.len(), 3);
+        assert!(search("ox", "")
+            .engine()
+            .unwrap()
+            .find_all(&rows(&["OX"]))
+            .is_empty());
+    }
+
+    #[test]
+    fn whole_words_are_matched() {
+        let mut options = search("cat", "");
+        options.whole_word = true;
+        let found = options
+            .engine()
+            .unwrap()
+            .find_all(&rows(&["cat concat cat_ cat."]));
+        assert_eq!(found, vec![(0, 0, 3), (0, 16, 19)]);
+        // Punctuation at the edge of a pattern has no word boundary to match
+        let mut options = search("$x", "");
+        options.use_regex = false;
+        options.whole_word = true;
+        let found = options.engine().unwrap().find_all(&rows(&["a $x b$x"]));
+        assert_eq!(found, vec![(0, 2, 4), (0, 6, 8)]);
+    }
+
+    #[test]
+    fn one_match_is_replaced() {
+        let engine = search(r"(\w+)@(\w+)", "$2 at $1").engine().unwrap();
+        let (row, end) = engine.replace_one(&Row::from("a@b c@d"), 4).unwrap();
+        assert_eq!(row.string, "a@b d at c");
+        assert_eq!(end, 10);
+        assert!(engine.replace_one(&Row::from("a@b c@d"), 1).is_none());
+    }
+
+    #[test]
+    fn every_match_is_replaced() {
+        let engine = search("o", "0").engine().unwrap();
+        let (changed, count) = engine.replace_all(&rows(&["foo", "bar", "box"]));
+        assert_eq!(count, 3);
+        let changed: Vec<_> = changed
+            .iter()
+            .map(|(y, row)| (*y, row.string.as_str()))
+            .collect();
+        assert_eq!(changed, vec![(0, "f00"), (2, "b0x")]);
+        // Without regex the replacement is used as it is written
+        let mut options = search("(o)", "$1");
+        options.use_regex = false;
+        let (changed, count) = options.engine().unwrap().replace_all(&rows(&["f(o)"]));
+        assert_eq!(count, 1);
+        assert_eq!(changed[0].1.string, "f$1");
+    }
+}
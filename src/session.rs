@@ -0,0 +1,52 @@
+// Session.rs - For reopening the files that were open last time
+use directories::BaseDirs;
+use ron::ser::{to_string_pretty, PrettyConfig};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::{fs, io};
+
+// The files that were open when Ox last quit, and where they were left
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Session {
+    #[serde(default)]
+    pub open_files: Vec<String>, // The full paths of the files, in tab order
+    #[serde(default)]
+    pub cursor_positions: HashMap<String, (usize, usize)>, // The column and line of each cursor
+    #[serde(default)]
+    pub scroll_offsets: HashMap<String, usize>, // The line at the top of the screen for each file
+}
+
+impl Session {
+    pub fn location() -> Option<PathBuf> {
+        // The session lives in the data folder, e.g. ~/.local/share/ox/session.ron
+        BaseDirs::new().map(|dirs| dirs.data_local_dir().join("ox").join("session.ron"))
+    }
+    pub fn load() -> Option<Self> {
+        // Read the last session, if there is one that can be understood
+        let contents = fs::read_to_string(Self::location()?).ok()?;
+        ron::de::from_str(&contents).ok()
+    }
+    pub fn save(&self) -> io::Result<()> {
+        // Write the session out for next time
+        let path = Self::location()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no data folder"))?;
+        if let Some(folder) = path.parent() {
+            fs::create_dir_all(folder)?;
+        }
+        let contents = to_string_pretty(self, PrettyConfig::new())
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error.to_string()))?;
+        fs::write(path, contents)
+    }
+    pub fn add(&mut self, path: &str, cursor: (usize, usize), scroll: usize) {
+        // Remember a file, skipping ones that haven't been saved anywhere yet
+        if let Ok(path) = fs::canonicalize(path) {
+            let path = path.to_string_lossy().to_string();
+            if !self.open_files.contains(&path) {
+                self.open_files.push(path.clone());
+            }
+            self.cursor_positions.insert(path.clone(), cursor);
+            self.scroll_offsets.insert(path, scroll);
+        }
+    }
+}
@@ -1,12 +1,15 @@
 // Terminal.rs - Handling low level terminal operations
 use crate::util::Exp;
 use crate::Position;
+use std::env;
 use std::io::{stdout, Error, Stdout, Write};
+use termion::input::MouseTerminal;
 use termion::raw::{IntoRawMode, RawTerminal};
 use termion::screen::AlternateScreen;
 use unicode_width::UnicodeWidthStr;
 
 // Struct to hold size
+#[derive(Clone, Copy, Debug)]
 pub struct Size {
     pub width: usize,
     pub height: usize,
@@ -18,6 +21,8 @@ pub struct Terminal {
     _stdout: RawTerminal<Stdout>,             // Ensures we're in raw mode for total control
     pub size: Size,                           // For holding the size of the terminal
     regex: Exp,                               // For holding the regex
+    cursor_colours: bool,                     // True if the cursor colour can be changed
+    mouse: Option<MouseTerminal<Stdout>>,     // Asks the terminal for mouse events while it's held
 }
 
 // Implement methods into the terminal struct / class
@@ -33,8 +38,50 @@ impl Terminal {
                 height: size.1 as usize,
             },
             regex: Exp::new(),
+            cursor_colours: Self::supports_cursor_colours(),
+            mouse: None,
         })
     }
+    fn supports_cursor_colours() -> bool {
+        // Guess from the environment whether the terminal understands cursor colours
+        let term = env::var("TERM").unwrap_or_default();
+        env::var("COLORTERM").is_ok_and(|colours| !colours.is_empty())
+            || [
+                "xterm",
+                "rxvt",
+                "alacritty",
+                "kitty",
+                "foot",
+                "tmux",
+                "screen",
+            ]
+            .iter()
+            .any(|name| term.starts_with(name))
+    }
+    pub fn set_cursor_colour(&mut self, colour: (u8, u8, u8)) {
+        // Change the colour of the cursor
+        if self.cursor_colours {
+            write!(
+                self.screen,
+                "\x1b]12;#{:02x}{:02x}{:02x}\x07",
+                colour.0, colour.1, colour.2
+            )
+            .unwrap();
+        }
+    }
+    pub fn reset_cursor_colour(&mut self) {
+        // Give the cursor back the terminal's own colour
+        if self.cursor_colours {
+            write!(self.screen, "\x1b]112\x07").unwrap();
+            self.flush();
+        }
+    }
+    pub fn set_mouse(&mut self, enabled: bool) {
+        // Start or stop reporting mouse events, dropping the wrapper stops them
+        if enabled != self.mouse.is_some() {
+            self.mouse = enabled.then(|| MouseTerminal::from(stdout()));
+        }
+    }
     pub fn goto(&mut self, p: &Position) {
         // Move the cursor to a position
         write!(
@@ -1,9 +1,12 @@
 // Undo.rs - Utilities for undoing, redoing and storing events
-use crate::util::line_offset;
+use crate::document::SelectionMode;
+use crate::pane::Orientation;
+use crate::util::{line_offset, CaseMode};
 use crate::{Direction, Position, Row};
+use std::time::{Duration, Instant};
 
 // Enum for the the types of banks
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy)]
 pub enum BankType {
     Line,   // For holding lines from the document
     Cursor, // For holding cursor positions
@@ -26,66 +29,234 @@ pub enum Event {
     UpdateLine(Position, i128, Box<Row>, Box<Row>), // For holding entire line updates
     MoveCursor(i128, Direction),                    // For moving the cursor
     GotoCursor(Position),                           // For setting the cursor position
+    GotoLine,                                       // Ask for a line to go to
     MoveWord(Direction),                            // Move cursor through words
     Theme(String),                                  // Theme change event
     Search,                                         // Search the document
     Replace,                                        // Replace certain occurances
     ReplaceAll,                                     // Replace everything
     Cmd,                                            // Trigger command mode
+    Select(SelectionMode),                          // Start or clear a selection
+    Comment,                                        // Toggle comments
+    Copy,                                           // Copy the selection or line
+    Cut,                                            // Cut the selection or line
+    Paste,                                          // Paste from the clipboard
+    Duplicate,                                      // Duplicate the selection or line
+    MoveLines(Direction),                           // Move the selected lines or current line
+    ConvertCase(CaseMode),                          // Change the case of the selection or word
+    Sort(bool, bool),                               // Sort lines, by length and descending
+    DeleteWord(Direction),                          // Delete to the next or previous word boundary
     Home,                                           // Moving cursor to the start of line
     End,                                            // Moving cursor to the end of line
     PageUp,                                         // Moving cursor one page up
     PageDown,                                       // Moving cursor one page down
     Overwrite(Vec<Row>, Vec<Row>),                  // Overwrite document
+    FinalNewline(bool, bool),                       // Add or remove the final newline
     New,                                            // New document
     Open(Option<String>),                           // Open document
+    Find,                                           // Find a file to open
+    Switch,                                         // Pick an open document to switch to
     Save(Option<String>, bool),                     // Save document
     SaveAll,                                        // Save all documents
     Undo,                                           // Undo event
     Redo,                                           // Redo event
+    RedoBranch(Option<usize>),                      // Redo a specific branch
     Commit,                                         // Commit undo event
     Quit(bool),                                     // Quit document
     QuitAll(bool),                                  // Quit all
     NextTab,                                        // Next tab
     PrevTab,                                        // Previous tab
+    SplitPane(Orientation),                         // Show the document in another pane
+    ClosePane,                                      // Close the focused pane
+    NextPane,                                       // Focus the next pane
+}
+
+// A patch of events that are undone and redone together
+pub type Patch = Vec<Event>;
+
+// A node in the undo tree, holding the patch that led to it from its parent
+#[derive(Debug)]
+struct Node<T> {
+    patch: Option<T>,      // The change made here, the root has none
+    parent: Option<usize>, // The node this change was made on top of
+    children: Vec<usize>,  // The changes made on top of this one, oldest first
+    active: usize,         // The child that redo goes to
+}
+
+// For holding every version of a document, undoing and then editing starts a new branch
+#[derive(Debug)]
+pub struct UndoTree<T> {
+    nodes: Vec<Node<T>>, // For storing every node, the root is at index 0
+    current: usize,      // For holding the node the document is currently at
+}
+
+// Methods for the UndoTree
+impl<T: Clone> UndoTree<T> {
+    pub fn new() -> Self {
+        // Initialise an undo tree with only the root
+        Self {
+            nodes: vec![Node {
+                patch: None,
+                parent: None,
+                children: vec![],
+                active: 0,
+            }],
+            current: 0,
+        }
+    }
+    pub fn push(&mut self, patch: T) {
+        // Add a change on top of the current node
+        let index = self.nodes.len();
+        self.nodes.push(Node {
+            patch: Some(patch),
+            parent: Some(self.current),
+            children: vec![],
+            active: 0,
+        });
+        let node = &mut self.nodes[self.current];
+        node.active = node.children.len();
+        node.children.push(index);
+        self.current = index;
+    }
+    pub fn undo(&mut self) -> Option<T> {
+        // Step back to the parent, giving the patch to reverse
+        let node = &self.nodes[self.current];
+        let (patch, parent) = (node.patch.clone()?, node.parent?);
+        let child = self.current;
+        let parent_node = &mut self.nodes[parent];
+        parent_node.active = parent_node
+            .children
+            .iter()
+            .position(|&c| c == child)
+            .unwrap_or_default();
+        self.current = parent;
+        Some(patch)
+    }
+    pub fn redo(&mut self) -> Option<T> {
+        // Step forward along the branch that was last used
+        let active = self.nodes[self.current].active;
+        self.redo_branch(active)
+    }
+    pub fn redo_branch(&mut self, idx: usize) -> Option<T> {
+        // Step forward along a specific branch, giving the patch to apply again
+        let child = *self.nodes[self.current].children.get(idx)?;
+        self.nodes[self.current].active = idx;
+        self.current = child;
+        self.nodes[child].patch.clone()
+    }
+    pub fn branches(&self) -> usize {
+        // Count the branches that can be redone from here
+        self.nodes[self.current].children.len()
+    }
+    pub fn trim(&mut self, limit: usize) {
+        // Forget the oldest changes until no more than a certain number are kept
+        while self.nodes.len() > limit + 1 && self.current != 0 {
+            // The change after the root on the way to the current node becomes the new root
+            let mut start = self.current;
+            while let Some(parent) = self.nodes[start].parent.filter(|&p| p != 0) {
+                start = parent;
+            }
+            // Only the changes made on top of it are kept, other branches go with the old root
+            let mut kept = vec![start];
+            let mut i = 0;
+            while i < kept.len() {
+                kept.extend(self.nodes[kept[i]].children.iter().copied());
+                i += 1;
+            }
+            let mut index = vec![None; self.nodes.len()];
+            for (new, &old) in kept.iter().enumerate() {
+                index[old] = Some(new);
+            }
+            let mut nodes: Vec<Option<Node<T>>> = self.nodes.drain(..).map(Some).collect();
+            self.nodes = kept
+                .iter()
+                .filter_map(|&old| nodes[old].take())
+                .map(|mut node| {
+                    node.parent = node.parent.and_then(|p| index[p]);
+                    node.children = node.children.iter().filter_map(|&c| index[c]).collect();
+                    node
+                })
+                .collect();
+            self.nodes[0].patch = None;
+            self.current = index[self.current].unwrap_or_default();
+        }
+    }
 }
 
 // A struct for holding all the events taken by the user
 #[derive(Debug)]
 pub struct EventStack {
-    history: Vec<Vec<Event>>,  // For storing the history of events
-    current_patch: Vec<Event>, // For storing the current group
+    history: UndoTree<Patch>,   // For storing the history of events
+    current_patch: Patch,       // For storing the current group
+    replaying: bool,            // True while redoing, so the events aren't recorded twice
+    limit: usize,               // The most patches to keep in the history
+    period: Duration,           // How long a pause in editing has to be to start a new patch
+    last_push: Option<Instant>, // For holding when the last event was recorded
 }
 
 // Methods for the EventStack
 impl EventStack {
-    pub fn new() -> Self {
+    pub fn new(limit: usize, period: u64) -> Self {
         // Initialise an Event stack
         Self {
-            history: vec![],
+            history: UndoTree::new(),
             current_patch: vec![],
+            replaying: false,
+            limit,
+            period: Duration::from_secs(period),
+            last_push: None,
         }
     }
+    pub fn configure(&mut self, limit: usize, period: u64) {
+        // Change how many patches are kept, forgetting the oldest if there are too many
+        self.limit = limit;
+        self.period = Duration::from_secs(period);
+        self.history.trim(limit);
+    }
     pub fn push(&mut self, event: Event) {
         // Add an event to the event stack
+        self.push_at(event, Instant::now());
+    }
+    fn push_at(&mut self, event: Event, now: Instant) {
+        // Edits made close together are undone together, a long enough pause starts a new patch
+        if self.replaying {
+            return;
+        }
+        if self
+            .last_push
+            .is_some_and(|last| now.saturating_duration_since(last) >= self.period)
+        {
+            self.commit();
+        }
         self.current_patch.insert(0, event);
+        self.last_push = Some(now);
     }
-    pub fn pop(&mut self) -> Option<Vec<Event>> {
-        // Take a patch off the event stack
-        self.history.pop()
+    pub fn undo(&mut self) -> Option<Patch> {
+        // Take a patch off the event stack to reverse it
+        self.commit();
+        self.history.undo()
     }
-    pub fn append(&mut self, patch: Vec<Event>) {
-        // Append a patch to the stack
-        self.history.push(patch);
+    pub fn redo(&mut self, branch: Option<usize>) -> Option<Patch> {
+        // Get a patch back to apply it again, from the last used branch by default
+        self.commit();
+        match branch {
+            Some(idx) => self.history.redo_branch(idx),
+            None => self.history.redo(),
+        }
+    }
+    pub fn branches(&self) -> usize {
+        // Count the branches that can be redone
+        self.history.branches()
     }
-    pub fn empty(&mut self) {
-        // Empty the stack
-        self.history.clear();
+    pub fn replay(&mut self, replaying: bool) {
+        // Stop recording while a redone patch is applied
+        self.replaying = replaying;
     }
     pub fn commit(&mut self) {
         // Commit patch to history
-        if !self.current_patch.is_empty() {
+        if !self.current_patch.is_empty() && !self.replaying {
             self.history.push(self.current_patch.clone());
+            self.history.trim(self.limit);
             self.current_patch.clear();
         }
     }
@@ -107,10 +278,11 @@ pub fn reverse(before: Event, limit: usize) -> Option<Vec<Event>> {
             },
             ch,
         )],
+        // The document had one more line when it was deleted, so the last line can come back
         Event::DeleteLine(pos, offset, before) => vec![
             Event::InsertLineAbove(Position {
                 x: pos.x,
-                y: line_offset(pos.y, offset, limit),
+                y: line_offset(pos.y, offset, limit + 1),
             }),
             Event::UpdateLine(pos, offset, Box::new(Row::from("")), before),
         ],
@@ -118,8 +290,91 @@ pub fn reverse(before: Event, limit: usize) -> Option<Vec<Event>> {
             vec![Event::UpdateLine(pos, offset, after, before)]
         }
         Event::Overwrite(before, after) => vec![Event::Overwrite(after, before)],
+        Event::FinalNewline(before, after) => vec![Event::FinalNewline(after, before)],
         Event::InsertTab(pos) => vec![Event::DeleteTab(pos)],
         Event::DeleteTab(pos) => vec![Event::InsertTab(pos)],
         _ => return None,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn insert(x: usize) -> Event {
+        // An insertion at a column of the first line
+        Event::Insertion(Position { x, y: 0 }, 'a')
+    }
+
+    fn columns(patch: &[Event]) -> Vec<usize> {
+        // The columns of the insertions in a patch, newest first
+        patch
+            .iter()
+            .map(|event| match event {
+                Event::Insertion(pos, _) => pos.x,
+                _ => panic!("unexpected event {:?}", event),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn trimming_forgets_the_oldest_changes() {
+        let mut tree = UndoTree::new();
+        for i in 0..5 {
+            tree.push(i);
+        }
+        tree.trim(3);
+        assert_eq!(tree.undo(), Some(4));
+        assert_eq!(tree.undo(), Some(3));
+        assert_eq!(tree.undo(), Some(2));
+        assert_eq!(tree.undo(), None);
+        assert_eq!(tree.redo(), Some(2));
+    }
+
+    #[test]
+    fn history_is_limited_to_max_undo_history() {
+        let mut stack = EventStack::new(2, 5);
+        for x in 0..3 {
+            stack.push(insert(x));
+            stack.commit();
+        }
+        assert_eq!(stack.undo().as_deref().map(columns), Some(vec![2]));
+        assert_eq!(stack.undo().as_deref().map(columns), Some(vec![1]));
+        assert!(stack.undo().is_none());
+    }
+
+    #[test]
+    fn edits_within_the_undo_period_are_grouped() {
+        let mut stack = EventStack::new(10, 5);
+        let start = Instant::now();
+        stack.push_at(insert(0), start);
+        stack.push_at(insert(1), start + Duration::from_secs(2));
+        stack.push_at(insert(2), start + Duration::from_secs(4));
+        assert_eq!(stack.undo().as_deref().map(columns), Some(vec![2, 1, 0]));
+        assert!(stack.undo().is_none());
+    }
+
+    #[test]
+    fn a_pause_starts_a_new_patch() {
+        let mut stack = EventStack::new(10, 5);
+        let start = Instant::now();
+        stack.push_at(insert(0), start);
+        stack.push_at(insert(1), start + Duration::from_secs(1));
+        stack.push_at(insert(2), start + Duration::from_secs(7));
+        assert_eq!(stack.undo().as_deref().map(columns), Some(vec![2]));
+        assert_eq!(stack.undo().as_deref().map(columns), Some(vec![1, 0]));
+        assert!(stack.undo().is_none());
+    }
+
+    #[test]
+    fn redone_events_are_not_recorded() {
+        let mut stack = EventStack::new(10, 5);
+        stack.push(insert(0));
+        stack.commit();
+        stack.replay(true);
+        stack.push(insert(1));
+        stack.replay(false);
+        assert_eq!(stack.undo().as_deref().map(columns), Some(vec![0]));
+        assert!(stack.undo().is_none());
+    }
+}
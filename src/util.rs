@@ -4,6 +4,16 @@ use regex::Regex;
 use unicode_segmentation::UnicodeSegmentation;
 use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
+// The ways that the case of text can be converted
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CaseMode {
+    Upper, // EVERY LETTER IN CAPITALS
+    Lower, // every letter in lower case
+    Title, // Each Word Starting With A Capital
+    Snake, // identifiers_split_by_underscores
+    Camel, // identifiersStartingEachWordWithACapital
+}
+
 // For holding general purpose regular expressions
 #[derive(Debug, Clone)]
 pub struct Exp {
@@ -87,6 +97,13 @@ pub fn line_offset(point: usize, offset: i128, limit: usize) -> usize {
     }
 }
 
+pub fn set_line_endings(text: &str, ending: &str) -> String {
+    // Put the same line ending on every line, whatever each one had before
+    text.replace("\r\n", "\n")
+        .replace('\r', "\n")
+        .replace('\n', ending)
+}
+
 pub fn spaces_to_tabs(code: &str, tab_width: usize) -> String {
     // Convert spaces to tabs
     let mut result = vec![];
@@ -132,3 +149,208 @@ pub fn tabs_to_spaces(code: &str, tab_width: usize) -> String {
     }
     result.join("\n")
 }
+
+pub fn compute_indent(prev_line: &str, tab_width: usize, indent_after: &[Regex]) -> String {
+    // Work out the indentation of a line from the line before it
+    let indent = &prev_line[..prev_line.len() - prev_line.trim_start().len()];
+    let opens = if indent_after.is_empty() {
+        prev_line.trim_end().ends_with(&['{', '(', ':'][..])
+    } else {
+        indent_after.iter().any(|regx| regx.is_match(prev_line))
+    };
+    if opens {
+        // Lines that open a block get one more level
+        format!("{}{}", indent, " ".repeat(tab_width))
+    } else {
+        indent.to_string()
+    }
+}
+
+pub fn convert_case(text: &str, mode: CaseMode) -> String {
+    // Convert the case of text, snake and camel case change each identifier on its own
+    let is_word = |c: char| c.is_alphanumeric();
+    let is_identifier = |c: char| c.is_alphanumeric() || c == '_';
+    match mode {
+        CaseMode::Upper => text.to_uppercase(),
+        CaseMode::Lower => text.to_lowercase(),
+        CaseMode::Title => map_words(text, is_word, |word| capitalise(&word.to_lowercase())),
+        CaseMode::Snake => map_words(text, is_identifier, |word| convert_identifier(word, true)),
+        CaseMode::Camel => map_words(text, is_identifier, |word| convert_identifier(word, false)),
+    }
+}
+
+fn map_words(
+    text: &str,
+    is_word: impl Fn(char) -> bool,
+    convert: impl Fn(&str) -> String,
+) -> String {
+    // Convert each run of word characters, leaving everything between them alone
+    let mut result = String::with_capacity(text.len());
+    let mut start = None;
+    for (i, c) in text.char_indices() {
+        match (is_word(c), start) {
+            (true, None) => start = Some(i),
+            (false, Some(from)) => {
+                result.push_str(&convert(&text[from..i]));
+                start = None;
+            }
+            _ => (),
+        }
+        if start.is_none() {
+            result.push(c);
+        }
+    }
+    if let Some(from) = start {
+        result.push_str(&convert(&text[from..]));
+    }
+    result
+}
+
+fn convert_identifier(identifier: &str, snake: bool) -> String {
+    // Join the words of an identifier back up, keeping underscores at either end
+    let body = identifier.trim_matches('_');
+    if body.is_empty() {
+        return identifier.to_string();
+    }
+    let lead = identifier.len() - identifier.trim_start_matches('_').len();
+    let trail = identifier.len() - identifier.trim_end_matches('_').len();
+    let words = split_identifier(body);
+    let body = if snake {
+        words
+            .iter()
+            .map(|word| word.to_lowercase())
+            .collect::<Vec<_>>()
+            .join("_")
+    } else {
+        words
+            .iter()
+            .enumerate()
+            .map(|(i, word)| {
+                if i == 0 {
+                    word.to_lowercase()
+                } else {
+                    capitalise(&word.to_lowercase())
+                }
+            })
+            .collect()
+    };
+    format!("{}{body}{}", "_".repeat(lead), "_".repeat(trail))
+}
+
+fn split_identifier(identifier: &str) -> Vec<String> {
+    // Break an identifier into words at underscores and changes of case, e.g. HTTPServer_port
+    let chars: Vec<char> = identifier.chars().collect();
+    let mut words = vec![];
+    let mut word = String::new();
+    for (i, &c) in chars.iter().enumerate() {
+        if c == '_' {
+            if !word.is_empty() {
+                words.push(std::mem::take(&mut word));
+            }
+            continue;
+        }
+        let prev = i.checked_sub(1).map(|i| chars[i]);
+        let next = chars.get(i + 1);
+        let boundary = c.is_uppercase()
+            && prev.is_some_and(|p| {
+                p.is_lowercase()
+                    || p.is_numeric()
+                    || (p.is_uppercase() && next.is_some_and(|n| n.is_lowercase()))
+            });
+        if boundary && !word.is_empty() {
+            words.push(std::mem::take(&mut word));
+        }
+        word.push(c);
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    words
+}
+
+fn capitalise(word: &str) -> String {
+    // Put the first letter of a word in upper case
+    let mut chars = word.chars();
+    chars.next().map_or_else(String::new, |first| {
+        first.to_uppercase().chain(chars).collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn indentation_follows_the_line_above() {
+        assert_eq!(compute_indent("    let x = 1;", 4, &[]), "    ");
+        assert_eq!(compute_indent("let x = 1;", 4, &[]), "");
+        assert_eq!(compute_indent("", 4, &[]), "");
+    }
+
+    #[test]
+    fn blocks_are_indented_by_a_tab_stop() {
+        assert_eq!(compute_indent("fn main() {", 4, &[]), "    ");
+        assert_eq!(compute_indent("    if x {  ", 4, &[]), "        ");
+        assert_eq!(compute_indent("  match x {", 2, &[]), "    ");
+        assert_eq!(compute_indent("def main():", 8, &[]), "        ");
+    }
+
+    #[test]
+    fn languages_can_say_what_opens_a_block() {
+        let rules = [Regex::new(r"\bdo$").unwrap()];
+        assert_eq!(compute_indent("  loop do", 2, &rules), "    ");
+        // The default openers no longer apply
+        assert_eq!(compute_indent("  f(x) {", 2, &rules), "  ");
+    }
+
+    #[test]
+    fn identifiers_are_split_into_words() {
+        assert_eq!(
+            split_identifier("snake_case_name"),
+            vec!["snake", "case", "name"]
+        );
+        assert_eq!(
+            split_identifier("camelCaseName"),
+            vec!["camel", "Case", "Name"]
+        );
+        assert_eq!(
+            split_identifier("HTTPServer_port"),
+            vec!["HTTP", "Server", "port"]
+        );
+        assert_eq!(split_identifier("utf8Decoder"), vec!["utf8", "Decoder"]);
+        assert_eq!(split_identifier("a__b"), vec!["a", "b"]);
+        assert!(split_identifier("").is_empty());
+    }
+
+    #[test]
+    fn case_is_converted() {
+        let text = "let parseHTTPRequest = my_value; // hello world";
+        assert_eq!(
+            convert_case(text, CaseMode::Snake),
+            "let parse_http_request = my_value; // hello world"
+        );
+        assert_eq!(
+            convert_case(text, CaseMode::Camel),
+            "let parseHttpRequest = myValue; // hello world"
+        );
+        assert_eq!(
+            convert_case("hello WORLD o'neil", CaseMode::Title),
+            "Hello World O'Neil"
+        );
+        assert_eq!(convert_case("Mixed Case", CaseMode::Upper), "MIXED CASE");
+        assert_eq!(convert_case("Mixed Case", CaseMode::Lower), "mixed case");
+    }
+
+    #[test]
+    fn underscores_around_identifiers_are_kept() {
+        assert_eq!(
+            convert_case("__privateName_", CaseMode::Snake),
+            "__private_name_"
+        );
+        assert_eq!(
+            convert_case("_private_name", CaseMode::Camel),
+            "_privateName"
+        );
+        assert_eq!(convert_case("___", CaseMode::Camel), "___");
+    }
+}